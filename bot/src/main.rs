@@ -0,0 +1,37 @@
+use bevy::{prelude::*, time::common_conditions::on_timer};
+use bot::BotConfig;
+use cuscuta_resources::TICKS_PER_SECOND;
+use library::*;
+use std::{env, time::Duration};
+
+/* how often we print the running numbers */
+const REPORT_SECONDS: u64 = 2;
+
+/* headless load tester. spawns a pile of fake players that join
+ * the server the normal way and then walk/swing around.
+ *   cargo run -- 32 random
+ *   cargo run -- 8 script --server 10.0.0.60:5001 */
+fn main() {
+    env::set_var("RUST_BACKTRACE", "1");
+    let config = BotConfig::from_args();
+    App::new()
+        /* no window, no renderer, just the loop */
+        .add_plugins(MinimalPlugins)
+        .add_plugins(bevy::log::LogPlugin::default())
+        .insert_resource(config)
+        .insert_resource(Time::<Fixed>::from_hz(TICKS_PER_SECOND))
+        .add_systems(Startup, bot::bot_setup)
+        .add_systems(
+            FixedUpdate,
+            (
+                bot::bot_join,
+                bot::bot_listen.after(bot::bot_join),
+                bot::bot_drive.after(bot::bot_listen),
+            ),
+        )
+        .add_systems(
+            Update,
+            bot::bot_report.run_if(on_timer(Duration::from_secs(REPORT_SECONDS))),
+        )
+        .run();
+}
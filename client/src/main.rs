@@ -1,19 +1,16 @@
-use bevy::{prelude::*, time::common_conditions::on_timer, window::PresentMode};
+use bevy::{prelude::*, window::PresentMode};
 use client::id_request;
 use cuscuta_resources::TICKS_PER_SECOND;
 use enemies::BossKillEvent;
 use library::*;
-use std::{env, time::Duration};
-use markov_chains::*;
+use std::env;
 use player::CollisionState;
-use room_gen::RoomConfig;
 
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
     App::new()
         /* room manager necessary? */
         .insert_resource(CollisionState::new())
-        .insert_resource(RoomConfig::new())
         .add_event::<BossKillEvent>()
         .insert_resource(Time::<Fixed>::from_hz(TICKS_PER_SECOND))
         .add_systems(PreStartup, init::ip_setup) // should run before we spawn / send data to server
@@ -44,9 +41,14 @@ fn main() {
             player::player_attack_enemy.after(player::animate_player),
             client::boss_kill_event.after(player::player_attack_enemy),
             ui::update_ui_elements,
+            ui::update_teammate_bars,
+            ui::update_door_vote_ui,
+            ui::update_room_debug,
             player::player_interact,
             player::restore_health,
+            traps::tick_afflictions.after(client::listen),
         )) 
+        .add_systems(Update, floors::tint_floor_theme.after(client::listen))
         /* networking shtuff. comment out if needed */
         .add_systems(FixedUpdate,
             client::send_player
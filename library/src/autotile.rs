@@ -0,0 +1,371 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::biomes::Tileset;
+use crate::dungeon_rng::{DungeonRng, RngStream};
+use crate::room_gen::RoomId;
+
+/* AUTOTILING. walls used to get their sprite off where they were in the
+ * room (x==0 left wall, y==0 bottom wall...), so corners, inner walls and
+ * anything in an odd shaped room all came out as the same top wall. now
+ * send_map_packet runs the finished map through autotile, which looks at
+ * each tile's neighbours and picks:
+ *   walls - a WallPiece off which of its 4 neighbours are wall
+ *           (N 1, E 2, S 4, W 8), and for solid ones the diagonals, so
+ *           we get faces, inside and outside corners, thin runs, ends,
+ *           T junctions and crosses
+ *   floor - a FloorPiece, the shaded edge if there's wall above or beside
+ *           it, otherwise one of the plain variants (rolled off the room,
+ *           so it looks the same every time you come back)
+ * the picks go out in the map packet's tiles layer next to the codes, the
+ * client asks the biome's Tileset for the sprite. doors count as wall so
+ * the outer wall runs straight through them, off the edge of the room is
+ * solid wall */
+
+// neighbour bits
+pub const NORTH: u8 = 1;
+pub const EAST: u8 = 2;
+pub const SOUTH: u8 = 4;
+pub const WEST: u8 = 8;
+
+/* plain floor variants, the base sprite flipped around */
+pub const FLOOR_VARIANTS: u8 = 4;
+
+/* map codes the autotiler treats as wall, outer 1, inner 11 and doors */
+pub fn is_wall_code(code: u8) -> bool {
+    matches!(code, 1 | 4..=7 | 11)
+}
+
+/* directions below are which side the floor is on. a face with floor to
+ * the south is the top wall of a room */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WallPiece {
+    /* wall all round, nothing to see */
+    Solid,
+    /* pillar on its own */
+    Lone,
+    /* stub of a thin wall sticking out toward that side */
+    EndN,
+    EndE,
+    EndS,
+    EndW,
+    /* one tile thick runs */
+    RunNS,
+    RunEW,
+    /* side of a thick wall */
+    FaceN,
+    FaceE,
+    FaceS,
+    FaceW,
+    /* outside corner of a block, floor on both sides */
+    OuterNE,
+    OuterSE,
+    OuterSW,
+    OuterNW,
+    /* inside corner, floor only on that diagonal. a room's corners */
+    InnerNE,
+    InnerSE,
+    InnerSW,
+    InnerNW,
+    /* thin run with a branch off toward that side */
+    TeeN,
+    TeeE,
+    TeeS,
+    TeeW,
+    Cross,
+}
+
+impl WallPiece {
+    pub const ALL: [WallPiece; 25] = [
+        WallPiece::Solid, WallPiece::Lone,
+        WallPiece::EndN, WallPiece::EndE, WallPiece::EndS, WallPiece::EndW,
+        WallPiece::RunNS, WallPiece::RunEW,
+        WallPiece::FaceN, WallPiece::FaceE, WallPiece::FaceS, WallPiece::FaceW,
+        WallPiece::OuterNE, WallPiece::OuterSE, WallPiece::OuterSW, WallPiece::OuterNW,
+        WallPiece::InnerNE, WallPiece::InnerSE, WallPiece::InnerSW, WallPiece::InnerNW,
+        WallPiece::TeeN, WallPiece::TeeE, WallPiece::TeeS, WallPiece::TeeW,
+        WallPiece::Cross,
+    ];
+
+    /* tile index in the map packet */
+    pub fn index(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_index(index: u8) -> WallPiece {
+        WallPiece::ALL.get(index as usize).copied().unwrap_or(WallPiece::FaceS)
+    }
+
+    /* `mask` is which neighbours are wall, `open` which diagonals are
+     * floor (same bits, NORTH for north east and round from there) */
+    pub fn pick(mask: u8, open: u8) -> WallPiece {
+        /* a thin run with floor both sides of where the branch meets it */
+        let tee = |sides: u8| open & sides == sides;
+        match mask {
+            0 => WallPiece::Lone,
+            NORTH => WallPiece::EndS,
+            EAST => WallPiece::EndW,
+            SOUTH => WallPiece::EndN,
+            WEST => WallPiece::EndE,
+            5 => WallPiece::RunNS,
+            10 => WallPiece::RunEW,
+            3 => WallPiece::OuterSW,
+            6 => WallPiece::OuterNW,
+            12 => WallPiece::OuterNE,
+            9 => WallPiece::OuterSE,
+            /* three walls, a face unless the corners either side are open */
+            14 if tee(SOUTH | EAST) => WallPiece::TeeS,
+            14 => WallPiece::FaceN,
+            13 if tee(SOUTH | WEST) => WallPiece::TeeW,
+            13 => WallPiece::FaceE,
+            11 if tee(NORTH | WEST) => WallPiece::TeeN,
+            11 => WallPiece::FaceS,
+            7 if tee(NORTH | EAST) => WallPiece::TeeE,
+            7 => WallPiece::FaceW,
+            _ => match open {
+                0 => WallPiece::Solid,
+                NORTH => WallPiece::InnerNE,
+                EAST => WallPiece::InnerSE,
+                SOUTH => WallPiece::InnerSW,
+                WEST => WallPiece::InnerNW,
+                _ => WallPiece::Cross,
+            },
+        }
+    }
+
+    /* only four wall sides and four corners are drawn, everything else
+     * borrows the closest one, flipped if that helps */
+    pub fn sprite(&self, tiles: &Tileset) -> TilePick {
+        match self {
+            WallPiece::Solid => TilePick::new(tiles.void),
+            WallPiece::Lone | WallPiece::EndN | WallPiece::RunEW | WallPiece::FaceS
+                | WallPiece::TeeN | WallPiece::TeeS | WallPiece::Cross => TilePick::new(tiles.top_wall),
+            WallPiece::EndS | WallPiece::FaceN => TilePick::new(tiles.bottom_wall),
+            WallPiece::RunNS | WallPiece::FaceE | WallPiece::TeeW => TilePick::new(tiles.left_wall),
+            WallPiece::FaceW | WallPiece::TeeE => TilePick::new(tiles.right_wall),
+            WallPiece::EndW | WallPiece::OuterNW => TilePick::new(tiles.small_corner_left),
+            WallPiece::EndE | WallPiece::OuterNE => TilePick::new(tiles.small_corner_right),
+            WallPiece::OuterSW => TilePick::new(tiles.small_corner_left).flipped(false, true),
+            WallPiece::OuterSE => TilePick::new(tiles.small_corner_right).flipped(false, true),
+            WallPiece::InnerSE => TilePick::new(tiles.corner_left),
+            WallPiece::InnerSW => TilePick::new(tiles.corner_right),
+            WallPiece::InnerNE => TilePick::new(tiles.corner_left).flipped(false, true),
+            WallPiece::InnerNW => TilePick::new(tiles.corner_right).flipped(false, true),
+        }
+    }
+}
+
+/* directions are which side the wall is on */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloorPiece {
+    Plain,
+    PlainFlipX,
+    PlainFlipY,
+    PlainFlipXY,
+    EdgeN,
+    EdgeE,
+    EdgeW,
+    /* wall above and beside */
+    CornerNE,
+    CornerNW,
+    /* wall only on the diagonal, the end of a wall's shadow */
+    NookNE,
+    NookNW,
+}
+
+impl FloorPiece {
+    pub const ALL: [FloorPiece; 11] = [
+        FloorPiece::Plain, FloorPiece::PlainFlipX, FloorPiece::PlainFlipY, FloorPiece::PlainFlipXY,
+        FloorPiece::EdgeN, FloorPiece::EdgeE, FloorPiece::EdgeW,
+        FloorPiece::CornerNE, FloorPiece::CornerNW,
+        FloorPiece::NookNE, FloorPiece::NookNW,
+    ];
+
+    /* tile index in the map packet */
+    pub fn index(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_index(index: u8) -> FloorPiece {
+        FloorPiece::ALL.get(index as usize).copied().unwrap_or(FloorPiece::Plain)
+    }
+
+    /* `mask` is which neighbours are wall, `walled` which diagonals are
+     * (NORTH for north east and round). walls below dont shade anything */
+    pub fn pick(mask: u8, walled: u8, variant: u8) -> FloorPiece {
+        if mask & NORTH != 0 {
+            if mask & WEST != 0 {
+                return FloorPiece::CornerNW;
+            }
+            if mask & EAST != 0 {
+                return FloorPiece::CornerNE;
+            }
+            return FloorPiece::EdgeN;
+        }
+        if mask & WEST != 0 {
+            return FloorPiece::EdgeW;
+        }
+        if mask & EAST != 0 {
+            return FloorPiece::EdgeE;
+        }
+        if walled & WEST != 0 {
+            return FloorPiece::NookNW;
+        }
+        if walled & NORTH != 0 {
+            return FloorPiece::NookNE;
+        }
+        FloorPiece::ALL[(variant % FLOOR_VARIANTS) as usize]
+    }
+
+    pub fn sprite(&self, tiles: &Tileset) -> TilePick {
+        match self {
+            FloorPiece::Plain => TilePick::new(tiles.floor),
+            FloorPiece::PlainFlipX => TilePick::new(tiles.floor).flipped(true, false),
+            FloorPiece::PlainFlipY => TilePick::new(tiles.floor).flipped(false, true),
+            FloorPiece::PlainFlipXY => TilePick::new(tiles.floor).flipped(true, true),
+            FloorPiece::EdgeN => TilePick::new(tiles.floor_top),
+            FloorPiece::EdgeE => TilePick::new(tiles.floor_right),
+            FloorPiece::EdgeW => TilePick::new(tiles.floor_left),
+            FloorPiece::CornerNE => TilePick::new(tiles.floor_corner_right),
+            FloorPiece::CornerNW => TilePick::new(tiles.floor_corner_left),
+            FloorPiece::NookNE => TilePick::new(tiles.floor_nook_right),
+            FloorPiece::NookNW => TilePick::new(tiles.floor_nook_left),
+        }
+    }
+}
+
+/* what the client actually draws for a tile */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TilePick {
+    pub path: &'static str,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl TilePick {
+    pub fn new(path: &'static str) -> Self {
+        Self {
+            path: path,
+            flip_x: false,
+            flip_y: false,
+        }
+    }
+
+    pub fn flipped(mut self, flip_x: bool, flip_y: bool) -> Self {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+        self
+    }
+
+    pub fn sprite(&self) -> Sprite {
+        Sprite {
+            flip_x: self.flip_x,
+            flip_y: self.flip_y,
+            ..default()
+        }
+    }
+}
+
+/* tile index for every spot in the map matrix ([x][y], y going up). only
+ * the room's width x height tiles are looked at, the rest (the packet has
+ * a spare row and column) stays 0. wall codes get a WallPiece, everything
+ * else the FloorPiece under it */
+pub fn autotile(matrix: &Vec<Vec<u8>>, width: usize, height: usize, dungeon_rng: &DungeonRng, room: RoomId) -> Vec<Vec<u8>> {
+    let mut rng = dungeon_rng.stream(RngStream::Tiles, room);
+    let wall = |x: isize, y: isize| {
+        x < 0 || y < 0 || x >= width as isize || y >= height as isize
+            || matrix.get(x as usize).and_then(|column| column.get(y as usize)).map_or(true, |code| is_wall_code(*code))
+    };
+    let bits = |x: isize, y: isize, offsets: [(isize, isize); 4], want: bool| {
+        offsets.iter().enumerate()
+            .filter(|(_, (dx, dy))| wall(x + dx, y + dy) == want)
+            .fold(0, |mask, (bit, _)| mask | 1 << bit)
+    };
+    let sides = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+    let diagonals = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+    let mut tiles = vec![vec![0; matrix.first().map_or(0, |column| column.len())]; matrix.len()];
+    for x in 0..width.min(matrix.len()) {
+        for y in 0..height.min(matrix[x].len()) {
+            let (sx, sy) = (x as isize, y as isize);
+            /* rolled for every tile so a wall going in doesnt shuffle the rest */
+            let variant: u8 = rng.gen_range(0..FLOOR_VARIANTS);
+            let mask = bits(sx, sy, sides, true);
+            tiles[x][y] = if is_wall_code(matrix[x][y]) {
+                WallPiece::pick(mask, bits(sx, sy, diagonals, false)).index()
+            } else {
+                FloorPiece::pick(mask, bits(sx, sy, diagonals, true), variant).index()
+            };
+        }
+    }
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* matrix [x][y] off rows written top down, so the test reads like the room */
+    fn map(rows: &[&str]) -> Vec<Vec<u8>> {
+        let height = rows.len();
+        let width = rows[0].len();
+        let mut matrix = vec![vec![0; height]; width];
+        for (row, line) in rows.iter().enumerate() {
+            for (x, tile) in line.chars().enumerate() {
+                matrix[x][height - 1 - row] = if tile == '#' { 11 } else { 0 };
+            }
+        }
+        matrix
+    }
+
+    fn tiles(matrix: &Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        autotile(matrix, matrix.len(), matrix[0].len(), &DungeonRng::new(1), RoomId(0))
+    }
+
+    #[test]
+    fn room_corners_are_inner_corners() {
+        let matrix = map(&[
+            "####",
+            "#..#",
+            "#..#",
+            "####",
+        ]);
+        let tiles = tiles(&matrix);
+        assert_eq!(WallPiece::from_index(tiles[0][0]), WallPiece::InnerNE);
+        assert_eq!(WallPiece::from_index(tiles[3][0]), WallPiece::InnerNW);
+        assert_eq!(WallPiece::from_index(tiles[0][3]), WallPiece::InnerSE);
+        assert_eq!(WallPiece::from_index(tiles[3][3]), WallPiece::InnerSW);
+        /* and the walls between them face the floor */
+        assert_eq!(WallPiece::from_index(tiles[1][0]), WallPiece::FaceN);
+        assert_eq!(WallPiece::from_index(tiles[1][3]), WallPiece::FaceS);
+        assert_eq!(FloorPiece::from_index(tiles[1][2]), FloorPiece::CornerNW);
+    }
+
+    #[test]
+    fn block_corners_are_outer_corners() {
+        let matrix = map(&[
+            "....",
+            ".##.",
+            ".##.",
+            "....",
+        ]);
+        let tiles = tiles(&matrix);
+        assert_eq!(WallPiece::from_index(tiles[1][1]), WallPiece::OuterSW);
+        assert_eq!(WallPiece::from_index(tiles[2][1]), WallPiece::OuterSE);
+        assert_eq!(WallPiece::from_index(tiles[1][2]), WallPiece::OuterNW);
+        assert_eq!(WallPiece::from_index(tiles[2][2]), WallPiece::OuterNE);
+    }
+
+    #[test]
+    fn wall_on_its_own_is_lone() {
+        let matrix = map(&[
+            "...",
+            ".#.",
+            "...",
+        ]);
+        let tiles = tiles(&matrix);
+        assert_eq!(WallPiece::from_index(tiles[1][1]), WallPiece::Lone);
+        /* floor under it catches its shadow */
+        assert_eq!(FloorPiece::from_index(tiles[1][0]), FloorPiece::EdgeN);
+    }
+}
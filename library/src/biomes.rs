@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::dungeon_rng::{DungeonRng, RngStream};
+use crate::reachability::{pixel_to_tile, reachable_spots, tile_to_pixel, ITEM_EDGE_MARGIN};
+use crate::room_gen::{RoomConfig, RoomManager};
+
+/* BIOMES. every floor of a run is one biome, picked off room_config.ron's
+ * depth biomes (floor n gets biomes[n], round and round if the run is
+ * deeper than the list). a biome decides:
+ *   tileset - which floor, wall and door sprites the client draws, the
+ *             autotiler (autotile.rs) picks the piece
+ *   tint    - the lighting, every floor and wall sprite gets it
+ *   decor   - rocks and junk scattered on the floor, just for looks
+ *   enemies - which enemy_type numbers live there. the room's enemy_type
+ *             range still applies, if none of the biome's kinds are in
+ *             range the whole range is used
+ * the server sends the biome with every map packet. only two floor sets
+ * and one wall set are drawn so far, the tint does most of the work */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Biome {
+    Lab,
+    Sewer,
+    Greenhouse,
+    FleshCaverns,
+}
+
+/* sprite paths, relative to assets. walls are named for where they sit
+ * in a room (the left wall has floor to its right), floor edges for where
+ * the wall shading them is */
+pub struct Tileset {
+    pub floor: &'static str,
+    pub floor_top: &'static str,
+    pub floor_left: &'static str,
+    pub floor_right: &'static str,
+    pub floor_corner_left: &'static str,
+    pub floor_corner_right: &'static str,
+    pub floor_nook_left: &'static str,
+    pub floor_nook_right: &'static str,
+    pub door: &'static str,
+    pub left_wall: &'static str,
+    pub right_wall: &'static str,
+    pub top_wall: &'static str,
+    pub bottom_wall: &'static str,
+    pub corner_left: &'static str,
+    pub corner_right: &'static str,
+    pub small_corner_left: &'static str,
+    pub small_corner_right: &'static str,
+    pub void: &'static str,
+}
+
+/* floor clutter, `share` of the open floor gets one */
+pub struct Decor {
+    pub sprite: &'static str,
+    pub tint: Color,
+    pub share: f32,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 4] = [Biome::Lab, Biome::Sewer, Biome::Greenhouse, Biome::FleshCaverns];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Biome::Lab => "lab",
+            Biome::Sewer => "sewer",
+            Biome::Greenhouse => "greenhouse",
+            Biome::FleshCaverns => "flesh caverns",
+        }
+    }
+
+    pub fn tileset(&self) -> Tileset {
+        /* the two floor sets dont name their edges the same */
+        let floor = match self {
+            Biome::Lab | Biome::FleshCaverns => [
+                "tiles/solid_floor/solid_floor.png",
+                "tiles/solid_floor/solid_floor_top.png",
+                "tiles/solid_floor/solid_floor_left.png",
+                "tiles/solid_floor/solid_floor_right.png",
+                "tiles/solid_floor/solid_floor_leftcorner.png",
+                "tiles/solid_floor/solid_floor_rightcorner.png",
+                "tiles/solid_floor/solid_floor_small_leftcorner.png",
+                "tiles/solid_floor/solid_floor_small_rightcorner.png",
+            ],
+            Biome::Sewer | Biome::Greenhouse => [
+                "tiles/cobblestone_floor/cobblestone_floor.png",
+                "tiles/cobblestone_floor/cobblestone_top.png",
+                "tiles/cobblestone_floor/cobblestone_left.png",
+                "tiles/cobblestone_floor/cobblestone_right.png",
+                "tiles/cobblestone_floor/cobblestone_topleft.png",
+                "tiles/cobblestone_floor/cobblestone_topright.png",
+                "tiles/cobblestone_floor/cobblestone_topleft_corner.png",
+                "tiles/cobblestone_floor/cobblestone_topright_corner.png",
+            ],
+        };
+        Tileset {
+            floor: floor[0],
+            floor_top: floor[1],
+            floor_left: floor[2],
+            floor_right: floor[3],
+            floor_corner_left: floor[4],
+            floor_corner_right: floor[5],
+            floor_nook_left: floor[6],
+            floor_nook_right: floor[7],
+            door: "tiles/solid_floor/solid_floor.png",
+            left_wall: "tiles/walls/left_wall.png",
+            right_wall: "tiles/walls/right_wall.png",
+            top_wall: "tiles/walls/north_wall.png",
+            bottom_wall: "tiles/walls/bottom_wall.png",
+            corner_left: "tiles/walls/topleft_corner.png",
+            corner_right: "tiles/walls/topright_corner.png",
+            small_corner_left: "tiles/walls/topleft_small_corner.png",
+            small_corner_right: "tiles/walls/topright_small_corner.png",
+            void: "tiles/walls/black_void.png",
+        }
+    }
+
+    pub fn tint(&self) -> Color {
+        match self {
+            Biome::Lab => Color::srgb(0.85, 0.95, 1.0),
+            Biome::Sewer => Color::srgb(0.7, 0.85, 0.65),
+            Biome::Greenhouse => Color::srgb(0.75, 1.0, 0.7),
+            Biome::FleshCaverns => Color::srgb(1.0, 0.7, 0.7),
+        }
+    }
+
+    pub fn decor(&self) -> Decor {
+        let (tint, share) = match self {
+            Biome::Lab => (Color::srgb(0.7, 0.75, 0.8), 0.01),
+            Biome::Sewer => (Color::srgb(0.45, 0.5, 0.3), 0.03),
+            Biome::Greenhouse => (Color::srgb(0.4, 0.75, 0.35), 0.04),
+            Biome::FleshCaverns => (Color::srgb(0.8, 0.35, 0.4), 0.03),
+        };
+        Decor {
+            sprite: "tiles/solid_floor/decor_rock.png",
+            tint: tint,
+            share: share,
+        }
+    }
+
+    /* 1 ninja, 2 berry rat, 3 splat monkey, 4 skelly */
+    pub fn enemies(&self) -> &'static [usize] {
+        match self {
+            Biome::Lab => &[1, 3, 4],
+            Biome::Sewer => &[2, 3],
+            Biome::Greenhouse => &[1, 2, 3],
+            Biome::FleshCaverns => &[2, 3, 4],
+        }
+    }
+
+    /* the kinds in range that live here, or the whole range if none do */
+    pub fn enemy_pool(&self, range: (usize, usize)) -> Vec<usize> {
+        let pool: Vec<usize> = (range.0..=range.1).filter(|kind| self.enemies().contains(kind)).collect();
+        if pool.is_empty() {
+            (range.0..=range.1).collect()
+        } else {
+            pool
+        }
+    }
+}
+
+pub fn default_biomes() -> Vec<Biome> {
+    Biome::ALL.to_vec()
+}
+
+/* new room, scatter the floor's clutter. stays off stealth props and
+ * traps so those still read on the map */
+pub fn roll_decor(room_manager: &mut RoomManager, room_config: &RoomConfig, dungeon_rng: &DungeonRng) {
+    let room = room_manager.current_room_id();
+    let mut rng = dungeon_rng.stream(RngStream::Decor, room);
+    let share = room_config.biome().decor().share;
+
+    let grid = room_manager.current_grid_ref();
+    let taken: Vec<(usize, usize)> = room_manager.room_state(room)
+        .map(|state| state.props.iter().map(|(spot, _)| *spot)
+            .chain(state.traps.iter().map(|trap| trap.spot))
+            .map(|spot| pixel_to_tile(grid, spot))
+            .collect())
+        .unwrap_or_default();
+    let decor: Vec<Vec2> = reachable_spots(grid, &room_manager.current_key_tiles(), ITEM_EDGE_MARGIN)
+        .into_iter()
+        .filter(|tile| !taken.contains(tile) && rng.gen::<f32>() < share)
+        .map(|tile| tile_to_pixel(grid, tile))
+        .collect();
+    room_manager.room_state_mut(room).decor = decor;
+}
@@ -0,0 +1,422 @@
+/* Headless bot clients for load/soak testing the server.
+ * Every bot is its own entity with its own socket, so to the
+ * server it looks exactly like another player on another port.
+ * They go through the normal id handshake, then walk around and
+ * swing at whatever enemies the server tells them about. */
+use std::env;
+use std::net::UdpSocket;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::cuscuta_resources::{Health, SERVER_ADR, TILE_SIZE, PLAYER_SPEED};
+use crate::network::{
+    ClientPacket, DecreaseEnemyHealthPacket, Header, IdPacket, KillEnemyPacket, PingPacket,
+    PlayerSendable, Sequence, ServerPacket, Timestamp, UDP,
+};
+use crate::enemies::EnemyId;
+
+/* how long we wait on an id before calling the join a bust */
+const BOT_JOIN_TIMEOUT: f32 = 5.;
+/* how often the IdPacket goes out again while we wait, udp drops things */
+const BOT_JOIN_RESEND: f32 = 0.5;
+/* how often each bot pings the server */
+const BOT_PING_RATE: f32 = 0.5;
+/* how often a random walker picks a new direction */
+const BOT_WANDER_RATE: f32 = 1.;
+/* same reach as player_attack_enemy (3 tiles wide box) */
+const BOT_ATTACK_REACH: f32 = TILE_SIZE as f32 * 1.5;
+
+/* what kind of input a bot drives */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BotPattern {
+    /* new random direction every BOT_WANDER_RATE, attacks anything close */
+    Random,
+    /* walks a fixed square, attacking on every corner. same every run */
+    Script,
+}
+
+/* parsed off the command line:
+ * bot [count] [random|script] [--server ip:port] */
+#[derive(Resource, Clone, Debug)]
+pub struct BotConfig {
+    pub count: usize,
+    pub pattern: BotPattern,
+    pub server: String,
+}
+
+impl BotConfig {
+    pub fn from_args() -> Self {
+        let mut config = Self {
+            count: 1,
+            pattern: BotPattern::Random,
+            server: String::from(SERVER_ADR),
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "random" => config.pattern = BotPattern::Random,
+                "script" => config.pattern = BotPattern::Script,
+                "--server" => {
+                    if let Some(addr) = args.next() {
+                        config.server = addr;
+                    }
+                }
+                _ => match arg.parse::<usize>() {
+                    Ok(count) => config.count = count,
+                    Err(_) => warn!("ignoring bot arg {}", arg),
+                },
+            }
+        }
+        config
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BotState {
+    Joining,
+    Joined,
+    Failed,
+}
+
+/* what a bot knows about an enemy, enough to swing at it */
+#[derive(Clone, Debug)]
+pub struct BotTarget {
+    pub id: EnemyId,
+    pub position: Vec2,
+    pub health: f32,
+}
+
+#[derive(Component)]
+pub struct Bot {
+    /* 0 until the server hands us one */
+    pub id: u8,
+    pub state: BotState,
+    pub sequence: Sequence,
+    pub transform: Transform,
+    pub velocity: Vec2,
+    pub health: Health,
+    pub attack: bool,
+    /* room bounds from the last map packet, in pixels */
+    pub room_size: Vec2,
+    pub targets: Vec<BotTarget>,
+    pub join_timer: Timer,
+    pub resend_timer: Timer,
+    pub ping_timer: Timer,
+    pub step_timer: Timer,
+    /* which leg of the square we are on for BotPattern::Script */
+    pub script_step: usize,
+}
+
+impl Bot {
+    pub fn new() -> Self {
+        Self {
+            id: 0,
+            state: BotState::Joining,
+            sequence: Sequence::new(0),
+            transform: Transform::from_xyz(0., 0., 900.),
+            velocity: Vec2::ZERO,
+            health: Health::new_init(),
+            attack: false,
+            room_size: Vec2::splat(40. * TILE_SIZE as f32),
+            targets: Vec::new(),
+            join_timer: Timer::from_seconds(BOT_JOIN_TIMEOUT, TimerMode::Once),
+            resend_timer: Timer::from_seconds(BOT_JOIN_RESEND, TimerMode::Repeating),
+            ping_timer: Timer::from_seconds(BOT_PING_RATE, TimerMode::Repeating),
+            step_timer: Timer::from_seconds(BOT_WANDER_RATE, TimerMode::Repeating),
+            script_step: 0,
+        }
+    }
+}
+
+/* running totals, reset every report */
+#[derive(Resource, Default, Debug)]
+pub struct BotStats {
+    pub joined: usize,
+    pub failed: usize,
+    pub pings: u32,
+    pub pongs: u32,
+    pub latency_total_ms: u64,
+    pub latency_max_ms: u64,
+    pub packets: u64,
+}
+
+/* spawns every bot, each bound to whatever port the os gives it */
+pub fn bot_setup(
+    mut commands: Commands,
+    config: Res<BotConfig>,
+) {
+    info!("spawning {} {:?} bots against {}", config.count, config.pattern, config.server);
+    for _ in 0..config.count {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        commands.spawn((Bot::new(), UDP { socket: socket }));
+    }
+    commands.insert_resource(BotStats::default());
+}
+
+/* same IdPacket the real client sends in id_request, one per bot.
+ * goes out as soon as the bot exists, then every BOT_JOIN_RESEND till
+ * the server answers. asking twice just gets the same id back */
+pub fn bot_join(
+    mut bots: Query<(&mut Bot, &UDP)>,
+    config: Res<BotConfig>,
+    time: Res<Time>,
+) {
+    for (mut bot, udp) in bots.iter_mut() {
+        if bot.state != BotState::Joining {
+            continue;
+        }
+        bot.resend_timer.tick(time.delta());
+        if !bot.is_added() && !bot.resend_timer.just_finished() {
+            continue;
+        }
+        let id_packet = ClientPacket::IdPacket(IdPacket {
+            head: Header::new(0, Sequence::new(0)),
+        });
+        send_to_server(&id_packet, udp, &config);
+    }
+}
+
+/* drains every bot's socket. mirrors client::listen but only
+ * keeps what a bot needs to keep walking and swinging */
+pub fn bot_listen(
+    mut bots: Query<(&mut Bot, &UDP)>,
+    mut stats: ResMut<BotStats>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed().as_millis() as u64;
+    for (mut bot, udp) in bots.iter_mut() {
+        loop {
+            let mut buf: [u8; 10000] = [0; 10000];
+            let packet = udp.socket.recv_from(&mut buf);
+            let (amt, _src) = match packet {
+                Ok(got) => got,
+                Err(_e) => break,
+            };
+            stats.packets += 1;
+
+            let deserializer = match flexbuffers::Reader::get_root(&buf[..amt]) {
+                Ok(reader) => reader,
+                Err(_e) => continue,
+            };
+            let rec_struct = match ServerPacket::deserialize(deserializer) {
+                Ok(rec) => rec,
+                Err(_e) => continue,
+            };
+
+            match rec_struct {
+                ServerPacket::IdPacket(id_packet) => {
+                    if bot.state != BotState::Joining {
+                        continue;
+                    }
+                    bot.id = id_packet.head.network_id;
+                    bot.sequence.new_index(id_packet.head.network_id.into());
+                    bot.sequence.assign(&id_packet.head.sequence);
+                    bot.state = BotState::Joined;
+                    stats.joined += 1;
+                }
+                ServerPacket::FullPacket(full_packet) => {
+                    if bot.state != BotState::Joining {
+                        continue;
+                    }
+                    warn!("bot turned away, server full ({} players)", full_packet.max);
+                    bot.state = BotState::Failed;
+                    stats.failed += 1;
+                }
+                ServerPacket::PlayerPacket(player_packet) => {
+                    /* only care about us, server teleports us on room change */
+                    if player_packet.head.network_id == bot.id {
+                        bot.transform = player_packet.transform;
+                        bot.health = player_packet.health;
+                    }
+                    bot.sequence.assign(&player_packet.head.sequence);
+                }
+                ServerPacket::MapPacket(map_packet) => {
+                    let (width, height) = map_packet.size;
+                    if width != 0. && height != 0. {
+                        bot.room_size = Vec2::new(width, height);
+                    }
+                    bot.targets.clear();
+                    bot.sequence.assign(&map_packet.head.sequence);
+                }
+                ServerPacket::EnemyPacket(enemy_packet) => {
+                    let position = enemy_packet.transform.translation.truncate();
+                    match bot.targets.iter_mut().find(|t| t.id.id == enemy_packet.enemytype.id) {
+                        Some(target) => {
+                            target.position = position;
+                            target.health = enemy_packet.health.current;
+                        }
+                        None => bot.targets.push(BotTarget {
+                            id: enemy_packet.enemytype.clone(),
+                            position: position,
+                            health: enemy_packet.health.current,
+                        }),
+                    }
+                    bot.sequence.assign(&enemy_packet.head.sequence);
+                }
+                ServerPacket::DespawnPacket(despawn_packet) => {
+                    bot.targets.retain(|t| t.id.id != despawn_packet.enemy_id.id);
+                }
+                ServerPacket::DespawnAllPacket(_) => {
+                    bot.targets.clear();
+                }
+                ServerPacket::PingPacket(ping_packet) => {
+                    let latency = now.saturating_sub(ping_packet.sent.time);
+                    stats.pongs += 1;
+                    stats.latency_total_ms += latency;
+                    stats.latency_max_ms = stats.latency_max_ms.max(latency);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/* moves every joined bot, sends its state, swings, and pings.
+ * runs on FixedUpdate so bots produce input at the same rate
+ * as a real client does */
+pub fn bot_drive(
+    mut bots: Query<(&mut Bot, &UDP)>,
+    mut stats: ResMut<BotStats>,
+    config: Res<BotConfig>,
+    time: Res<Time>,
+    real_time: Res<Time<Real>>,
+) {
+    let mut rng = rand::thread_rng();
+    let dt = time.delta_seconds();
+    let now = real_time.elapsed().as_millis() as u64;
+
+    for (mut bot, udp) in bots.iter_mut() {
+        match bot.state {
+            BotState::Failed => continue,
+            BotState::Joining => {
+                bot.join_timer.tick(time.delta());
+                if bot.join_timer.just_finished() {
+                    bot.state = BotState::Failed;
+                    stats.failed += 1;
+                    warn!("bot never got an id from {}", config.server);
+                }
+                continue;
+            }
+            BotState::Joined => {}
+        }
+
+        /* pick where we're heading */
+        bot.step_timer.tick(time.delta());
+        let turned = bot.step_timer.just_finished();
+        match config.pattern {
+            BotPattern::Random => {
+                if turned || bot.velocity == Vec2::ZERO {
+                    let angle: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+                    bot.velocity = Vec2::from_angle(angle) * PLAYER_SPEED;
+                }
+            }
+            BotPattern::Script => {
+                if turned {
+                    bot.script_step = (bot.script_step + 1) % 4;
+                }
+                bot.velocity = match bot.script_step {
+                    0 => Vec2::X,
+                    1 => Vec2::Y,
+                    2 => Vec2::NEG_X,
+                    _ => Vec2::NEG_Y,
+                } * PLAYER_SPEED;
+            }
+        }
+
+        /* walk, clamped to the room same as move_player */
+        let half = bot.room_size / 2. - Vec2::splat(TILE_SIZE as f32 * 1.5);
+        let step = bot.velocity * dt;
+        let mut translation = bot.transform.translation;
+        translation.x = (translation.x + step.x).clamp(-half.x, half.x);
+        translation.y = (translation.y + step.y).clamp(-half.y, half.y);
+        bot.transform.translation = translation;
+
+        /* swing at anything in reach */
+        let position = bot.transform.translation.truncate();
+        bot.attack = match config.pattern {
+            BotPattern::Random => bot.targets.iter().any(|t| t.position.distance(position) < BOT_ATTACK_REACH),
+            BotPattern::Script => turned,
+        };
+        if bot.attack {
+            let mut killed = Vec::new();
+            for target in bot.targets.iter_mut() {
+                if target.position.distance(position) >= BOT_ATTACK_REACH {
+                    continue;
+                }
+                target.health -= 1.;
+                let packet = ClientPacket::DecreaseEnemyHealthPacket(DecreaseEnemyHealthPacket {
+                    enemy_id: target.id.clone(),
+                    decrease_by: 1.,
+                });
+                send_to_server(&packet, udp, &config);
+                if target.health <= 0. {
+                    killed.push(target.id.clone());
+                }
+            }
+            for enemy_id in killed {
+                let packet = ClientPacket::KillEnemyPacket(KillEnemyPacket { enemy_id: enemy_id.clone() });
+                send_to_server(&packet, udp, &config);
+                bot.targets.retain(|t| t.id.id != enemy_id.id);
+            }
+        }
+
+        /* player state, same shape client::send_player sends */
+        let to_send = ClientPacket::PlayerPacket(PlayerSendable {
+            head: Header::new(bot.id, bot.sequence.clone()),
+            transform: bot.transform,
+            velocity: bot.velocity,
+            health: bot.health,
+            crouch: false,
+            attack: bot.attack,
+            roll: false,
+            sprint: false,
+        });
+        send_to_server(&to_send, udp, &config);
+
+        bot.ping_timer.tick(time.delta());
+        if bot.ping_timer.just_finished() {
+            let ping = ClientPacket::PingPacket(PingPacket {
+                head: Header::new(bot.id, bot.sequence.clone()),
+                sent: Timestamp::new(now),
+            });
+            send_to_server(&ping, udp, &config);
+            stats.pings += 1;
+        }
+        bot.sequence.geti();
+    }
+}
+
+/* spits out how the run is going, then resets the latency window */
+pub fn bot_report(
+    bots: Query<&Bot>,
+    mut stats: ResMut<BotStats>,
+) {
+    let joining = bots.iter().filter(|b| b.state == BotState::Joining).count();
+    let avg = if stats.pongs > 0 { stats.latency_total_ms / stats.pongs as u64 } else { 0 };
+    let lost = stats.pings.saturating_sub(stats.pongs);
+    info!(
+        "bots joined: {} failed: {} joining: {} | latency avg: {}ms max: {}ms | pings lost: {} | packets in: {}",
+        stats.joined, stats.failed, joining, avg, stats.latency_max_ms, lost, stats.packets
+    );
+    stats.pings = 0;
+    stats.pongs = 0;
+    stats.latency_total_ms = 0;
+    stats.latency_max_ms = 0;
+    stats.packets = 0;
+}
+
+fn send_to_server<T: Serialize>(
+    packet: &T,
+    udp: &UDP,
+    config: &BotConfig,
+) {
+    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+    packet.serialize(&mut serializer).unwrap();
+    let packet: &[u8] = serializer.view();
+    if let Err(e) = udp.socket.send_to(&packet, &config.server) {
+        warn!("bot send failed: {}", e);
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fs;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::player::{NetworkId, Player};
+use crate::ui::{CarnageBar, CarnageChangeEvent};
+
+/* where the server looks if --carnage-rules isnt given.
+ * relative to wherever the server was launched from */
+pub const CARNAGE_RULES_PATH: &str = "carnage_rules.ron";
+
+/* seconds since an enemy last saw you before you count as unseen again */
+const DEFAULT_UNDETECTED_WINDOW: f32 = 3.;
+
+/* things players do that move their carnage bar */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CarnageTrigger {
+    /* any kill */
+    Kill,
+    /* kill with nothing having spotted you recently. fires alongside Kill */
+    KillUndetected,
+    /* an enemy got eyes on you */
+    Detected,
+    /* left a room with every enemy dead and nobody ever spotted */
+    RoomClearedUndetected,
+    /* smashed a pot */
+    PotBroken,
+    /* got hit */
+    DamageTaken,
+    /* walked through a door */
+    RoomEntered,
+}
+
+/* one line of the rules file. carnage > 0 pushes toward carnage,
+ * < 0 toward stealth. cooldown is per player, 0 means every time */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CarnageRule {
+    pub trigger: CarnageTrigger,
+    pub carnage: f32,
+    #[serde(default)]
+    pub cooldown: f32,
+}
+
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct CarnageRules {
+    #[serde(default = "default_undetected_window")]
+    pub undetected_window: f32,
+    pub rules: Vec<CarnageRule>,
+}
+
+fn default_undetected_window() -> f32 {
+    DEFAULT_UNDETECTED_WINDOW
+}
+
+impl CarnageRules {
+    /* what we shipped with before the rules file, plus the new triggers */
+    pub fn new() -> Self {
+        Self {
+            undetected_window: DEFAULT_UNDETECTED_WINDOW,
+            rules: vec![
+                CarnageRule { trigger: CarnageTrigger::Kill, carnage: 2.5, cooldown: 0. },
+                CarnageRule { trigger: CarnageTrigger::KillUndetected, carnage: -2., cooldown: 0. },
+                CarnageRule { trigger: CarnageTrigger::Detected, carnage: 3., cooldown: 5. },
+                CarnageRule { trigger: CarnageTrigger::RoomClearedUndetected, carnage: -10., cooldown: 0. },
+                CarnageRule { trigger: CarnageTrigger::PotBroken, carnage: 1., cooldown: 1. },
+                CarnageRule { trigger: CarnageTrigger::DamageTaken, carnage: 0.5, cooldown: 1. },
+                CarnageRule { trigger: CarnageTrigger::RoomEntered, carnage: -5., cooldown: 0. },
+            ],
+        }
+    }
+
+    /* bad or missing file isnt worth killing the server over,
+     * yell and fall back to the defaults */
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(text) => match ron::from_str::<CarnageRules>(&text) {
+                Ok(rules) => {
+                    println!("loaded {} carnage rules from {}", rules.rules.len(), path);
+                    rules
+                }
+                Err(e) => {
+                    println!("couldnt parse {}: {}, using default carnage rules", path, e);
+                    Self::new()
+                }
+            },
+            Err(e) => {
+                println!("couldnt read {}: {}, using default carnage rules", path, e);
+                Self::new()
+            }
+        }
+    }
+
+    pub fn get(&self, trigger: CarnageTrigger) -> Option<&CarnageRule> {
+        self.rules.iter().find(|rule| rule.trigger == trigger)
+    }
+}
+
+/* gameplay says something happened, apply_carnage_rules decides what it's worth */
+#[derive(Event, Debug)]
+pub struct CarnageEvent {
+    pub player: u8,
+    pub trigger: CarnageTrigger,
+}
+
+/* when each (player, trigger) last paid out, in seconds of server time */
+#[derive(Resource)]
+pub struct CarnageCooldowns {
+    pub last: HashMap<(u8, CarnageTrigger), f32>,
+}
+
+impl CarnageCooldowns {
+    pub fn new() -> Self {
+        Self { last: HashMap::new() }
+    }
+}
+
+/* who has been spotted and when. enemy_movement fills it in,
+ * kills and room changes read it */
+#[derive(Resource)]
+pub struct Detection {
+    pub last_seen: HashMap<u8, f32>,
+    /* anyone spotted since we walked into this room */
+    pub room_detected: bool,
+}
+
+impl Detection {
+    pub fn new() -> Self {
+        Self {
+            last_seen: HashMap::new(),
+            room_detected: false,
+        }
+    }
+
+    /* marks player as seen. true if they werent already being watched */
+    pub fn spot(&mut self, player: u8, now: f32, window: f32) -> bool {
+        let fresh = !self.seen_recently(player, now, window);
+        self.last_seen.insert(player, now);
+        self.room_detected = true;
+        fresh
+    }
+
+    pub fn seen_recently(&self, player: u8, now: f32, window: f32) -> bool {
+        match self.last_seen.get(&player) {
+            Some(seen) => now - seen < window,
+            None => false,
+        }
+    }
+
+    pub fn new_room(&mut self) {
+        self.room_detected = false;
+    }
+}
+
+/* the one place carnage bars get touched on the server */
+pub fn apply_carnage_rules(
+    mut events: EventReader<CarnageEvent>,
+    rules: Res<CarnageRules>,
+    mut cooldowns: ResMut<CarnageCooldowns>,
+    mut bars: Query<(&NetworkId, &mut CarnageBar), With<Player>>,
+    mut carnage_change: EventWriter<CarnageChangeEvent>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds();
+    let mut changed = false;
+    for event in events.read() {
+        changed |= apply_carnage_event(event, &rules, &mut cooldowns, bars.iter_mut(), now);
+    }
+    if changed {
+        carnage_change.send(CarnageChangeEvent(true));
+    }
+}
+
+/* scores one event against its rule and cooldown. check_door calls this
+ * straight away for the room it's leaving, so the next room rolls on a
+ * party value that already counts it. true if a bar moved */
+pub fn apply_carnage_event<'a>(
+    event: &CarnageEvent,
+    rules: &CarnageRules,
+    cooldowns: &mut CarnageCooldowns,
+    bars: impl Iterator<Item = (&'a NetworkId, Mut<'a, CarnageBar>)>,
+    now: f32,
+) -> bool {
+    let Some(rule) = rules.get(event.trigger) else {
+        return false;
+    };
+    if let Some(last) = cooldowns.last.get(&(event.player, event.trigger)) {
+        if now - last < rule.cooldown {
+            return false;
+        }
+    }
+    cooldowns.last.insert((event.player, event.trigger), now);
+
+    let mut changed = false;
+    for (id, mut bar) in bars {
+        if id.id != event.player {
+            continue;
+        }
+        if rule.carnage >= 0. {
+            bar.up_carnage(rule.carnage);
+        } else {
+            bar.down_carnage(-rule.carnage);
+        }
+        changed = true;
+    }
+    changed
+}
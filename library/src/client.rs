@@ -3,15 +3,19 @@ use std::net::SocketAddr;
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::autotile::{FloorPiece, WallPiece};
 use crate::collision::Aabb;
 use crate::{cuscuta_resources::*, player};
 use crate::enemies::{BossKill, BossKillEvent, ClientEnemy, Enemy, EnemyId, EnemyKind, EnemyMovement};
 use crate::network::{
-    CarnagePacket, ClientPacket, ClientPacketQueue, EnemyS2C, Header, IdPacket, KillEnemyPacket, MapS2C, PlayerSendable, Sequence, ServerPacket, UDP
+    CarnagePacket, ClientPacket, ClientPacketQueue, EnemyS2C, FullPacket, Header, IdPacket, KillEnemyPacket, MapS2C, PlayerSendable, Sequence, ServerPacket, UDP
 };
 use crate::player::*;
-use crate::room_gen::{ClientDoor, ClientRoomManager, Door, DoorType, InnerWall, Potion, Room};
-use crate::ui::CarnageBar;
+use crate::room_gen::{ClientDoor, ClientRoomManager, Door, DoorType, InnerWall, Potion, Room, RoomType};
+use crate::special_rooms::{ShopCounter, ShopPrice, SHOP_PRICE};
+use crate::stealth::PropKind;
+use crate::traps::{Afflictions, TrapKind};
+use crate::ui::{CarnageBar, CarnageStatus, DoorVoteStatus};
 
 /* sends out all clientPackets from the ClientPacketQueue */
 pub fn client_send_packets(udp: Res<UDP>, mut packets: ResMut<ClientPacketQueue>) {
@@ -46,6 +50,13 @@ pub fn recv_id(
     info!("ASSIGNED ID: {:?}", id.id);
 }
 
+/* server turned us away, the party is full. no map is coming
+ * so dont sit in init_listen forever */
+pub fn recv_full(ds_struct: &FullPacket) {
+    error!("server is full ({} players), try again later", ds_struct.max);
+    std::process::exit(1);
+}
+
 /* Sends id request to the server
  * ID PLESASE */
 pub fn id_request(
@@ -100,7 +111,9 @@ pub fn listen(
     mut idstore: ResMut<'_, EnemyIdChecker>,
     mut carnage: Query<&mut CarnageBar>,
     mut event_writer: EventWriter<BossKillEvent>,
-
+    mut door_vote: ResMut<DoorVoteStatus>,
+    mut carnage_status: ResMut<CarnageStatus>,
+    (mut afflictions, mut shop): (ResMut<Afflictions>, ResMut<ShopCounter>),
 ) {
     //info!("Listening!!!");
     loop{
@@ -128,6 +141,9 @@ pub fn listen(
             recv_id(&id_packet, &mut sequence, &mut client_id);
             sequence.assign(&id_packet.head.sequence);
         }
+        ServerPacket::FullPacket(full_packet) => {
+            recv_full(&full_packet);
+        }
         ServerPacket::PlayerPacket(player_packet) => {
             /*  gahhhh sequence borrow checker is giving me hell */
             /* if we encounter porblems, it's herer fs */ 
@@ -136,6 +152,10 @@ pub fn listen(
         }
         ServerPacket::MapPacket(map_packet) => {
             receive_map_packet(&mut commands, &asset_server, &map_packet, &mut room_query, &mut room_manager, &mut texture_atlases);
+            /* fresh shelf, anything we were told no about can be asked again,
+             * and an answer for the old room isnt coming */
+            shop.asked.clear();
+            shop.waiting = false;
             sequence.assign(&map_packet.head.sequence);
         }
         ServerPacket::EnemyPacket(enemy_packet) => {
@@ -150,12 +170,33 @@ pub fn listen(
         }
 
         ServerPacket::CarnagePacket(carnage_pack) => {
-            update_carnage(&mut carnage,&carnage_pack);
+            update_carnage(&mut carnage, &mut carnage_status, &client_id, &carnage_pack);
 
         }
         ServerPacket::MonkeyPacket(monkey_packet) => {
             player::spawn_other_monkey(&mut commands, monkey_packet.transform, &asset_server, &mut texture_atlases,);
         }
+        /* we never ping, bots do */
+        ServerPacket::PingPacket(_) => {}
+        ServerPacket::DoorVotePacket(vote_packet) => {
+            door_vote.set(&vote_packet);
+            sequence.assign(&vote_packet.head.sequence);
+        }
+        /* stepped on something, only ever sent to us */
+        ServerPacket::HazardPacket(hazard_packet) => {
+            for (_, _, _, mut health, _, _, _, _, id, _) in players_q.iter_mut() {
+                if id.id == client_id.id && health.current > 0. {
+                    health.current -= hazard_packet.damage;
+                }
+            }
+            afflictions.take(&hazard_packet);
+            sequence.assign(&hazard_packet.head.sequence);
+        }
+        /* what the shop said, player_interact sorts it out */
+        ServerPacket::ShopPacket(shop_packet) => {
+            sequence.assign(&shop_packet.head.sequence);
+            shop.answers.push(shop_packet);
+        }
     }
 }// stupid loop
 }
@@ -229,6 +270,11 @@ fn receive_player_packet(
             sprite: SpriteBundle {
                 texture: player_sheet_handle,
                 transform: saranpack.transform,
+                /* tint per id so a full party isnt four identical guys */
+                sprite: Sprite {
+                    color: player_color(saranpack.head.network_id),
+                    ..default()
+                },
                 ..default()
             },
             atlas: TextureAtlas {
@@ -412,16 +458,23 @@ fn despawn_enemy(
 
 /** INDEX TO USE
     0 - floor
-    1 - left wall
-    2 - right wall
+    1 - outer wall
     3 - chest/pot
     4 - left door
     5 - right door
     6 - top door
     7 - bottom door 
-    8 - top wall
-    9 - bottom wall 
-    10 - pot */
+    10 - pot
+    11 - inner wall
+    12 - smashed pot
+    13 - stairs down
+    14 - potion for sale
+    15/16 - pressure plate up/held, 17/18 - lever up/held
+    19 - low cover, 20 - tall grass, 21 - shadow, 22 - creaky boards
+    23 - spikes, 24 - acid, 25 - spore vent, 26 - tripwire
+    27 - decor, whatever clutter the biome has
+    walls and floor (under anything) get their sprite off the tiles layer,
+    what autotile picked for that spot */
 fn receive_map_packet (
     mut commands: &mut Commands,
     asset_server: &AssetServer,
@@ -437,6 +490,12 @@ fn receive_map_packet (
         room_manager.width = new_width;
         room_manager.height = new_height;
     }   
+    room_manager.states = map_packet.states.clone();
+    room_manager.carnage = map_packet.carnage;
+    room_manager.floor = map_packet.floor;
+    room_manager.last_floor = map_packet.last_floor;
+    room_manager.biome = map_packet.biome;
+    let tiles = map_packet.biome.tileset();
 
     let map_array = &map_packet.matrix;
     let mut horizontal = -(new_width / 2.0) + (TILE_SIZE as f32 / 2.0);
@@ -461,23 +520,30 @@ fn receive_map_packet (
         None,
     );
     let pot_layout_handle = texture_atlases.add(pot_layout);
+    /* doors wear the type of room past them, locked ones dont care */
+    let client_door = |door_type: DoorType| ClientDoor {
+        door_type: door_type,
+        behind: map_packet.doors.iter()
+            .find(|(door, _)| *door == door_type)
+            .map_or(RoomType::Combat, |(_, behind)| *behind),
+        locked: map_packet.locked,
+    };
    // info!("starting ({}, {})",horizontal, vertical);
     for a in 0..map_array.len() {
         for b in 0..map_array[0].len() {
             let val = map_array[a][b];
+            let piece = map_packet.tiles.get(a).and_then(|column| column.get(b)).copied().unwrap_or(0);
+            let floor = FloorPiece::from_index(piece).sprite(&tiles);
+            let wall = WallPiece::from_index(piece).sprite(&tiles);
             match val {
                 0 => commands.spawn((SpriteBundle {
-                    texture: asset_server
-                        .load("tiles/cobblestone_floor/cobblestone_floor.png")
-                        .clone(),
+                    texture: asset_server.load(floor.path).clone(),
+                    sprite: floor.sprite(),
                     transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
                     ..default() },Background,Room,)),
                 1 => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/walls/left_wall.png").clone(),
-                    transform: Transform::from_xyz(horizontal, vertical, z_index),
-                    ..default() },Wall,Room,)),
-                2 => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/walls/right_wall.png").clone(),
+                    texture: asset_server.load(wall.path).clone(),
+                    sprite: wall.sprite(),
                     transform: Transform::from_xyz(horizontal, vertical, z_index),
                     ..default() },Wall,Room,)),
 /*poton */      3 => {
@@ -486,35 +552,26 @@ fn receive_map_packet (
                     transform: Transform::from_xyz(horizontal, vertical, z_index),
                     ..default() },Potion,Room,));
                     commands.spawn((SpriteBundle {
-                    texture: asset_server
-                        .load("tiles/cobblestone_floor/cobblestone_floor.png")
-                        .clone(),
+                    texture: asset_server.load(floor.path).clone(),
+                    sprite: floor.sprite(),
                     transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
                     ..default() },Background,Room,))}
                 4 => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/solid_floor/solid_floor.png").clone(),
+                    texture: asset_server.load(tiles.door).clone(),
                     transform: Transform::from_xyz(horizontal, vertical, z_index+0.5),
-                    ..default() },ClientDoor{door_type: DoorType::Left,},Room,)),
+                    ..default() },client_door(DoorType::Left),Room,)),
                 5 => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/solid_floor/solid_floor.png").clone(),
+                    texture: asset_server.load(tiles.door).clone(),
                     transform: Transform::from_xyz(horizontal, vertical, z_index+0.5),
-                    ..default() },ClientDoor{door_type: DoorType::Right,},Room,)),
+                    ..default() },client_door(DoorType::Right),Room,)),
                 6 => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/solid_floor/solid_floor.png").clone(),
+                    texture: asset_server.load(tiles.door).clone(),
                     transform: Transform::from_xyz(horizontal, vertical, z_index+0.5),
-                    ..default() },ClientDoor{door_type: DoorType::Top,},Room,)),
+                    ..default() },client_door(DoorType::Top),Room,)),
                 7 => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/solid_floor/solid_floor.png").clone(),
+                    texture: asset_server.load(tiles.door).clone(),
                     transform: Transform::from_xyz(horizontal, vertical, z_index+0.5),
-                    ..default() },ClientDoor{door_type: DoorType::Bottom,},Room,)),
-                8 => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/walls/north_wall.png").clone(),
-                    transform: Transform::from_xyz(horizontal, vertical, z_index),
-                    ..default() },Wall,Room,)),
-                9 => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/walls/bottom_wall.png").clone(),
-                    transform: Transform::from_xyz(horizontal, vertical, z_index),
-                    ..default() },Wall,Room,)),
+                    ..default() },client_door(DoorType::Bottom),Room,)),
                 10 => {commands.spawn(( 
                     SpriteBundle {
                         texture: asset_server.load("tiles/1x2_pot.png").clone(),
@@ -528,19 +585,131 @@ fn receive_map_packet (
                     Pot::new(),
                     Room,));
                     commands.spawn((SpriteBundle {
-                        texture: asset_server
-                            .load("tiles/cobblestone_floor/cobblestone_floor.png")
-                            .clone(),
+                        texture: asset_server.load(floor.path).clone(),
+                        sprite: floor.sprite(),
                         transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
                         ..default() },Background,Room,))
                 },
                 /* inner walls */
                 11 => commands.spawn((SpriteBundle {
-                    texture: asset_server.load("tiles/walls/north_wall.png").clone(),
+                    texture: asset_server.load(wall.path).clone(),
+                    sprite: wall.sprite(),
                     transform: Transform::from_xyz(horizontal, vertical, z_index),
                     ..default() },Wall, InnerWall::new(),Room,)),
+                /* pot someone already smashed, last time through */
+                12 => {commands.spawn((
+                    SpriteBundle {
+                        texture: asset_server.load("tiles/1x2_pot.png").clone(),
+                        transform: Transform::from_xyz(horizontal, vertical, z_index + 0.5),
+                        ..default()
+                    },
+                    TextureAtlas {
+                        layout: pot_layout_handle.clone(),
+                        index:1,
+                    },
+                    Pot{ touch: 1 },
+                    Room,));
+                    commands.spawn((SpriteBundle {
+                        texture: asset_server.load(floor.path).clone(),
+                        sprite: floor.sprite(),
+                        transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
+                        ..default() },Background,Room,))
+                },
+                /* stairs the floor boss left, no art yet so a dark slab */
+                13 => {commands.spawn((SpriteBundle {
+                    texture: asset_server.load("tiles/solid_floor/solid_floor.png").clone(),
+                    sprite: Sprite { color: Color::srgb(0.15, 0.15, 0.2), ..default() },
+                    transform: Transform::from_xyz(horizontal, vertical, z_index + 0.5),
+                    ..default() },Room,));
+                    commands.spawn((SpriteBundle {
+                        texture: asset_server.load(floor.path).clone(),
+                        sprite: floor.sprite(),
+                        transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
+                        ..default() },Background,Room,))
+                },
+                /* shop shelf, costs health to pick up */
+                14 => {
+                    commands.spawn(( SpriteBundle {
+                    texture: asset_server.load("items/potion.png").clone(),
+                    transform: Transform::from_xyz(horizontal, vertical, z_index),
+                    ..default() },Potion,ShopPrice(SHOP_PRICE),Room,));
+                    commands.spawn((SpriteBundle {
+                    texture: asset_server.load(floor.path).clone(),
+                    sprite: floor.sprite(),
+                    transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
+                    ..default() },Background,Room,))}
+                /* teamwork puzzles, no art yet so tinted slabs */
+                15..=18 => {
+                    let color = match val {
+                        15 => Color::srgb(0.5, 0.5, 0.55),
+                        16 => Color::srgb(0.4, 0.9, 0.4),
+                        17 => Color::srgb(0.6, 0.4, 0.2),
+                        _ => Color::srgb(1.0, 0.85, 0.3),
+                    };
+                    commands.spawn((SpriteBundle {
+                    texture: asset_server.load("tiles/solid_floor/solid_floor.png").clone(),
+                    sprite: Sprite { color: color, ..default() },
+                    transform: Transform::from_xyz(horizontal, vertical, z_index + 0.5),
+                    ..default() },Room,));
+                    commands.spawn((SpriteBundle {
+                        texture: asset_server.load(floor.path).clone(),
+                        sprite: floor.sprite(),
+                        transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
+                        ..default() },Background,Room,))
+                },
+                /* stealth props, no art yet either. grass and shadow go over
+                 * whoever's standing in them, cover and boards under */
+                19..=22 => {
+                    let (color, z) = match PropKind::from_code(val) {
+                        Some(PropKind::Cover) => (Color::srgb(0.45, 0.3, 0.15), z_index - 0.4),
+                        Some(PropKind::Grass) => (Color::srgba(0.2, 0.6, 0.15, 0.6), z_index + 0.5),
+                        Some(PropKind::Shadow) => (Color::srgba(0., 0., 0.05, 0.55), z_index + 0.5),
+                        _ => (Color::srgb(0.6, 0.45, 0.3), z_index - 0.4),
+                    };
+                    commands.spawn((SpriteBundle {
+                    sprite: Sprite { color: color, custom_size: Some(Vec2::splat(TILE_SIZE as f32)), ..default() },
+                    transform: Transform::from_xyz(horizontal, vertical, z),
+                    ..default() },Room,));
+                    commands.spawn((SpriteBundle {
+                        texture: asset_server.load(floor.path).clone(),
+                        sprite: floor.sprite(),
+                        transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
+                        ..default() },Background,Room,))
+                },
+                /* traps, same deal, all under the players */
+                23..=26 => {
+                    let color = match TrapKind::from_code(val) {
+                        Some(TrapKind::Spikes) => Color::srgb(0.55, 0.55, 0.6),
+                        Some(TrapKind::Acid) => Color::srgba(0.5, 0.9, 0.1, 0.8),
+                        Some(TrapKind::Spores) => Color::srgb(0.6, 0.3, 0.7),
+                        _ => Color::srgba(0.85, 0.8, 0.5, 0.5),
+                    };
+                    commands.spawn((SpriteBundle {
+                    sprite: Sprite { color: color, custom_size: Some(Vec2::splat(TILE_SIZE as f32)), ..default() },
+                    transform: Transform::from_xyz(horizontal, vertical, z_index - 0.4),
+                    ..default() },Room,));
+                    commands.spawn((SpriteBundle {
+                        texture: asset_server.load(floor.path).clone(),
+                        sprite: floor.sprite(),
+                        transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
+                        ..default() },Background,Room,))
+                },
+                /* biome clutter, sits on the floor and does nothing */
+                27 => {
+                    let decor = map_packet.biome.decor();
+                    commands.spawn((SpriteBundle {
+                    texture: asset_server.load(decor.sprite).clone(),
+                    sprite: Sprite { color: decor.tint, ..default() },
+                    transform: Transform::from_xyz(horizontal, vertical, z_index - 0.4),
+                    ..default() },Room,));
+                    commands.spawn((SpriteBundle {
+                        texture: asset_server.load(floor.path).clone(),
+                        sprite: floor.sprite(),
+                        transform: Transform::from_xyz(horizontal, vertical, z_index-0.5),
+                        ..default() },Background,Room,))
+                },
                 _ => commands.spawn(( SpriteBundle {
-                    texture: asset_server.load("tiles/walls/bottom_wall.png").clone(),
+                    texture: asset_server.load(tiles.bottom_wall).clone(),
                     transform: Transform::from_xyz(-10000.0, -10000.0, z_index),
                     ..default() },Wall,Room,)),
             };
@@ -567,15 +736,17 @@ pub fn send_player(
     >,
     seq: Res<Sequence>,
     clientid: Res<ClientId>,
-    udp: Res<UDP>
+    udp: Res<UDP>,
+    mut sent_crouch: Local<bool>,
 ){
     'playa: for (id, velo, trans, heal, crouch, roll, sprint, attack) in player_q.iter(){
         if id.id == clientid.id{
             /* we don't want to send if we arent doing anything, no use...
-             * same goes for server!!!!! */
-            if velo.velocity.y == 0. && velo.velocity.x == 0. {
+             * same goes for server!!!!! ducking down behind cover counts */
+            if velo.velocity.y == 0. && velo.velocity.x == 0. && crouch.crouching == *sent_crouch {
                 continue 'playa;
             }
+            *sent_crouch = crouch.crouching;
             let to_send = ClientPacket::PlayerPacket(PlayerSendable {
                 head: Header {
                     network_id: id.id,
@@ -687,6 +858,9 @@ can do same with enemy but a paststatequeue needs creted for their stuff yk yk y
             recv_id(&id_packet, &mut sequence, &mut client_id);
             sequence.assign(&id_packet.head.sequence);
         }
+        ServerPacket::FullPacket(full_packet) => {
+            recv_full(&full_packet);
+        }
         ServerPacket::PlayerPacket(player_packet) => {
             /*  gahhhh sequence borrow checker is giving me hell */
             /* if we encounter porblems, it's herer fs */ 
@@ -723,12 +897,20 @@ fn kill_everyone(
     }
 }
 
+/* hud bar is ours, everyone else's (and the party's) go in
+ * CarnageStatus for the teammate bars and party marker */
 pub fn update_carnage(
     mut carnage: &mut Query<&mut CarnageBar>,
+    status: &mut CarnageStatus,
+    client_id: &ClientId,
     pack: &CarnagePacket,
 ){
-    for mut carn in carnage.iter_mut(){
-        *carn = pack.carnage.clone();
+    status.bars = pack.bars.clone();
+    status.party = pack.party.clone();
+    if let Some(ours) = status.get(client_id.id){
+        for mut carn in carnage.iter_mut(){
+            *carn = ours.clone();
+        }
     }
 }
 
@@ -743,9 +925,13 @@ pub fn boss_kill_event(
     mut camera: Query<&mut Transform, (Without<Player>, With<Camera>, Without<PopupTimer>)>,
     mut popup: Query<(&mut PopupTimer, &mut Transform)>,
     mut boss_bool: ResMut<BossKill>,
-
+    room_manager: Res<ClientRoomManager>,
 ){
     for event in boss_event.read(){
+        /* every other floor's boss leaves stairs, server sends those */
+        if !room_manager.last_floor{
+            continue;
+        }
         println!("spaning chalice you kill boss");
         commands.spawn((
             SpriteBundle{
@@ -2,12 +2,17 @@
  * use `mod constants;` to grab.
  * I hope this dead_code isn't package wide... */
 #![allow(dead_code)]
+use std::env;
 use std::net::SocketAddr;
 use bevy::prelude::*;
+use bevy::color::palettes::css::{DEEP_SKY_BLUE, GOLD, HOT_PINK, LIME, ORANGE_RED, VIOLET, WHITE, AQUA};
 use flexbuffers::FlexbufferSerializer;
 use serde::{Deserialize, Serialize};
 
+use crate::carnage::CARNAGE_RULES_PATH;
 use crate::network::KillEnemyPacket;
+use crate::prefabs::{RoomGenMode, PREFABS_PATH};
+use crate::room_gen::{HALLWAY_WIDTH, ROOM_CONFIG_PATH};
 
 #[derive(Component, Deref, DerefMut)]
 pub struct PopupTimer(pub Timer);
@@ -16,6 +21,11 @@ pub const CLIENT_ID_DEFAULT: u8 = 0;
 
 pub const TICKS_PER_SECOND: f64 = 60.;
 
+/* default party cap, server can override with --max-players */
+pub const MAX_PLAYERS: u8 = 4;
+/* how hard the party carnage leans toward the bloodiest player, 0-1 */
+pub const PARTY_CARNAGE_WEIGHT: f32 = 0.5;
+
 pub const SERVER_ADR: &str = "10.0.0.60:5001"; //136.142.159.86:5001
 
 pub const POT_SPRITE_COL: u32 = 1;
@@ -128,7 +138,122 @@ impl Health {
 
 #[derive(Resource)]
 pub struct PlayerCount{
-    pub count: u8
+    pub count: u8,
+    /* no more ids handed out past this */
+    pub max: u8,
+}
+
+impl PlayerCount{
+    pub fn new(max: u8) -> Self{
+        Self{
+            count: 0,
+            max: max,
+        }
+    }
+
+    pub fn full(&self) -> bool{
+        self.count >= self.max
+    }
+}
+
+/* server knobs off the command line
+ * server [--max-players N] [--carnage-weight 0-1] [--carnage-rules file.ron] [--room-config file.ron] [--seed N] [--hallway-width N]
+ *        [--prefabs file.ron] [--room-gen procedural|mixed|prefab] */
+#[derive(Resource, Clone, Debug)]
+pub struct ServerConfig{
+    pub max_players: u8,
+    pub carnage_weight: f32,
+    pub carnage_rules: String,
+    /* room sizes/counts + markov matrices, hot reloaded */
+    pub room_config: String,
+    /* None rolls a fresh one */
+    pub seed: Option<u64>,
+    /* corridor width in floor tiles */
+    pub hallway_width: usize,
+    /* hand drawn rooms and chunks */
+    pub prefabs: String,
+    pub room_gen: RoomGenMode,
+}
+
+impl ServerConfig{
+    pub fn from_args() -> Self{
+        let mut config = Self{
+            max_players: MAX_PLAYERS,
+            carnage_weight: PARTY_CARNAGE_WEIGHT,
+            carnage_rules: String::from(CARNAGE_RULES_PATH),
+            room_config: String::from(ROOM_CONFIG_PATH),
+            seed: None,
+            hallway_width: HALLWAY_WIDTH,
+            prefabs: String::from(PREFABS_PATH),
+            room_gen: RoomGenMode::Mixed,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next(){
+            match arg.as_str(){
+                "--max-players" => {
+                    match args.next().and_then(|n| n.parse::<u8>().ok()){
+                        /* id 0 is the server, so 254 is as high as we go */
+                        Some(n) if n > 0 && n < u8::MAX => config.max_players = n,
+                        _ => println!("--max-players wants a number 1-254, using {}", MAX_PLAYERS),
+                    }
+                }
+                "--carnage-weight" => {
+                    match args.next().and_then(|n| n.parse::<f32>().ok()){
+                        Some(w) if (0. ..=1.).contains(&w) => config.carnage_weight = w,
+                        _ => println!("--carnage-weight wants 0-1, using {}", PARTY_CARNAGE_WEIGHT),
+                    }
+                }
+                "--carnage-rules" => {
+                    match args.next(){
+                        Some(path) => config.carnage_rules = path,
+                        None => println!("--carnage-rules wants a path, using {}", CARNAGE_RULES_PATH),
+                    }
+                }
+                "--room-config" => {
+                    match args.next(){
+                        Some(path) => config.room_config = path,
+                        None => println!("--room-config wants a path, using {}", ROOM_CONFIG_PATH),
+                    }
+                }
+                "--seed" => {
+                    match args.next().and_then(|n| n.parse::<u64>().ok()){
+                        Some(seed) => config.seed = Some(seed),
+                        None => println!("--seed wants a whole number, rolling one"),
+                    }
+                }
+                "--hallway-width" => {
+                    match args.next().and_then(|n| n.parse::<usize>().ok()){
+                        Some(w) if (1..=8).contains(&w) => config.hallway_width = w,
+                        _ => println!("--hallway-width wants 1-8, using {}", HALLWAY_WIDTH),
+                    }
+                }
+                "--prefabs" => {
+                    match args.next(){
+                        Some(path) => config.prefabs = path,
+                        None => println!("--prefabs wants a path, using {}", PREFABS_PATH),
+                    }
+                }
+                "--room-gen" => {
+                    match args.next().as_deref().and_then(RoomGenMode::from_arg){
+                        Some(mode) => config.room_gen = mode,
+                        None => println!("--room-gen wants procedural, mixed or prefab, using mixed"),
+                    }
+                }
+                _ => println!("ignoring server arg {}", arg),
+            }
+        }
+        config
+    }
+}
+
+/* one tint per NetworkId so teammates can tell each other apart.
+ * wraps around if someone cranks max players past the list */
+pub const PLAYER_COLORS: [Srgba; 8] = [WHITE, DEEP_SKY_BLUE, ORANGE_RED, LIME, GOLD, VIOLET, HOT_PINK, AQUA];
+
+pub fn player_color(id: u8) -> Color{
+    /* ids start at 1, server is 0 */
+    let index = (id.max(1) - 1) as usize % PLAYER_COLORS.len();
+    Color::from(PLAYER_COLORS[index])
 }
 
 #[derive(Resource)]
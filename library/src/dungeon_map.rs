@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use crate::room_gen::RoomId;
+
+/* DUNGEON MAP. where every room sits, in tiles, y up, layout 0,0 in the
+ * middle. replaces the old dense 400x400 room_map: tiles live in square
+ * chunks that only get made once something is drawn in them, so the map
+ * grows whichever way the layout goes and there's no edge to fall off */
+
+// tiles per chunk side
+pub const CHUNK_SIZE: i32 = 32;
+
+/* a box of tiles, x/y is the bottom left corner */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl MapRect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    /* box of a layout slot centered on x/y */
+    pub fn centered(x: f32, y: f32, width: usize, height: usize) -> Self {
+        Self::new(
+            (x - width as f32 / 2.).round() as i32,
+            (y - height as f32 / 2.).round() as i32,
+            width as i32,
+            height as i32,
+        )
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    pub fn top(&self) -> i32 {
+        self.y + self.height
+    }
+
+    /* smallest box holding both */
+    pub fn union(&self, other: &MapRect) -> MapRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        MapRect::new(x, y, self.right().max(other.right()) - x, self.top().max(other.top()) - y)
+    }
+
+    /* same box with `by` tiles more on every side */
+    pub fn padded(&self, by: i32) -> MapRect {
+        MapRect::new(self.x - by, self.y - by, self.width + by * 2, self.height + by * 2)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DungeonMap {
+    /* chunk coords -> CHUNK_SIZE * CHUNK_SIZE tiles, row by row */
+    pub chunks: HashMap<(i32, i32), Vec<Option<RoomId>>>,
+    /* footprint each room was drawn with, so lookups dont scan chunks */
+    pub footprints: HashMap<RoomId, MapRect>,
+}
+
+impl DungeonMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* chunk a tile lands in and its spot inside that chunk. euclid so
+     * negative tiles go left/down instead of folding onto 0 */
+    fn split(x: i32, y: i32) -> ((i32, i32), usize) {
+        let chunk = (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE));
+        let spot = y.rem_euclid(CHUNK_SIZE) * CHUNK_SIZE + x.rem_euclid(CHUNK_SIZE);
+        (chunk, spot as usize)
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<RoomId> {
+        let (chunk, spot) = Self::split(x, y);
+        self.chunks.get(&chunk).and_then(|tiles| tiles[spot])
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, room: RoomId) {
+        let (chunk, spot) = Self::split(x, y);
+        self.chunks
+            .entry(chunk)
+            .or_insert_with(|| vec![None; (CHUNK_SIZE * CHUNK_SIZE) as usize])[spot] = Some(room);
+    }
+
+    /* draws a room's whole footprint */
+    pub fn add_room(&mut self, room: RoomId, rect: MapRect) {
+        for x in rect.x..rect.right() {
+            for y in rect.y..rect.top() {
+                self.set(x, y, room);
+            }
+        }
+        self.footprints
+            .entry(room)
+            .and_modify(|old| *old = old.union(&rect))
+            .or_insert(rect);
+    }
+
+    pub fn room_bounds(&self, room: RoomId) -> Option<MapRect> {
+        self.footprints.get(&room).copied()
+    }
+
+    /* nothing drawn anywhere in rect */
+    pub fn is_free(&self, rect: MapRect) -> bool {
+        (rect.x..rect.right()).all(|x| (rect.y..rect.top()).all(|y| self.get(x, y).is_none()))
+    }
+
+    /* box around everything drawn so far, None while empty */
+    pub fn bounds(&self) -> Option<MapRect> {
+        self.footprints.values().copied().reduce(|all, rect| all.union(&rect))
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.footprints.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_tiles_go_in_their_own_chunks() {
+        let mut map = DungeonMap::new();
+        map.set(-1, -1, RoomId(1));
+        map.set(0, 0, RoomId(2));
+        map.set(CHUNK_SIZE * 5, -CHUNK_SIZE * 3, RoomId(3));
+        assert_eq!(map.chunks.len(), 3);
+        assert_eq!(map.get(-1, -1), Some(RoomId(1)));
+        assert_eq!(map.get(0, 0), Some(RoomId(2)));
+        assert_eq!(map.get(CHUNK_SIZE * 5, -CHUNK_SIZE * 3), Some(RoomId(3)));
+        /* same spot in the chunk next door stays empty */
+        assert_eq!(map.get(CHUNK_SIZE - 1, CHUNK_SIZE - 1), None);
+    }
+
+    #[test]
+    fn rooms_fill_their_box_and_nothing_else() {
+        let mut map = DungeonMap::new();
+        let rect = MapRect::new(-40, -10, 50, 20);
+        map.add_room(RoomId(0), rect);
+        assert_eq!(map.room_bounds(RoomId(0)), Some(rect));
+        assert!(!map.is_free(MapRect::new(9, 9, 5, 5)));
+        assert!(map.is_free(MapRect::new(10, -10, 5, 20)));
+        assert!(!map.is_free(rect.padded(1)));
+        assert!(map.is_free(MapRect::new(-41, -10, 1, 20)));
+        map.add_room(RoomId(1), MapRect::new(100, 100, 4, 4));
+        assert_eq!(map.bounds(), Some(MapRect::new(-40, -10, 144, 114)));
+    }
+}
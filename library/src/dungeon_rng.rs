@@ -0,0 +1,124 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::room_gen::RoomId;
+
+/* independent rolls for each part of generation. each room gets a
+ * fresh rng per stream, so pulling one more number for enemies
+ * can never shift where the walls go (or the next room) */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RngStream {
+    Layout,
+    Walls,
+    Enemies,
+    Items,
+    /* whole-dungeon rolls (the room graph), see DungeonRng::dungeon */
+    Dungeon,
+    /* which prefab or chunk a room gets, and where chunks land */
+    Prefabs,
+    /* room shapes and cave noise */
+    Shapes,
+    /* challenge room waves past the first, see DungeonRng::wave */
+    Waves,
+    /* whether a room gets a co-op puzzle, which one and where */
+    Teamwork,
+    /* stealth props, what kind and where */
+    Props,
+    /* traps, what kind and where */
+    Traps,
+    /* floor clutter, see biomes.rs */
+    Decor,
+    /* which plain floor variant each tile gets, see autotile.rs */
+    Tiles,
+    /* rooms the dungeon sprouts mid floor, see DungeonLayout::grow */
+    Growth,
+}
+
+/* one seed per run. same seed + same carnage history = same dungeon */
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct DungeonRng {
+    pub seed: u64,
+    /* room ids start over every floor, so the floor goes in the key too */
+    pub floor: u32,
+}
+
+impl DungeonRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed: seed, floor: 0 }
+    }
+
+    /* same run, rolls for another floor */
+    pub fn for_floor(&self, floor: u32) -> Self {
+        Self { seed: self.seed, floor: floor }
+    }
+
+    /* the only roll that isnt reproducible, picking the seed itself */
+    pub fn random_seed() -> u64 {
+        rand::thread_rng().gen()
+    }
+
+    /* rng for stream in one room */
+    pub fn stream(&self, stream: RngStream, room: RoomId) -> StdRng {
+        self.keyed(stream, room.0 as u64)
+    }
+
+    /* rng for one challenge wave in a room, wave goes above the room id */
+    pub fn wave(&self, room: RoomId, wave: usize) -> StdRng {
+        self.keyed(RngStream::Waves, ((wave as u64) << 16) | room.0 as u64)
+    }
+
+    /* rng for rolls that arent about any one room, like the graph itself */
+    pub fn dungeon(&self) -> StdRng {
+        self.keyed(RngStream::Dungeon, 0)
+    }
+
+    fn keyed(&self, stream: RngStream, room: u64) -> StdRng {
+        let key = ((self.floor as u64) << 48) | ((stream as u64) << 32) | (room & 0xFFFF_FFFF);
+        StdRng::seed_from_u64(mix(self.seed ^ mix(key)))
+    }
+}
+
+/* splitmix64 finalizer, spreads nearby keys (room 1 vs 2) far apart */
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(mut rng: StdRng) -> Vec<u64> {
+        (0..8).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_rolls() {
+        let a = DungeonRng::new(1234);
+        let b = DungeonRng::new(1234);
+        for room in [RoomId(0), RoomId(1), RoomId(57)] {
+            assert_eq!(draw(a.stream(RngStream::Walls, room)), draw(b.stream(RngStream::Walls, room)));
+            assert_eq!(draw(a.stream(RngStream::Enemies, room)), draw(b.stream(RngStream::Enemies, room)));
+        }
+        assert_ne!(draw(a.stream(RngStream::Walls, RoomId(0))), draw(DungeonRng::new(1235).stream(RngStream::Walls, RoomId(0))));
+    }
+
+    #[test]
+    fn streams_rooms_and_floors_dont_share_rolls() {
+        let rng = DungeonRng::new(1234);
+        let streams = [RngStream::Layout, RngStream::Walls, RngStream::Enemies, RngStream::Items, RngStream::Tiles];
+        let mut seen: Vec<Vec<u64>> = Vec::new();
+        for stream in streams {
+            for room in 0..16 {
+                seen.push(draw(rng.stream(stream, RoomId(room))));
+            }
+        }
+        seen.push(draw(rng.for_floor(1).stream(RngStream::Walls, RoomId(0))));
+        seen.push(draw(rng.dungeon()));
+        for (index, rolls) in seen.iter().enumerate() {
+            assert!(!seen[index + 1..].contains(rolls), "rolls {} came up twice", index);
+        }
+    }
+}
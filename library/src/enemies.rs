@@ -1,12 +1,25 @@
 use bevy::prelude::*;
-use rand::Rng;
+use rand::{Rng, rngs::StdRng};
 use serde::{Deserialize, Serialize};
 
+use crate::dungeon_rng::{DungeonRng, RngStream};
+use crate::reachability::{reachable_spots, take_spot, ENEMY_EDGE_MARGIN};
+use crate::room_state::SavedEnemy;
+use crate::stealth::{PropMap, NOISE_HEARD};
+use crate::carnage::{CarnageEvent, CarnageRules, CarnageTrigger, Detection};
 use crate::{collision::*, cuscuta_resources::*, network::{KillEnemyPacket, ServerPacket, UDP}, player::{self, *}, markov_chains::*, room_gen::*};
 
 #[derive(Event)]
 pub struct BossKillEvent(pub Vec2);
 
+/* which way an enemy paces when it cant see anyone. rooms pace
+ * left/right, hallway patrols walk their leg */
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PatrolAxis(pub Vec2);
+
+/* patrols per hallway before party scaling */
+const HALLWAY_PATROLS: (usize, usize) = (0, 2);
+
 #[derive(Resource)]
 pub struct BossKill{pub dead:bool}
 
@@ -303,48 +316,51 @@ impl EnemyId {
 
 pub fn enemy_movement(
     mut commands: Commands,
-    mut enemy_query: Query<(&mut Transform, &mut EnemyTimer, &mut EnemyMovement, &mut Health, Entity, &EnemyId), With<Enemy>>,
+    mut enemy_query: Query<(&mut Transform, &mut EnemyTimer, &mut EnemyMovement, &mut Health, Entity, &EnemyId, &Enemy, Option<&PatrolAxis>)>,
     mut player_query: Query<
-        (&mut Transform, &mut Health),
+        (&mut Transform, &mut Health, Option<&NetworkId>, Option<&Crouch>),
         (With<Trackable>, Without<Enemy>)>,
     wall_query: Query<(&Transform, &Wall), (Without<Player>, Without<EnemyTimer>, Without<Trackable>)>,
     time: Res<Time>,
     addresses: Res<AddressList>,
     udp: Res<UDP>,
+    mut carnage_rules: EventWriter<CarnageEvent>,
+    mut detection: ResMut<Detection>,
+    rules: Res<CarnageRules>,
+    mut boss_kill: EventWriter<BossKillEvent>,
+    room_manager: Res<RoomManager>,
 ) {
+    let now = time.elapsed_seconds();
+    /* grass, shadows, cover and creaky boards, see stealth.rs */
+    let props = PropMap::current(&room_manager);
    // info!("running enemy mvmt");
     // for every enemy
-    for (mut transform, mut timer, mut movement, mut health, ent, eid) in enemy_query.iter_mut() {
+    for (mut transform, mut timer, mut movement, mut health, ent, eid, enemy, patrol) in enemy_query.iter_mut() {
       //  info!("Sanity CHECK");
         // checking which player each enemy should follow (if any are in range)
         let mut player_transform: Transform = Transform::from_xyz(0., 0., 0.); //to appease the all-knowing compiler
         // checking which player is closest
         let mut longest: f32 = 99999999999.0;
-        //determining spot distance
-        let spot;
-        if health.max == SK_HEALTH.max {spot = SK_SPOT_DIST;}
-        else if health.max == B_HEALTH.max {spot = B_SPOT_DIST;}
-        else if health.max == BR_HEALTH.max {spot = BR_SPOT_DIST;}
-        else if health.max == SP_HEALTH.max {spot = SP_SPOT_DIST;}
-        else {spot = N_SPOT_DIST;}
-        //determining speed
-        let mut speed;
-        if health.max == SK_HEALTH.max {speed = SK_MAX_SPEED;}
-        else if health.max == B_HEALTH.max {speed = B_MAX_SPEED;}
-        else if health.max == BR_HEALTH.max {speed = BR_MAX_SPEED;}
-        else if health.max == SP_HEALTH.max {speed = SP_MAX_SPEED;}
-        else {speed = N_MAX_SPEED;}
+        /* stats come off the Enemy itself, health.max gets scaled
+         * with party size so it cant tell us who we are anymore */
+        let spot = enemy.spot_distance;
+        let mut speed = enemy.max_speed;
         // for every player - FINDING CLOSEST PLAYER
-        for (mut pt, mut ph) in player_query.iter_mut() {
+        for (mut pt, mut ph, pid, crouch) in player_query.iter_mut() {
             if ph.current <= 0. {
                 continue;
             }
+            /* monkeys dont crouch */
+            let crouching = crouch.map_or(false, |crouch| crouch.crouching);
+            let range = props.spot_range(spot, pt.translation.truncate(), crouching);
+            let heard = props.heard(pt.translation.truncate(), crouching);
             // find hypotenuse to get distance to player
             let xdis = (pt.translation.x - transform.translation.x).abs()
                 * (pt.translation.x - transform.translation.x).abs();
             let ydis = (pt.translation.y - transform.translation.y).abs()
                 * (pt.translation.y - transform.translation.y).abs();
-            if ydis + xdis < spot * spot {
+            let in_earshot = heard && ydis + xdis < (spot * NOISE_HEARD) * (spot * NOISE_HEARD);
+            if ydis + xdis < range * range || in_earshot {
                 let mut blocked = false;
                 //line of sight
                 for a in 0..20 {
@@ -355,6 +371,9 @@ pub fn enemy_movement(
                     let ynew = transform.translation.y
                         + dec * (pt.translation.y - transform.translation.y);
                     let pointaabb = Aabb::new(Vec3::new(xnew, ynew, 0.), Vec2::splat(1.));
+                    if props.covers(Vec2::new(xnew, ynew), transform.translation.truncate(), pt.translation.truncate(), crouching) {
+                        blocked = true;
+                    }
                     for (wt, _w) in wall_query.iter() {
                         //checking if any line hitbox collides with any wall
                         //if wt.translation.z == pt.translation.z || wt.translation.z == pt.translation.z - 0.1 {
@@ -365,10 +384,18 @@ pub fn enemy_movement(
                         //}
                     }
                 }
-                if blocked == true {
+                /* you can hear through walls */
+                if blocked == true && !in_earshot {
                     continue;
                 }
 
+                /* monkeys get chased too but only players get spotted */
+                if let Some(pid) = pid {
+                    if detection.spot(pid.id, now, rules.undetected_window) {
+                        carnage_rules.send(CarnageEvent{player: pid.id, trigger: CarnageTrigger::Detected});
+                    }
+                }
+
                 // making sure enemy chases closest enemy
                 if ydis + xdis < longest {
                     longest = ydis + xdis;
@@ -384,6 +411,9 @@ pub fn enemy_movement(
                 health.current = health.current - 0.5;
                 if health.current <= 0.0 {
                     commands.entity(ent).despawn();
+                    if let EnemyKind::Boss(_) = eid.kind {
+                        boss_kill.send(BossKillEvent(transform.translation.truncate()));
+                    }
                     let mut serializer = flexbuffers::FlexbufferSerializer::new();
                     let to_send: ServerPacket = ServerPacket::DespawnPacket(KillEnemyPacket{enemy_id: eid.clone()}.clone());
                     to_send.serialize(&mut serializer).unwrap();
@@ -397,9 +427,15 @@ pub fn enemy_movement(
             //if hit player
             if enemy_aabb.intersects(&player_aabb) && ph.current != 69.69 {
                 
-                if health.max == SK_HEALTH.max || health.max == SP_HEALTH.max {ph.current -= 10.;}
-                else if health.max == B_HEALTH.max {ph.current -= 20.;}
+                match eid.kind {
+                    EnemyKind::Skeleton(_) | EnemyKind::SplatMonkey(_) => ph.current -= 10.,
+                    EnemyKind::Boss(_) => ph.current -= 20.,
+                    _ => {}
+                }
                 ph.current -= 5.;
+                if let Some(pid) = pid {
+                    carnage_rules.send(CarnageEvent{player: pid.id, trigger: CarnageTrigger::DamageTaken});
+                }
 
                 // knockback applied to player
                 let direction_to_player = player_transform.translation - transform.translation;
@@ -431,8 +467,9 @@ pub fn enemy_movement(
                 }
                 speed = speed * 10.;
             } else {
+                let pace = patrol.map(|p| p.0).unwrap_or(Vec2::X);
                 normalized_direction =
-                    Vec3::new(1. * movement.axis as f32, 0. * movement.axis as f32, 0.);
+                    Vec3::new(pace.x * movement.axis as f32, pace.y * movement.axis as f32, 0.);
             }
             //collision detection
             //let mut collide = false;
@@ -568,6 +605,28 @@ pub fn handle_enemy_collision(
     
 }
 
+/* party size curves. a room tuned for one player gets half again
+ * the bodies per extra player, and each body a quarter more health.
+ * nobody home (start room at boot) means nobody to fight */
+const PARTY_COUNT_STEP: f32 = 0.5;
+const PARTY_HEALTH_STEP: f32 = 0.25;
+
+pub fn party_enemy_count(base: usize, players: u8) -> usize {
+    if players == 0 {
+        return 0;
+    }
+    let scale = 1. + PARTY_COUNT_STEP * (players - 1) as f32;
+    (base as f32 * scale).round() as usize
+}
+
+pub fn party_enemy_health(base: &Health, players: u8) -> Health {
+    let scale = 1. + PARTY_HEALTH_STEP * players.saturating_sub(1) as f32;
+    Health {
+        max: base.max * scale,
+        current: base.current * scale,
+    }
+}
+
 pub fn server_spawn_enemies(
     mut commands: &mut Commands,
     mut enemy_id: &mut EnemyId,
@@ -575,29 +634,72 @@ pub fn server_spawn_enemies(
     room_config: &RoomConfig,
     roomman: &RoomManager,
     n_p: &PlayerCount,
+    dungeon_rng: &DungeonRng,
+) {
+    /* shops and rest stops are safe, challenge rooms get more later */
+    if matches!(roomman.current_room_type(), RoomType::Shop | RoomType::Rest) {
+        return;
+    }
+    let mut rng = dungeon_rng.stream(RngStream::Enemies, roomman.current_room_id());
+    spawn_room_enemies(commands, enemy_id, last_attribute_array, room_config, roomman, n_p, &mut rng);
+}
+
+/* a room's worth of enemies off whatever rng it's handed, challenge
+ * waves come through here too (see special_rooms.rs) */
+pub fn spawn_room_enemies(
+    commands: &mut Commands,
+    enemy_id: &mut EnemyId,
+    last_attribute_array: &mut LastAttributeArray,
+    room_config: &RoomConfig,
+    roomman: &RoomManager,
+    n_p: &PlayerCount,
+    rng: &mut StdRng,
 ) {
-    let mut rng = rand::thread_rng();
+    /* hallways get a couple patrols instead of a room's worth */
+    let hallway = roomman.current_layout_room().filter(|room| room.is_hallway()).cloned();
     
-    let enemy_count_range = room_config.get_enemy_count(last_attribute_array.get_attribute(2).unwrap_or(1));
+    let enemy_count_range = match hallway {
+        Some(_) => HALLWAY_PATROLS,
+        None => room_config.get_enemy_count(last_attribute_array.get_attribute(ENEMY_COUNT).unwrap_or(1)),
+    };
     //println!("Enemy range min: {}, max: {}",enemy_count_range.0,enemy_count_range.1);
     
     let mut enemy_count = rng.gen_range(enemy_count_range.0..=enemy_count_range.1);
     // println!("BEFORE MULTIPLYING: {}",enemy_count);
     // println!("NUM PLAYERS: {}",n_p.count);
-    enemy_count = enemy_count * n_p.count as usize;
+    enemy_count = party_enemy_count(enemy_count, n_p.count);
     // println!("AFTER: {}",enemy_count);
     //println!("Min count {} - Max count {}",enemy_count_range.0,enemy_count_range.1);
 
-    let enemy_types = room_config.get_enemy_type(last_attribute_array.get_attribute(3).unwrap_or(1));
-
-    let (x,y) = roomman.current_room_max();
+    /* hallway patrols are always the sneaky kinds, whatever the last room rolled.
+     * either way only what lives in this floor's biome */
+    let enemy_types = match hallway {
+        Some(_) => room_config.get_enemy_type(0),
+        None => room_config.get_enemy_type(last_attribute_array.get_attribute(ENEMY_TYPE).unwrap_or(1)),
+    };
+    let enemy_pool = room_config.biome().enemy_pool(enemy_types);
+
+    /* rooms spawn on open floor reachable from the middle, never inside an inner wall */
+    let grid = roomman.current_grid_ref();
+    let mut spots = reachable_spots(grid, &roomman.current_key_tiles(), ENEMY_EDGE_MARGIN);
+    /* prefab enemy markers fill up first, in the order they were drawn */
+    let mut marked: Vec<Vec2> = roomman.get_markers(roomman.current_room_id()).map(|markers| markers.enemies.clone()).unwrap_or_default();
+    marked.reverse();
 
     for _ in 0..enemy_count {
 
-        let random_x = rng.gen_range((-x + 128.)..(x - 128.));
-        let random_y = rng.gen_range((-y + 128.)..(y - 128.));
+        let (random_x, random_y, patrol) = match hallway.as_ref().and_then(|room| room.hallway_patrol_spot(rng)) {
+            Some((spot, axis)) => (spot.x, spot.y, axis),
+            None => match marked.pop().or_else(|| take_spot(grid, &mut spots, rng)) {
+                Some(spot) => (spot.x, spot.y, Vec2::X),
+                None => {
+                    println!("room {} ran out of open floor for enemies", roomman.current_room_id().0);
+                    break;
+                }
+            },
+        };
         //info!("random x: {}, random y: {}", random_x, random_y);
-        let enemy_type_index = rng.gen_range(enemy_types.0..=enemy_types.1);
+        let enemy_type_index = enemy_pool[rng.gen_range(0..enemy_pool.len())];
         // 0 ninja
         // 1 berry rat
         // 2 splat monkey
@@ -614,7 +716,7 @@ pub fn server_spawn_enemies(
                             String::from(N_PATH),
                             N_SPRITE_H,
                             N_SPRITE_W,
-                            N_MAX_SPEED,
+                            room_config.scale_enemy_speed(N_MAX_SPEED),
                             N_SPOT_DIST,
                             N_HEALTH,
                             N_SIZE,
@@ -627,8 +729,9 @@ pub fn server_spawn_enemies(
                         timer: EnemyTimer {
                             time: Timer::from_seconds(3.0, TimerMode::Repeating),
                         },
-                        health: Health::new(&N_HEALTH),
+                        health: room_config.scale_enemy_health(party_enemy_health(&N_HEALTH, n_p.count)),
                     },
+                    PatrolAxis(patrol),
                 ));
             }
             2 => {
@@ -641,7 +744,7 @@ pub fn server_spawn_enemies(
                             String::from(BR_PATH),
                             BR_SPRITE_H,
                             BR_SPRITE_W,
-                            BR_MAX_SPEED,
+                            room_config.scale_enemy_speed(BR_MAX_SPEED),
                             BR_SPOT_DIST,
                             BR_HEALTH,
                             BR_SIZE,
@@ -654,8 +757,9 @@ pub fn server_spawn_enemies(
                         timer: EnemyTimer {
                             time: Timer::from_seconds(3.0, TimerMode::Repeating),
                         },
-                        health: Health::new(&BR_HEALTH),
+                        health: room_config.scale_enemy_health(party_enemy_health(&BR_HEALTH, n_p.count)),
                     },
+                    PatrolAxis(patrol),
                 ));
                 //println!("spawned enemy - berry wat@({},{})", random_x, random_y);
                 //println!("spawned enemy - berry wat@({},{})", random_x, random_y);
@@ -670,7 +774,7 @@ pub fn server_spawn_enemies(
                             String::from(SP_PATH),
                             SP_SPRITE_H,
                             SP_SPRITE_W,
-                            SP_MAX_SPEED,
+                            room_config.scale_enemy_speed(SP_MAX_SPEED),
                             SP_SPOT_DIST,
                             SP_HEALTH,
                             SP_SIZE,
@@ -683,8 +787,9 @@ pub fn server_spawn_enemies(
                         timer: EnemyTimer {
                             time: Timer::from_seconds(3.0, TimerMode::Repeating),
                         },
-                        health: Health::new(&SP_HEALTH),
+                        health: room_config.scale_enemy_health(party_enemy_health(&SP_HEALTH, n_p.count)),
                     },
+                    PatrolAxis(patrol),
                 ));
                 //println!("spawned enemy - monke @({},{})", random_x, random_y);
             }
@@ -698,7 +803,7 @@ pub fn server_spawn_enemies(
                             String::from(SK_PATH),
                             SK_SPRITE_H,
                             SK_SPRITE_W,
-                            SK_MAX_SPEED,
+                            room_config.scale_enemy_speed(SK_MAX_SPEED),
                             SK_SPOT_DIST,
                             SK_HEALTH,
                             SK_SIZE,
@@ -711,8 +816,9 @@ pub fn server_spawn_enemies(
                         timer: EnemyTimer {
                             time: Timer::from_seconds(3.0, TimerMode::Repeating),
                         },
-                        health: Health::new(&SK_HEALTH),
+                        health: room_config.scale_enemy_health(party_enemy_health(&SK_HEALTH, n_p.count)),
                     },
+                    PatrolAxis(patrol),
                 ));
                // println!("spawned enemy - skelly@({},{})", random_x, random_y);
             }
@@ -726,7 +832,7 @@ pub fn server_spawn_enemies(
                             String::from(B_PATH),
                             B_SPRITE_H,
                             B_SPRITE_W,
-                            B_MAX_SPEED,
+                            room_config.scale_enemy_speed(B_MAX_SPEED),
                             B_SPOT_DIST,
                             B_HEALTH,
                             B_SIZE,
@@ -739,8 +845,9 @@ pub fn server_spawn_enemies(
                         timer: EnemyTimer {
                             time: Timer::from_seconds(3.0, TimerMode::Repeating),
                         },
-                        health: Health::new(&B_HEALTH),
+                        health: room_config.scale_enemy_health(party_enemy_health(&B_HEALTH, n_p.count)),
                     },
+                    PatrolAxis(patrol),
                 ));
                 println!("spawned enemy - boss");
             }
@@ -774,4 +881,37 @@ pub fn server_spawn_enemies(
     //         health: Health {max: 2., current: 2.},
     //     },
     // ));
+}
+
+/* puts back whoever was still standing when the party last left this
+ * room, same spot and health. fresh ids so clients dont mix them up
+ * with the ones they just despawned */
+pub fn server_restore_enemies(
+    commands: &mut Commands,
+    enemy_id: &mut EnemyId,
+    saved: &[SavedEnemy],
+    roomman: &RoomManager,
+    dungeon_rng: &DungeonRng,
+) {
+    let mut rng = dungeon_rng.stream(RngStream::Enemies, roomman.current_room_id());
+    for enemy in saved {
+        commands.spawn((
+            ServerEnemyBundle {
+                transform: Transform::from_translation(enemy.translation),
+                id: EnemyId::new(enemy_id.get_plus(), enemy.kind.clone()),
+                enemy: enemy.enemy.clone(),
+                motion: EnemyMovement::new(
+                    Vec2::new(rng.gen::<f32>(), rng.gen::<f32>()).normalize(),
+                    1,
+                    Vec3::new(99999., 0., 0.),
+                ),
+                timer: EnemyTimer {
+                    time: Timer::from_seconds(3.0, TimerMode::Repeating),
+                },
+                health: enemy.health,
+            },
+            PatrolAxis(enemy.patrol),
+        ));
+    }
+    println!("room {} put back {} enemies", roomman.current_room_id().0, saved.len());
 }
\ No newline at end of file
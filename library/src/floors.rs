@@ -0,0 +1,181 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::biomes::{default_biomes, Biome};
+use crate::carnage::Detection;
+use crate::cuscuta_resources::{AddressList, Background, Health, PlayerCount, ServerConfig, Wall, TILE_SIZE};
+use crate::collision::Aabb;
+use crate::dungeon_rng::DungeonRng;
+use crate::enemies::{server_spawn_enemies, BossKillEvent, Enemy, EnemyId};
+use crate::markov_chains::LastAttributeArray;
+use crate::network::{DespawnAllPacket, ServerPacket, UDP};
+use crate::player::Player;
+use crate::prefabs::PrefabLibrary;
+use crate::reachability::{pixel_to_tile, tile_to_pixel};
+use crate::room_gen::{spawn_start_room, ClientRoomManager, DungeonLayout, Room, RoomChangeEvent, RoomConfig, RoomManager};
+use crate::server::DoorVote;
+use crate::ui::PartyCarnage;
+
+/* FLOORS. the boss of every floor but the last leaves stairs behind
+ * instead of the chalice. once the whole party is standing on them
+ * everything room related gets thrown out and a fresh layout rolls
+ * for the next floor down, one tier tougher (see DepthScaling). players
+ * keep their health, carnage bars and whatever markov history they had */
+
+/* what changes the deeper you go, in room_config.ron under depth */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DepthScaling {
+    /* floors in a run, the last one's boss drops the chalice */
+    pub floors: u32,
+    /* extra per floor below the first, 0.25 = +25% a floor */
+    pub enemy_count: f32,
+    pub enemy_health: f32,
+    pub enemy_speed: f32,
+    /* enemy_type ranges start this much higher per floor (never past
+     * their max) so the weak kinds thin out */
+    pub enemy_type: usize,
+    /* biome of each floor, going round again past the end. see biomes.rs */
+    #[serde(default = "default_biomes")]
+    pub biomes: Vec<Biome>,
+}
+
+impl DepthScaling {
+    pub fn new() -> Self {
+        Self {
+            floors: 3,
+            enemy_count: 0.25,
+            enemy_health: 0.3,
+            enemy_speed: 0.1,
+            enemy_type: 1,
+            biomes: default_biomes(),
+        }
+    }
+
+    /* multiplier for a per floor step at depth (0 is the first floor) */
+    pub fn scale(step: f32, depth: u32) -> f32 {
+        1. + step * depth as f32
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.floors < 1 {
+            problems.push(String::from("depth floors has to be at least 1"));
+        }
+        for (field, step) in [("enemy_count", self.enemy_count), ("enemy_health", self.enemy_health), ("enemy_speed", self.enemy_speed)] {
+            if step < 0. {
+                problems.push(format!("depth {} cant be negative, floors only get harder", field));
+            }
+        }
+        if self.biomes.is_empty() {
+            problems.push(String::from("depth biomes needs at least one biome"));
+        }
+        problems
+    }
+}
+
+/* client side, lights each new room's walls and floor for the biome
+ * it's in */
+pub fn tint_floor_theme(
+    mut tiles: Query<&mut Sprite, (Added<Room>, Or<(With<Background>, With<Wall>)>)>,
+    room_manager: Res<ClientRoomManager>,
+){
+    let color = room_manager.biome.tint();
+    for mut sprite in tiles.iter_mut(){
+        sprite.color = color;
+    }
+}
+
+/* way down, server side. rides along in the boss room's RoomState */
+#[derive(Component)]
+pub struct Stairs;
+
+/* a floor boss went down (KillEnemyPacket or a monkey got it). every
+ * floor but the last gets stairs on the tile it died on, then the map
+ * goes out again so clients see them */
+pub fn open_stairs(
+    mut boss_kill: EventReader<BossKillEvent>,
+    mut commands: Commands,
+    mut room_manager: ResMut<RoomManager>,
+    room_config: Res<RoomConfig>,
+    mut room_change: EventWriter<RoomChangeEvent>,
+){
+    for event in boss_kill.read(){
+        if room_config.last_floor(){
+            println!("floor {} boss is down, that was the last floor", room_config.depth());
+            continue;
+        }
+        let grid = room_manager.current_grid_ref();
+        let spot = tile_to_pixel(grid, pixel_to_tile(grid, event.0));
+        commands.spawn((
+            Transform::from_xyz(spot.x, spot.y, room_manager.current_room_z_index() + 0.1),
+            Stairs,
+            Room,
+        ));
+        let room = room_manager.current_room_id();
+        room_manager.room_state_mut(room).stairs = Some(spot);
+        println!("floor {} boss is down, stairs open in room {}", room_config.depth(), room.0);
+        room_change.send(RoomChangeEvent(true));
+    }
+}
+
+/* everyone alive on the stairs = next floor */
+pub fn check_stairs(
+    mut commands: Commands,
+    mut players: Query<(&mut Transform, &Health), With<Player>>,
+    stairs: Query<&Transform, (With<Stairs>, Without<Player>)>,
+    room_query: Query<Entity, With<Room>>,
+    enemies: Query<Entity, With<Enemy>>,
+    mut room_manager: ResMut<RoomManager>,
+    mut room_config: ResMut<RoomConfig>,
+    mut dungeon_rng: ResMut<DungeonRng>,
+    mut last_attribute_array: ResMut<LastAttributeArray>,
+    mut enemy_id: ResMut<EnemyId>,
+    (config, num_players, party): (Res<ServerConfig>, Res<PlayerCount>, Res<PartyCarnage>),
+    (addresses, udp): (Res<AddressList>, Res<UDP>),
+    (mut vote, mut detection, mut room_change): (ResMut<DoorVote>, ResMut<Detection>, EventWriter<RoomChangeEvent>),
+){
+    let Some(stairs) = stairs.iter().next() else {
+        return;
+    };
+    let stairs_box = Aabb::new(stairs.translation, Vec2::splat(TILE_SIZE as f32));
+    let mut living = players.iter().filter(|(_, health)| health.current > 0.).peekable();
+    if living.peek().is_none() || !living.all(|(transform, _)| Aabb::new(transform.translation, Vec2::splat(TILE_SIZE as f32)).intersects(&stairs_box)){
+        return;
+    }
+
+    let depth = room_config.depth() + 1;
+    room_config.set_depth(depth);
+    *dungeon_rng = dungeon_rng.for_floor(depth);
+    println!("party heads down to floor {} ({})", depth, room_config.biome().name());
+
+    /* out with the old floor, clients too */
+    for entity in room_query.iter().chain(enemies.iter()){
+        commands.entity(entity).despawn();
+    }
+    let packet = ServerPacket::DespawnAllPacket(DespawnAllPacket { kill: true });
+    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+    packet.serialize(&mut serializer).unwrap();
+    let to_send = serializer.view();
+    for addr in addresses.list.iter(){
+        udp.socket.send_to(&to_send, addr).unwrap();
+    }
+
+    /* same prefabs, everything else starts over */
+    let prefabs = std::mem::replace(&mut room_manager.prefabs, PrefabLibrary::new());
+    *room_manager = RoomManager::new();
+    room_manager.prefabs = prefabs;
+    room_manager.layout = DungeonLayout::generate(&mut dungeon_rng.dungeon(), config.hallway_width, room_config.floor_rules());
+    room_manager.layout.print_layout();
+    room_manager.add_layout_to_map();
+    spawn_start_room(&mut commands, &mut room_manager, party.percent(), &mut last_attribute_array, &room_config, &dungeon_rng);
+    server_spawn_enemies(&mut commands, &mut enemy_id, &mut last_attribute_array, &room_config, &room_manager, &num_players, &dungeon_rng);
+
+    /* everybody lands in the middle of the new start room */
+    let z_index = room_manager.current_room_z_index();
+    for (mut transform, _) in players.iter_mut(){
+        transform.translation = Vec3::new(0., 0., z_index);
+    }
+    vote.clear();
+    detection.new_room();
+    room_change.send(RoomChangeEvent(true));
+}
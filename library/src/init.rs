@@ -4,11 +4,17 @@ use bevy::prelude::*;
 use serde::Deserialize;
 
 
+use crate::carnage::{CarnageCooldowns, CarnageRules, Detection};
 use crate::client::*;
+use crate::dungeon_rng::DungeonRng;
 use crate::cuscuta_resources::*;
 use crate::player::{Attack, Crouch, NetworkId, Player, Roll, Sprint};
-use crate::ui::CarnageBar;
-use crate::{camera::spawn_camera, cuscuta_resources::{self, AddressList, ClientId, EnemiesToKill, PlayerCount, TICKS_PER_SECOND}, enemies::{EnemyId, EnemyKind, *}, markov_chains::*, network::*, room_gen::{self, *}, ui::client_spawn_ui
+use crate::prefabs::PrefabLibrary;
+use crate::server::DoorVote;
+use crate::special_rooms::{Challenge, ShopCounter};
+use crate::traps::Afflictions;
+use crate::ui::{CarnageStatus, DoorVoteStatus, PartyCarnage};
+use crate::{camera::spawn_camera, cuscuta_resources::{self, AddressList, ClientId, EnemiesToKill, PlayerCount, ServerConfig, TICKS_PER_SECOND}, enemies::{EnemyId, EnemyKind, *}, markov_chains::*, network::*, room_gen::{self, *}, ui::client_spawn_ui
 
 };
 
@@ -55,12 +61,17 @@ pub fn client_setup(
     //client_spawn_pot(&mut commands, &asset_server, &mut texture_atlases);
 
     commands.insert_resource(ClientRoomManager::new());
+    commands.insert_resource(DoorVoteStatus::new());
+    commands.insert_resource(CarnageStatus::new());
+    commands.insert_resource(Afflictions::new());
+    commands.insert_resource(ShopCounter::new());
     
 }
 
 
 pub fn server_setup(
     mut commands: Commands,
+    config: Res<ServerConfig>,
 ){
     info!("entered setup");
     /* send from where ?*/
@@ -69,8 +80,6 @@ pub fn server_setup(
     socket.set_nonblocking(true).unwrap();
     commands.insert_resource(UDP{socket:socket});
 
-    let room_config = RoomConfig::new();
-    
     /* who we connected to again?*/
     commands.insert_resource(AddressList::new());
     /* lilk ordering action. 0 is server's Sequence index/id */
@@ -84,27 +93,53 @@ pub fn server_setup(
 
     commands.insert_resource(EnemiesToKill::new());
 
+    /* nobody is heading anywhere yet */
+    commands.insert_resource(DoorVote::new());
+    commands.insert_resource(Challenge::new());
+
     commands.insert_resource(EnemyId::new(0, EnemyKind::skeleton()));
-    commands.spawn((CarnageBar::new()));
+    /* each player carries their own CarnageBar, this is the blend */
+    commands.insert_resource(PartyCarnage::new(config.carnage_weight));
+    /* what moves those bars, see carnage_rules.ron */
+    commands.insert_resource(CarnageRules::load(&config.carnage_rules));
+    commands.insert_resource(CarnageCooldowns::new());
+    commands.insert_resource(Detection::new());
 
     let mut room_manager = RoomManager::new();
     let mut last_attribute_array = LastAttributeArray::new();
-    let room_config = RoomConfig::new();
+    /* room sizes, counts and markov matrices, see room_config.ron */
+    let room_config = RoomConfig::load(&config.room_config);
+    commands.insert_resource(RoomConfigFile::new(&config.room_config));
     let mut first_enemy = EnemyId::new(0, EnemyKind::skeleton());
-    let mut player_count = PlayerCount{count:0};
+    let mut player_count = PlayerCount::new(config.max_players);
+    info!("room for {} players", config.max_players);
+
+
 
+    /* --seed N to replay a run, otherwise roll one and say what it was */
+    let dungeon_rng = DungeonRng::new(config.seed.unwrap_or_else(DungeonRng::random_seed));
+    println!("dungeon seed: {}", dungeon_rng.seed);
 
+    /* whole dungeon graph up front, rooms fill in as the party reaches them */
+    room_manager.layout = DungeonLayout::generate(&mut dungeon_rng.dungeon(), config.hallway_width, room_config.floor_rules());
+    room_manager.layout.print_layout();
+    room_manager.add_layout_to_map();
+    /* hand drawn rooms and chunks, see prefabs.ron */
+    room_manager.prefabs = PrefabLibrary::load(&config.prefabs, config.room_gen);
 
-    spawn_start_room(&mut commands, &mut room_manager, 0.,&mut last_attribute_array,&room_config);
+    /* nobody has done anything yet, start room rolls at a neutral party */
+    let party = PartyCarnage::new(config.carnage_weight);
+    spawn_start_room(&mut commands, &mut room_manager, party.percent(),&mut last_attribute_array,&room_config, &dungeon_rng);
  
 
-    server_spawn_enemies(&mut commands, &mut first_enemy, &mut last_attribute_array, &room_config, &room_manager, &player_count);
+    server_spawn_enemies(&mut commands, &mut first_enemy, &mut last_attribute_array, &room_config, &room_manager, &player_count, &dungeon_rng);
     commands.insert_resource(room_config);
     commands.insert_resource(first_enemy);
     commands.insert_resource(room_manager);
     commands.insert_resource(last_attribute_array);
 
     commands.insert_resource(player_count);
+    commands.insert_resource(dungeon_rng);
     
 
     info!("done setup");
@@ -9,4 +9,19 @@ pub mod player;
 pub mod room_gen;
 pub mod server;
 pub mod client;
-pub mod markov_chains;
\ No newline at end of file
+pub mod markov_chains;
+pub mod bot;
+pub mod carnage;
+pub mod dungeon_rng;
+pub mod dungeon_map;
+pub mod reachability;
+pub mod prefabs;
+pub mod room_shapes;
+pub mod room_state;
+pub mod floors;
+pub mod special_rooms;
+pub mod teamwork;
+pub mod stealth;
+pub mod traps;
+pub mod biomes;
+pub mod autotile;
\ No newline at end of file
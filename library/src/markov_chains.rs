@@ -2,182 +2,401 @@ use std::vec::Vec;
 use crate::cuscuta_resources::*;
 use crate::ui::CarnageBar;
 use bevy::prelude::*;
+use rand::{Rng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
-pub enum Room_Attributes{
-    Room_Size,
-    Inner_Walls,
-    Enemy_Count,
-    Enemy_Type,
-    Item_Count,
+/* the seven chains room gen reads itself. they have to exist in the
+ * config and keep three states (stealth, neutral, carnage) so they
+ * line up with RoomConfig's states */
+pub const ROOM_SIZE: &str = "room_size";
+pub const INNER_WALLS: &str = "inner_walls";
+pub const ENEMY_COUNT: &str = "enemy_count";
+pub const ENEMY_TYPE: &str = "enemy_type";
+pub const ITEM_COUNT: &str = "item_count";
+pub const STEALTH_PROPS: &str = "stealth_props";
+pub const TRAPS: &str = "traps";
+pub const BUILT_IN_CHAINS: [&str; 7] = [ROOM_SIZE, INNER_WALLS, ENEMY_COUNT, ENEMY_TYPE, ITEM_COUNT, STEALTH_PROPS, TRAPS];
+/* boss rooms put every chain one past its last state, for the built in
+ * chains thats RoomConfig's boss state */
+pub const BOSS_STATE: u8 = 3;
+
+fn default_order() -> usize {
+    1
 }
 
-impl Room_Attributes {
-    pub fn get_preset_matrix() -> [Vec<Vec<f32>>; 5] {
-        [
-            vec![
-                // Room_Size: Large, Medium, Small
-                vec![0.2, 0.3, 0.5],
-                vec![0.25, 0.5, 0.25],
-                vec![0.4, 0.5, 0.1],
-            ],
-            vec![
-                // Inner_Walls: Some, Little, None
-                vec![0.0, 0.35, 0.65],
-                vec![0.25, 0.5, 0.25],
-                vec![0.10, 0.8, 0.10],
-            ],
-            vec![
-                // Enemy_Count: Many, Some, Few
-                vec![0.0, 0.35, 0.65],
-                vec![0.25, 0.5, 0.25],
-                vec![0.10, 0.8, 0.10],
-            ],
-            vec![
-                // Enemy_Type: Stealth, Both, Carnage
-                vec![0.0, 0.35, 0.65],
-                vec![0.25, 0.5, 0.25],
-                vec![0.10, 0.8, 0.10],
-            ],
-            vec![
-                // Item_Count: Some, Little, None
-                vec![0.0, 0.35, 0.65],
-                vec![0.25, 0.5, 0.25],
-                vec![0.10, 0.8, 0.10],
-            ],
-        ]
-    }
-
-    pub fn get_matrix_for_attribute(attribute: &Room_Attributes) -> Vec<Vec<f32>> {
-        let all_vectors: [Vec<Vec<f32>>; 5] = Room_Attributes::get_preset_matrix();
-        match attribute {
-            Room_Attributes::Room_Size => all_vectors[0].clone(),
-            Room_Attributes::Inner_Walls => all_vectors[1].clone(),
-            Room_Attributes::Enemy_Count => all_vectors[2].clone(),
-            Room_Attributes::Enemy_Type => all_vectors[3].clone(),
-            Room_Attributes::Item_Count => all_vectors[4].clone(),
-        }
-    }
+fn default_skew() -> String {
+    String::from("carnage")
+}
 
-    pub fn get_matrix_by_index(index: usize) -> Option<Vec<Vec<f32>>> {
-        let all_vectors = Room_Attributes::get_preset_matrix();
+/* one room attribute. rows are keyed on what the last room rolled
+ * (order 1) or the last two rooms (order 2, row = older * states + last),
+ * each row is the chance of every state next. skew names one of SKEWS */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarkovChain {
+    pub name: String,
+    pub states: Vec<String>,
+    #[serde(default = "default_order")]
+    pub order: usize,
+    #[serde(default = "default_skew")]
+    pub skew: String,
+    pub rows: Vec<Vec<f32>>,
+}
 
-        match index {
-            0 => Some(all_vectors[0].clone()), // Room_Size
-            1 => Some(all_vectors[1].clone()), // Inner_Walls
-            2 => Some(all_vectors[2].clone()), // Enemy_Count
-            3 => Some(all_vectors[3].clone()), // Enemy_Type
-            4 => Some(all_vectors[4].clone()), // Item_Count
-            _ => None, // Handle invalids
+impl MarkovChain {
+    /* first order chain over stealth/neutral/carnage */
+    pub fn three_state(name: &str, rows: Vec<Vec<f32>>) -> Self {
+        Self {
+            name: String::from(name),
+            states: vec![String::from("stealth"), String::from("neutral"), String::from("carnage")],
+            order: 1,
+            skew: default_skew(),
+            rows: rows,
         }
     }
-}
 
-#[derive(Resource, Debug)]
-pub struct LastAttributeArray {
-    pub attributes: [u8; 5], 
-}
+    pub fn boss_state(&self) -> u8 {
+        self.states.len() as u8
+    }
 
-impl LastAttributeArray {
-    // Constructor to initialize all values to 0 (default to "high")
-    pub fn new() -> Self {
-        Self { attributes: [1; 5] }
+    /* where a chain sits before any room has rolled, the middle state */
+    pub fn start_state(&self) -> u8 {
+        (self.states.len() / 2) as u8
     }
 
-    // Method to set a specific attribute
-    pub fn set_attribute(&mut self, index: usize, value: u8) {
-        if index < self.attributes.len() && value <= 3 {
-            self.attributes[index] = value;
-        } else {
-            println!("Invalid index or value!");
+    pub fn state_name(&self, state: u8) -> &str {
+        if state == self.boss_state() {
+            return "boss";
         }
+        self.states.get(state as usize).map(|name| name.as_str()).unwrap_or("???")
     }
 
-    // Method to get the value of a specific attribute
-    pub fn get_attribute(&self, index: usize) -> Option<u8> {
-        self.attributes.get(index).copied()
+    /* row for the last room (and the one before, for order 2). boss has
+     * no row of its own so it carries on like the last real state */
+    pub fn row(&self, last: u8, previous: u8) -> &Vec<f32> {
+        let top = self.states.len().max(1) - 1;
+        let last = (last as usize).min(top);
+        let previous = (previous as usize).min(top);
+        let index = if self.order == 2 { previous * self.states.len() + last } else { last };
+        &self.rows[index.min(self.rows.len() - 1)]
     }
 
-    // print array
-    pub fn print_array(&self) {
-        println!("LastAttributeArray: {:?}", self.attributes);
+    /* roll is 0-1, picks the next state off the skewed row */
+    pub fn next_state(&self, last: u8, previous: u8, carnage_percent: f32, roll: f32) -> u8 {
+        let skew = skew_by_name(&self.skew).unwrap_or(Skew_Row);
+        let skewed_row = skew(self.row(last, previous), carnage_percent);
+        pick_state(&skewed_row, roll)
     }
 
-    pub fn get_last_attribute_array(&self) -> [u8; 5]{
-        self.attributes
+    /* everything wrong with this chain, in plain words */
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let name = &self.name;
+        if self.states.len() < 2 {
+            problems.push(format!("chain {} needs at least 2 states, found {}", name, self.states.len()));
+            return problems;
+        }
+        if self.order != 1 && self.order != 2 {
+            problems.push(format!("chain {} order has to be 1 or 2, found {}", name, self.order));
+            return problems;
+        }
+        if skew_by_name(&self.skew).is_none() {
+            let known: Vec<&str> = SKEWS.iter().map(|(skew, _)| *skew).collect();
+            problems.push(format!("chain {} has unknown skew {} (know {})", name, self.skew, known.join(", ")));
+        }
+        let rows = self.states.len().pow(self.order as u32);
+        if self.rows.len() != rows {
+            problems.push(format!("chain {} needs {} rows for {} states at order {}, found {}", name, rows, self.states.len(), self.order, self.rows.len()));
+        }
+        for (row_index, row) in self.rows.iter().enumerate() {
+            if row.len() != self.states.len() {
+                problems.push(format!("chain {} row {} needs {} chances, found {}", name, row_index, self.states.len(), row.len()));
+                continue;
+            }
+            if row.iter().any(|chance| !(0. ..=1.).contains(chance)) {
+                problems.push(format!("chain {} row {} has a chance outside 0-1: {:?}", name, row_index, row));
+            }
+            let total: f32 = row.iter().sum();
+            if (total - 1.).abs() > 0.001 {
+                problems.push(format!("chain {} row {} adds up to {} instead of 1: {:?}", name, row_index, total, row));
+            }
+        }
+        problems
     }
 }
 
+/* the chains we shipped with */
+pub fn default_chains() -> Vec<MarkovChain> {
+    vec![
+        // Large, Medium, Small
+        MarkovChain::three_state(ROOM_SIZE, vec![
+            vec![0.2, 0.3, 0.5],
+            vec![0.25, 0.5, 0.25],
+            vec![0.4, 0.5, 0.1],
+        ]),
+        // Some, Little, None
+        MarkovChain::three_state(INNER_WALLS, vec![
+            vec![0.0, 0.35, 0.65],
+            vec![0.25, 0.5, 0.25],
+            vec![0.10, 0.8, 0.10],
+        ]),
+        // Many, Some, Few
+        MarkovChain::three_state(ENEMY_COUNT, vec![
+            vec![0.0, 0.35, 0.65],
+            vec![0.25, 0.5, 0.25],
+            vec![0.10, 0.8, 0.10],
+        ]),
+        // Stealth, Both, Carnage
+        MarkovChain::three_state(ENEMY_TYPE, vec![
+            vec![0.0, 0.35, 0.65],
+            vec![0.25, 0.5, 0.25],
+            vec![0.10, 0.8, 0.10],
+        ]),
+        // Some, Little, None
+        MarkovChain::three_state(ITEM_COUNT, vec![
+            vec![0.0, 0.35, 0.65],
+            vec![0.25, 0.5, 0.25],
+            vec![0.10, 0.8, 0.10],
+        ]),
+        // Lots, Some, Few
+        MarkovChain::three_state(STEALTH_PROPS, vec![
+            vec![0.5, 0.35, 0.15],
+            vec![0.3, 0.4, 0.3],
+            vec![0.15, 0.35, 0.5],
+        ]),
+        // Few, Some, Lots
+        MarkovChain::three_state(TRAPS, vec![
+            vec![0.4, 0.4, 0.2],
+            vec![0.25, 0.5, 0.25],
+            vec![0.2, 0.4, 0.4],
+        ]),
+    ]
+}
+
+/* what one chain rolled for the newest room */
+#[derive(Clone, Debug)]
+pub struct ChainState {
+    pub name: String,
+    pub state: u8,
+    /* what it rolled the room before, order 2 chains read it */
+    pub previous: u8,
+    /* name of the state, for the logs and the f3 readout */
+    pub label: String,
+}
+
 #[derive(Resource, Debug)]
-pub struct NextAttributeArray {
-    pub attributes: [u8; 5], 
+pub struct LastAttributeArray {
+    /* one per chain, in RoomConfig's order */
+    pub attributes: Vec<ChainState>,
+    /* party carnage (0-1) these were rolled with, for the debug readout */
+    pub carnage: f32,
 }
 
-impl NextAttributeArray {
-    // Constructor to initialize all values to 0 (default to "high")
+impl LastAttributeArray {
+    // nothing rolled yet, chains start out in their middle state
     pub fn new() -> Self {
-        Self { attributes: [0; 5] }
+        Self { attributes: Vec::new(), carnage: 0.5 }
+    }
+
+    // what a chain rolled last, None if it never has
+    pub fn get_attribute(&self, name: &str) -> Option<u8> {
+        self.find(name).map(|attribute| attribute.state)
     }
 
-    // Method to set a specific attribute
-    pub fn set_next_attribute(&mut self, index: usize, value: u8) {
-        if index < self.attributes.len() && value <= 3 {
-            self.attributes[index] = value;
-        } else {
-            println!("Invalid index or value!");
+    pub fn find(&self, name: &str) -> Option<&ChainState> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    /* steps every chain once. chains are matched up by name so a reloaded
+     * config can add, drop or reorder them. boss rooms skip the dice */
+    pub fn roll(&mut self, chains: &[MarkovChain], carnage_percent: f32, boss: bool, rng: &mut StdRng) {
+        let mut next = Vec::with_capacity(chains.len());
+        for chain in chains {
+            let (last, previous) = match self.find(&chain.name) {
+                Some(attribute) => (attribute.state, attribute.previous),
+                None => (chain.start_state(), chain.start_state()),
+            };
+            /* always roll, even for the boss, so the rng lines up either way */
+            let roll: f32 = rng.gen_range(0.0..1.0);
+            let state = if boss { chain.boss_state() } else { chain.next_state(last, previous, carnage_percent, roll) };
+            next.push(ChainState {
+                name: chain.name.clone(),
+                state: state,
+                previous: last,
+                label: String::from(chain.state_name(state)),
+            });
         }
+        self.attributes = next;
+        self.carnage = carnage_percent;
     }
 
-    // Method to get the value of a specific attribute
-    pub fn get_attribute(&self, index: usize) -> Option<u8> {
-        self.attributes.get(index).copied()
+    /* (chain, state) pairs for the readouts */
+    pub fn labels(&self) -> Vec<(String, String)> {
+        self.attributes.iter().map(|attribute| (attribute.name.clone(), attribute.label.clone())).collect()
     }
 
+    // print array
     pub fn print_array(&self) {
-        println!("LastAttributeArray: {:?}", self.attributes);
+        println!("LastAttributeArray: {:?}", self.labels());
     }
 }
 
+/* skews a chain can name. each takes a row and the party carnage (0-1)
+ * and hands back the bent row, add a line here to make a new one usable */
+pub type SkewFn = fn(&[f32], f32) -> Vec<f32>;
 
-//SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW 
-pub fn Skew_Row(input_matrix: Vec<Vec<f32>>, carnage_percent:f32, row_index:usize) -> Vec<f32> {
-    //skew towards carnage rooms 
-    let high_carnage_vec = vec![0.05,0.10,0.85];
-    //skew towards stealth rooms 
-    let low_carnage_vec = vec![0.85,0.10,0.05];
+pub const SKEWS: [(&str, SkewFn); 3] = [
+    ("carnage", Skew_Row),
+    ("stealth", skew_backwards),
+    ("flat", skew_flat),
+];
 
+pub fn skew_by_name(name: &str) -> Option<SkewFn> {
+    SKEWS.iter().find(|(skew, _)| *skew == name).map(|(_, skew)| *skew)
+}
+
+/* where a fully skewed row ends up, 0.85 on the end we lean toward,
+ * 0.05 on the other end and the rest spread over the middle.
+ * for three states thats the old [0.05, 0.10, 0.85] */
+fn lean_row(len: usize, toward_last: bool) -> Vec<f32> {
+    let mut row = match len {
+        0 => return Vec::new(),
+        1 => return vec![1.],
+        2 => vec![0.15, 0.85],
+        _ => {
+            let mut row = vec![0.10 / (len - 2) as f32; len];
+            row[0] = 0.05;
+            row[len - 1] = 0.85;
+            row
+        }
+    };
+    if !toward_last {
+        row.reverse();
+    }
+    row
+}
+
+//SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW SKEW
+/* high carnage pulls the row toward the last state, low toward the
+ * first, dead on 0.5 leaves it alone */
+pub fn Skew_Row(row: &[f32], carnage_percent: f32) -> Vec<f32> {
     //chooses which vec to skew or if to skew at all
     let skew_vec = if carnage_percent == 0.5 {
-        return input_matrix[row_index].clone(); 
+        return row.to_vec();
     } else if carnage_percent < 0.5 {
-        low_carnage_vec
+        //skew towards stealth rooms
+        lean_row(row.len(), false)
     } else {
-        high_carnage_vec
+        //skew towards carnage rooms
+        lean_row(row.len(), true)
     };
 
     //flag for which calculation to do
     let up_flag = if carnage_percent < 0.5 { 0 } else { 1 };
 
-    //initialize output matrix to return later
-    let mut skewed_out_vec = vec![0.; 3];
+    //initialize output row to return later
+    let mut skewed_out_vec = vec![0.; row.len()];
 
     if up_flag == 0{
-        for (j, &value) in input_matrix[row_index].iter().enumerate() {
+        for (j, &value) in row.iter().enumerate() {
             skewed_out_vec[j] = (1.-2.*(carnage_percent))*skew_vec[j]+(2.*carnage_percent)*value;
         }
-    }else{       
-        for (j, &value) in input_matrix[row_index].iter().enumerate(){
+    }else{
+        for (j, &value) in row.iter().enumerate(){
             skewed_out_vec[j] = (1.-2.*(carnage_percent-0.5))*value+(2.*(carnage_percent-0.5))*skew_vec[j];
         }
     }
     skewed_out_vec
 }
 
+/* same thing flipped, for attributes that should calm down as the party gets loud */
+pub fn skew_backwards(row: &[f32], carnage_percent: f32) -> Vec<f32> {
+    Skew_Row(row, 1. - carnage_percent)
+}
+
+/* carnage doesnt matter for this one */
+pub fn skew_flat(row: &[f32], _carnage_percent: f32) -> Vec<f32> {
+    row.to_vec()
+}
+
+/* walks the skewed row as a cdf. roll is 0-1, row is the chance
+ * of each state, returns the state */
+pub fn pick_state(skewed_row: &[f32], roll: f32) -> u8 {
+    let mut total = 0.;
+    for (state, chance) in skewed_row.iter().enumerate() {
+        total += chance;
+        if roll < total {
+            return state as u8;
+        }
+    }
+    /* float slop, rows dont always sum to exactly 1 */
+    (skewed_row.len() - 1) as u8
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 0.0001)
+    }
 
+    #[test]
+    fn default_chains_are_valid() {
+        for chain in default_chains() {
+            assert!(chain.validate().is_empty(), "{:?}", chain.validate());
+        }
+        let names: Vec<String> = default_chains().into_iter().map(|chain| chain.name).collect();
+        assert_eq!(names, BUILT_IN_CHAINS);
+    }
 
+    #[test]
+    fn validate_catches_rows_off_one() {
+        let chain = MarkovChain::three_state("test", vec![
+            vec![0.2, 0.3, 0.5],
+            vec![0.5, 0.5, 0.5],
+            vec![0.4, 0.5, 0.1],
+        ]);
+        let problems = chain.validate();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("row 1 adds up to"));
+    }
 
+    #[test]
+    fn validate_wants_states_squared_rows_at_order_2() {
+        let mut chain = MarkovChain::three_state("test", vec![vec![0.25, 0.5, 0.25]; 3]);
+        chain.order = 2;
+        assert!(chain.validate()[0].contains("needs 9 rows"));
 
+        chain.rows = vec![vec![0.25, 0.5, 0.25]; 9];
+        assert!(chain.validate().is_empty());
+        /* row = older * states + last */
+        chain.rows[2 * 3 + 1] = vec![1., 0., 0.];
+        assert_eq!(chain.row(1, 2), &vec![1., 0., 0.]);
+    }
 
+    #[test]
+    fn pick_state_walks_the_row() {
+        let row = [0.2, 0.3, 0.5];
+        assert_eq!(pick_state(&row, 0.), 0);
+        assert_eq!(pick_state(&row, 0.19), 0);
+        assert_eq!(pick_state(&row, 0.2), 1);
+        assert_eq!(pick_state(&row, 0.49), 1);
+        assert_eq!(pick_state(&row, 0.99), 2);
+        /* a row a hair short of 1 still lands somewhere */
+        assert_eq!(pick_state(&[0.2, 0.3, 0.49], 0.995), 2);
+    }
 
+    #[test]
+    fn skew_row_leans_with_carnage() {
+        let row = [0.25, 0.5, 0.25];
+        assert!(close(&Skew_Row(&row, 0.5), &row));
+        assert!(close(&Skew_Row(&row, 1.), &[0.05, 0.10, 0.85]));
+        assert!(close(&Skew_Row(&row, 0.), &[0.85, 0.10, 0.05]));
+        /* halfway up is halfway between */
+        assert!(close(&Skew_Row(&row, 0.75), &[0.15, 0.3, 0.55]));
+        for carnage in [0., 0.1, 0.3, 0.5, 0.8, 1.] {
+            let total: f32 = Skew_Row(&row, carnage).iter().sum();
+            assert!((total - 1.).abs() < 0.0001);
+        }
+    }
+}
@@ -6,6 +6,8 @@ use std::io;
 use crate::enemies::{EnemyId, EnemyMovement};
 use crate::cuscuta_resources::Health;
 use crate::ui::CarnageBar;
+use crate::room_gen::{DoorType, RoomType};
+use crate::biomes::Biome;
 
 
 /* Packets queues are used to hold packets when creted, before
@@ -95,6 +97,12 @@ impl Sequence{
     /* changes index value */
     pub fn new_index(&mut self, index:usize){
         self.index = index;
+        self.grow_to(index);
+    }
+
+    /* makes sure there's a clock slot for index. ids are capped
+     * by PlayerCount.max, so this stays small */
+    pub fn grow_to(&mut self, index:usize){
         while index+1 > self.nums.len(){
             self.nums.push(0);
         }
@@ -170,9 +178,25 @@ pub struct PlayerSendable{
 pub struct MapS2C{
     pub head: Header,
     pub matrix: Vec<Vec<u8>>,
+    /* autotiled piece for every spot in matrix, a WallPiece where the
+     * code is a wall and the FloorPiece under it everywhere else */
+    pub tiles: Vec<Vec<u8>>,
     pub size: (f32, f32),
     pub max: (f32, f32),
     pub z: f32,
+    /* (chain, state) per markov chain + the carnage they rolled at, for the f3 readout */
+    pub states: Vec<(String, String)>,
+    pub carnage: f32,
+    /* how deep the party is (0 is the first floor), and whether this
+     * floor's boss drops the chalice instead of stairs */
+    pub floor: u32,
+    pub last_floor: bool,
+    /* what room type is past each door (through the hallway), for the
+     * door markers, and whether a challenge has them shut */
+    pub doors: Vec<(DoorType, RoomType)>,
+    pub locked: bool,
+    /* the floor's biome, picks the client's tiles and tint */
+    pub biome: Biome,
 }
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct EnemyS2C{
@@ -188,6 +212,14 @@ pub struct IdPacket{
     pub head: Header
 }
 
+/* answer to an IdPacket when the party already has max players.
+ * no id and no map are coming after it */
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct FullPacket{
+    pub head: Header,
+    pub max: u8,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct KillEnemyPacket{
     pub enemy_id: EnemyId,
@@ -234,9 +266,61 @@ pub struct DecreaseEnemyHealthPacket{
 }
 
 
+/* client smashed a pot. pots get smashed client side so this is the
+ * only way the server hears about it (for carnage, and so it stays
+ * smashed next time the party comes through) */
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PotPacket{
+    pub head: Header,
+    /* where the pot was, room centered on 0,0 */
+    pub pos: Vec2,
+}
+
+/* client picked up a potion, same deal as pots */
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PotionPacket{
+    pub head: Header,
+    pub pos: Vec2,
+}
+
+/* round trip probe. server just bounces it straight back to
+ * whoever sent it, so the sender can diff the timestamp */
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PingPacket{
+    pub head: Header,
+    pub sent: Timestamp,
+}
+
+/* where the party is headed. door None means the vote fell
+ * through (or we already went), clients clear their countdown */
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DoorVotePacket{
+    pub head: Header,
+    pub door: Option<DoorType>,
+    /* seconds left before we go, stragglers or not */
+    pub remaining: f32,
+    /* NetworkIds standing on the chosen door */
+    pub voters: Vec<u8>,
+}
+
+/* a trap got you. the server decides when traps go off but health is
+ * the client's to keep, so the client takes the damage and runs the
+ * statuses itself (see traps::Afflictions) */
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct HazardPacket{
+    pub head: Header,
+    pub damage: f32,
+    /* seconds of each, 0 for none */
+    pub slow: f32,
+    pub poison: f32,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CarnagePacket{
-    pub carnage: CarnageBar,
+    /* (NetworkId, bar) for every player */
+    pub bars: Vec<(u8, CarnageBar)>,
+    /* what room gen is working off of */
+    pub party: CarnageBar,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -246,6 +330,21 @@ pub enum ClientPacket{
     KillEnemyPacket(KillEnemyPacket),
     DecreaseEnemyHealthPacket(DecreaseEnemyHealthPacket),
     MonkeyPacket(MonkeyPacket),
+    PingPacket(PingPacket),
+    PotPacket(PotPacket),
+    PotionPacket(PotionPacket),
+}
+
+/* answer to a PotionPacket in a shop. the server checks the potion is
+ * still on the shelf and that they can pay, and if so takes the price
+ * off its copy of their health. health is that copy after the sale,
+ * the client takes it as is so both ends agree on what was paid */
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ShopPacket{
+    pub head: Header,
+    pub pos: Vec2,
+    pub bought: bool,
+    pub health: f32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -258,6 +357,11 @@ pub enum ServerPacket{
     MonkeyPacket(MonkeyPacket),
     DespawnAllPacket(DespawnAllPacket),
     CarnagePacket(CarnagePacket),
+    PingPacket(PingPacket),
+    DoorVotePacket(DoorVotePacket),
+    HazardPacket(HazardPacket),
+    ShopPacket(ShopPacket),
+    FullPacket(FullPacket),
 }
 
 pub unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] { // will slice anything into u8 array 
@@ -5,7 +5,10 @@ use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::enemies::{BossKillEvent, EnemyId, EnemyKind, EnemyToKill};
-use crate::network::{ClientPacket, DecreaseEnemyHealthPacket, Header, KillEnemyPacket, MonkeyPacket, Sequence, ServerPacket, UDP};
+use crate::network::{ClientPacket, DecreaseEnemyHealthPacket, Header, KillEnemyPacket, MonkeyPacket, PotPacket, PotionPacket, Sequence, ServerPacket, ShopPacket, UDP};
+use crate::room_state::same_spot;
+use crate::special_rooms::{ShopCounter, ShopPrice, SHOP_ANSWER_TIMEOUT};
+use crate::traps::Afflictions;
 
 use crate::{
     collision::{self, *},
@@ -61,7 +64,7 @@ pub struct ServerCymbalMonkey {
 
 #[derive(Component, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
 pub struct NetworkId {
-    pub id: u8, // we will have at most 2 players so no more than a u8 is needed
+    pub id: u8, // capped by ServerConfig.max_players (254 tops), a u8 does it
     pub addr: SocketAddr,
 }
 impl NetworkId {
@@ -214,7 +217,8 @@ pub struct ServerPlayerBundle {
     pub sprinting: Sprint,
     pub attacking: Attack,
     pub player: Player,
-    pub track: Trackable, //pub inputs: InputQueue,
+    pub track: Trackable,
+    pub carnage: CarnageBar, //pub inputs: InputQueue,
                           //pub time: Timestamp,
 }
 
@@ -483,35 +487,91 @@ pub fn tick_timer(
 pub fn player_interact(
     mut commands: Commands,
     mut player: Query<
-        (&mut Transform, &mut Velocity, &NetworkId, &mut ItemStatus),
+        (&mut Transform, &mut Velocity, &NetworkId, &mut ItemStatus, &mut Health),
         (With<Player>, Without<Background>),
     >,
     input: Res<ButtonInput<KeyCode>>,
     client_id: Res<ClientId>,
     mut pot_q: Query<(& Transform, &mut Pot, &mut TextureAtlas), (With<Pot>, Without<Player>)>,
-    potion_query: Query<(Entity, &Transform), (With<Potion>, Without<Player>, Without<Pot>)>,
+    potion_query: Query<(Entity, &Transform, Option<&ShopPrice>), (With<Potion>, Without<Player>, Without<Pot>)>,
+    seq: Res<Sequence>,
+    udp: Res<UDP>,
+    mut shop: ResMut<ShopCounter>,
+    time: Res<Time>,
 ) {
-    for (player_transform, mut _player_velocity, id, mut potion_status) in player.iter_mut() {
+    for (player_transform, mut _player_velocity, id, mut potion_status, mut health) in player.iter_mut() {
         if id.id == client_id.id {
+            /* the server said yes (it's taken the price off its copy of our
+             * health already) or no to a shop potion we asked for */
+            let answers: Vec<ShopPacket> = shop.answers.drain(..).collect();
+            for answer in answers {
+                shop.waiting = false;
+                if !answer.bought {
+                    println!("shop wouldnt sell us the potion at {:?}", answer.pos);
+                    continue;
+                }
+                for (potion_entity, potion_transform, _) in potion_query.iter() {
+                    if same_spot(potion_transform.translation, answer.pos) {
+                        commands.entity(potion_entity).despawn();
+                    }
+                }
+                /* the server's number, so our next PlayerPacket doesnt undo the sale */
+                health.current = answer.health;
+                potion_status.has_potion = true;
+            }
+            /* answer got lost, drop the ask so walking back on tries again */
+            if shop.waiting && time.elapsed_seconds() - shop.asked_at > SHOP_ANSWER_TIMEOUT {
+                println!("no answer from the shop, asking again");
+                shop.waiting = false;
+                shop.asked.pop();
+            }
+
             // player collider
             let player_collider =
                 collision::Aabb::new(player_transform.translation, Vec2::splat(TILE_SIZE as f32));
             // loop through potions in room
-            for (potion_entity, potion_transform) in potion_query.iter() {
+            for (potion_entity, potion_transform, price) in potion_query.iter() {
                 let potion_collider =
                     Aabb::new(potion_transform.translation, Vec2::splat(TILE_SIZE as f32));
 
                 // if player intersects
                 if player_collider.intersects(&potion_collider) && !potion_status.has_potion {
-                    // check here if player is already carrying potion
-                    potion_status.has_potion = true; // Player now has a potion
-                    info!(
-                        "Player at {:?} picked up a potion at {:?}!",
-                        player_transform.translation, potion_transform.translation
-                    );
-
-                    // despawn potion
-                    commands.entity(potion_entity).despawn();
+                    /* shop potions are paid in blood, and you cant pay with your
+                     * last drop. the server does the charging, we just ask
+                     * (once) and see what it says */
+                    if let Some(ShopPrice(cost)) = price {
+                        let spot = potion_transform.translation.truncate();
+                        if health.current <= *cost || shop.waiting || shop.asked.contains(&spot) {
+                            continue;
+                        }
+                        shop.asked.push(spot);
+                        shop.waiting = true;
+                        shop.asked_at = time.elapsed_seconds();
+                    } else {
+                        // check here if player is already carrying potion
+                        potion_status.has_potion = true; // Player now has a potion
+                        info!(
+                            "Player at {:?} picked up a potion at {:?}!",
+                            player_transform.translation, potion_transform.translation
+                        );
+
+                        // despawn potion
+                        commands.entity(potion_entity).despawn();
+                    }
+
+                    /* so the server forgets it too, or it comes back next visit.
+                     * for shop potions this is the asking */
+                    let to_send = ClientPacket::PotionPacket(PotionPacket {
+                        head: Header {
+                            network_id: id.id,
+                            sequence: seq.clone(),
+                        },
+                        pos: potion_transform.translation.truncate(),
+                    });
+                    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+                    to_send.serialize(&mut serializer).unwrap();
+                    let packet: &[u8] = serializer.view();
+                    udp.socket.send_to(&packet, SERVER_ADR).unwrap();
                 }
             }
 
@@ -536,6 +596,19 @@ pub fn player_interact(
                     if pot.touch == 1 {
                         pot_atlas.index = pot_atlas.index + 1;
                         potion_status.has_monkey = true;
+
+                        /* server scores pots for carnage */
+                        let to_send = ClientPacket::PotPacket(PotPacket {
+                            head: Header {
+                                network_id: id.id,
+                                sequence: seq.clone(),
+                            },
+                            pos: pot_transform.translation.truncate(),
+                        });
+                        let mut serializer = flexbuffers::FlexbufferSerializer::new();
+                        to_send.serialize(&mut serializer).unwrap();
+                        let packet: &[u8] = serializer.view();
+                        udp.socket.send_to(&packet, SERVER_ADR).unwrap();
                     }
                 
                 }
@@ -885,7 +958,7 @@ pub fn move_player(
     time: Res<Time>,
     input: Res<ButtonInput<KeyCode>>,
     mut player_query: Query<
-        (&mut Transform, &mut Velocity, &NetworkId, &mut Health),
+        (&mut Transform, &mut Velocity, &NetworkId, &mut Health, &mut Crouch),
         (With<Player>, Without<Background>, Without<Door>),
     >,
     mut enemies: Query<&mut Transform, (With<Enemy>, Without<Player>, Without<Door>)>,
@@ -902,6 +975,7 @@ pub fn move_player(
         ),
     >,
     mut collision_state: ResMut<CollisionState>,
+    afflictions: Res<Afflictions>,
 ) {
     
     let mut hit_door = false;
@@ -909,7 +983,7 @@ pub fn move_player(
     let mut door_type: Option<DoorType> = Option::None;
 
     // Player movement
-    for (mut pt, mut pv, id, health) in player_query.iter_mut() {
+    for (mut pt, mut pv, id, health, mut crouch) in player_query.iter_mut() {
         if id.id != client_id.id {
             continue;
         }
@@ -942,14 +1016,16 @@ pub fn move_player(
             1.0
         };
 
-        let crouch_multiplier = if input.pressed(KeyCode::KeyC) {
+        // crouch rides along in PlayerSendable, enemies care (stealth.rs)
+        crouch.crouching = input.pressed(KeyCode::KeyC);
+        let crouch_multiplier = if crouch.crouching {
             CROUCH_MULTIPLIER
         } else {
             1.0
         };
 
         // set new max speed
-        let max_speed = PLAYER_SPEED * speed_multiplier * crouch_multiplier;
+        let max_speed = PLAYER_SPEED * speed_multiplier * crouch_multiplier * afflictions.speed_multiplier();
 
         /* check if rolling */
         /*let roll = roll_query.as_mut();
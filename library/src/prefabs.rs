@@ -0,0 +1,340 @@
+use std::fs;
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::reachability::{door_tile, key_tiles, keys_connected, tile_to_pixel};
+use crate::room_gen::{solid_runs, DoorType, InnerWall, BOSS_ROOM_MIN_SIZE, ROOM_MIN_SIZE};
+
+/* PREFABS. hand drawn rooms and bits of rooms that room gen mixes in with
+ * the random ones. a prefab is rows of characters, top row first:
+ *   # wall       . floor      e enemy spawn     p potion     o pot
+ *   D door socket (outer wall of whole rooms only, where a door may go)
+ *   ~ leave alone (chunks only, whatever the room had stays)
+ * walls turn into InnerWall runs so they draw, collide and regen like
+ * any other inner wall */
+
+/* where the server looks if --prefabs isnt given.
+ * relative to wherever the server was launched from */
+pub const PREFABS_PATH: &str = "prefabs.ron";
+// chunks get stamped at least this many tiles off the outer walls
+pub const CHUNK_EDGE_MARGIN: usize = 3;
+// and can be at most this big each way
+pub const CHUNK_MAX_SIZE: usize = 16;
+
+/* how room gen builds a room. server picks with --room-gen */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomGenMode {
+    /* random inner walls only, how it always was */
+    Procedural,
+    /* some rooms are whole prefabs (template_chance), the rest are random
+     * with a few chunks stamped in */
+    Mixed,
+    /* whole prefab whenever one fits, chunks everywhere else */
+    Prefab,
+}
+
+impl RoomGenMode {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "procedural" => Some(RoomGenMode::Procedural),
+            "mixed" => Some(RoomGenMode::Mixed),
+            "prefab" => Some(RoomGenMode::Prefab),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrefabKind {
+    /* a whole room, outer wall and door sockets included */
+    Room,
+    /* a bit of wall/markers stamped into a random room */
+    Chunk,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Prefab {
+    pub name: String,
+    pub kind: PrefabKind,
+    /* markov states it fits: stealth, neutral, carnage, boss. whole
+     * rooms match on the room_size chain, chunks on inner_walls */
+    pub tags: Vec<String>,
+    pub tiles: Vec<String>,
+}
+
+/* where a prefab wants things spawned, pixels with the room centered on 0,0 */
+#[derive(Clone, Debug, Default)]
+pub struct RoomMarkers {
+    pub enemies: Vec<Vec2>,
+    pub potions: Vec<Vec2>,
+    pub pots: Vec<Vec2>,
+}
+
+impl RoomMarkers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Prefab {
+    pub fn width(&self) -> usize {
+        self.tiles.first().map(|row| row.chars().count()).unwrap_or(0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /* character at x, y counting from the bottom left like the room grids */
+    pub fn tile(&self, x: usize, y: usize) -> char {
+        self.tiles[self.height() - 1 - y].chars().nth(x).unwrap_or('~')
+    }
+
+    /* the border tile a door on that side sits in, same spot generate_doors uses */
+    fn socket_tile(&self, door_type: DoorType) -> (usize, usize) {
+        door_tile(self.width(), self.height(), door_type)
+    }
+
+    /* sides this room can take a door on */
+    pub fn sockets(&self) -> Vec<DoorType> {
+        DoorType::ALL.into_iter().filter(|door_type| {
+            let (x, y) = self.socket_tile(*door_type);
+            self.tile(x, y) == 'D'
+        }).collect()
+    }
+
+    pub fn tagged(&self, state: &str) -> bool {
+        self.tags.iter().any(|tag| tag == state)
+    }
+
+    /* inner wall runs for the walls, stamped with the bottom left at x, y.
+     * whole rooms leave their outer wall to generate_walls_and_floors */
+    pub fn walls(&self, x: usize, y: usize) -> Vec<InnerWall> {
+        let (x0, y0, x1, y1) = match self.kind {
+            PrefabKind::Room => (1, 1, self.width() - 1, self.height() - 1),
+            PrefabKind::Chunk => (0, 0, self.width(), self.height()),
+        };
+        solid_runs(x0, y0, x1, y1, |tile_x, tile_y| self.tile(tile_x, tile_y) == '#')
+            .into_iter()
+            .map(|mut wall| {
+                wall.start_pos.x += x;
+                wall.start_pos.y += y;
+                wall
+            })
+            .collect()
+    }
+
+    /* tiles with a spawn marker on them, stamped with the bottom left at x, y */
+    pub fn marker_tiles(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let mut tiles = Vec::new();
+        for tile_x in 0..self.width() {
+            for tile_y in 0..self.height() {
+                if matches!(self.tile(tile_x, tile_y), 'e' | 'p' | 'o') {
+                    tiles.push((x + tile_x, y + tile_y));
+                }
+            }
+        }
+        tiles
+    }
+
+    /* spawn markers in pixels, for a room `grid` big with the prefab's bottom left at x, y */
+    pub fn markers(&self, grid: &Vec<Vec<u32>>, x: usize, y: usize) -> RoomMarkers {
+        let mut markers = RoomMarkers::new();
+        for (tile_x, tile_y) in self.marker_tiles(x, y) {
+            let spot = tile_to_pixel(grid, (tile_x, tile_y));
+            match self.tile(tile_x - x, tile_y - y) {
+                'e' => markers.enemies.push(spot),
+                'p' => markers.potions.push(spot),
+                _ => markers.pots.push(spot),
+            }
+        }
+        markers
+    }
+
+    /* everything wrong with this prefab, in plain words */
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let name = &self.name;
+        let (width, height) = (self.width(), self.height());
+        if self.tiles.iter().any(|row| row.chars().count() != width) {
+            problems.push(format!("prefab {} rows arent all the same length", name));
+            return problems;
+        }
+        if let Some(bad) = self.tiles.iter().flat_map(|row| row.chars()).find(|c| !"#.epoD~".contains(*c)) {
+            problems.push(format!("prefab {} has a {:?}, only # . e p o D ~ mean anything", name, bad));
+        }
+        for tag in self.tags.iter() {
+            if !["stealth", "neutral", "carnage", "boss"].contains(&tag.as_str()) {
+                problems.push(format!("prefab {} tag {} isnt stealth, neutral, carnage or boss", name, tag));
+            }
+        }
+
+        match self.kind {
+            PrefabKind::Chunk => {
+                if width == 0 || height == 0 || width > CHUNK_MAX_SIZE || height > CHUNK_MAX_SIZE {
+                    problems.push(format!("prefab {} chunk has to be 1-{} tiles each way, is {}x{}", name, CHUNK_MAX_SIZE, width, height));
+                }
+                if self.tiles.iter().any(|row| row.contains('D')) {
+                    problems.push(format!("prefab {} chunk cant have door sockets", name));
+                }
+            }
+            PrefabKind::Room => {
+                if width < ROOM_MIN_SIZE || height < ROOM_MIN_SIZE {
+                    problems.push(format!("prefab {} room has to be at least {} tiles each way, is {}x{}", name, ROOM_MIN_SIZE, width, height));
+                    return problems;
+                }
+                let sockets: Vec<(usize, usize)> = DoorType::ALL.iter().map(|door_type| self.socket_tile(*door_type)).collect();
+                for x in 0..width {
+                    for y in 0..height {
+                        let c = self.tile(x, y);
+                        let border = x == 0 || y == 0 || x + 1 == width || y + 1 == height;
+                        if border && c != '#' && !(c == 'D' && sockets.contains(&(x, y))) {
+                            problems.push(format!("prefab {} outer wall has a {:?} at {}, {} (only # and D in the middle of a side)", name, c, x, y));
+                        }
+                        if !border && (c == 'D' || c == '~') {
+                            problems.push(format!("prefab {} room has a {:?} inside at {}, {}", name, c, x, y));
+                        }
+                    }
+                }
+                if self.sockets().is_empty() {
+                    problems.push(format!("prefab {} room has no door sockets", name));
+                }
+                if self.tagged("boss") && (width < BOSS_ROOM_MIN_SIZE || height < BOSS_ROOM_MIN_SIZE) {
+                    problems.push(format!("prefab {} is tagged boss so has to be at least {} tiles each way, is {}x{}", name, BOSS_ROOM_MIN_SIZE, width, height));
+                }
+                /* middle, every socket's entry spot and every marker have to connect */
+                let mut grid = vec![vec![0; height]; width];
+                for x in 0..width {
+                    for y in 0..height {
+                        if matches!(self.tile(x, y), '#' | 'D') {
+                            grid[x][y] = 1;
+                        }
+                    }
+                }
+                let mut keys = key_tiles(&grid, &self.sockets());
+                keys.extend(self.marker_tiles(0, 0));
+                if !keys_connected(&grid, &keys) {
+                    problems.push(format!("prefab {} room walls off its middle, a door or a marker", name));
+                }
+            }
+        }
+        problems
+    }
+}
+
+/* every prefab plus how often room gen reaches for them */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrefabLibrary {
+    /* chance a room is a whole prefab in mixed mode */
+    pub template_chance: f32,
+    /* chunks stamped into each random room */
+    pub chunks_per_room: (usize, usize),
+    pub prefabs: Vec<Prefab>,
+    /* comes off the command line, not the file */
+    #[serde(skip, default = "default_mode")]
+    pub mode: RoomGenMode,
+}
+
+fn default_mode() -> RoomGenMode {
+    RoomGenMode::Mixed
+}
+
+impl PrefabLibrary {
+    /* nothing to mix in, rooms come out fully random */
+    pub fn new() -> Self {
+        Self {
+            template_chance: 0.,
+            chunks_per_room: (0, 0),
+            prefabs: Vec::new(),
+            mode: RoomGenMode::Procedural,
+        }
+    }
+
+    /* bad or missing file isnt worth killing the server over,
+     * yell and go without prefabs */
+    pub fn load(path: &str, mode: RoomGenMode) -> Self {
+        if mode == RoomGenMode::Procedural {
+            return Self::new();
+        }
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("couldnt read {}: {}, no prefabs", path, e);
+                return Self::new();
+            }
+        };
+        let mut library = match ron::from_str::<PrefabLibrary>(&text) {
+            Ok(library) => library,
+            Err(e) => {
+                println!("couldnt parse {}: {}, no prefabs", path, e);
+                return Self::new();
+            }
+        };
+        let problems = library.validate();
+        if !problems.is_empty() {
+            for problem in problems {
+                println!("{}: {}", path, problem);
+            }
+            println!("no prefabs");
+            return Self::new();
+        }
+        library.mode = mode;
+        println!("loaded {} prefabs from {} ({:?})", library.prefabs.len(), path, mode);
+        library
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !(0. ..=1.).contains(&self.template_chance) {
+            problems.push(format!("template_chance has to be 0-1, is {}", self.template_chance));
+        }
+        if self.chunks_per_room.0 > self.chunks_per_room.1 {
+            problems.push(format!("chunks_per_room is backwards: {:?}", self.chunks_per_room));
+        }
+        for prefab in self.prefabs.iter() {
+            problems.extend(prefab.validate());
+        }
+        problems
+    }
+
+    /* whole room for this state that has a socket for every door and
+     * fits in max_width x max_height, if the mode and the dice say so */
+    pub fn pick_room(&self, state: &str, doors: &[DoorType], max_width: usize, max_height: usize, rng: &mut StdRng) -> Option<&Prefab> {
+        /* always roll so the rng lines up whatever the mode */
+        let roll: f32 = rng.gen_range(0.0..1.0);
+        let wanted = match self.mode {
+            RoomGenMode::Procedural => false,
+            RoomGenMode::Mixed => roll < self.template_chance,
+            RoomGenMode::Prefab => true,
+        };
+        if !wanted {
+            return None;
+        }
+        let fits: Vec<&Prefab> = self.prefabs.iter()
+            .filter(|prefab| prefab.kind == PrefabKind::Room && prefab.tagged(state))
+            .filter(|prefab| prefab.width() <= max_width && prefab.height() <= max_height)
+            .filter(|prefab| {
+                let sockets = prefab.sockets();
+                doors.iter().all(|door_type| sockets.contains(door_type))
+            })
+            .collect();
+        fits.choose(rng).copied()
+    }
+
+    /* chunks to stamp into a random room of this state */
+    pub fn pick_chunks(&self, state: &str, rng: &mut StdRng) -> Vec<&Prefab> {
+        if self.mode == RoomGenMode::Procedural {
+            return Vec::new();
+        }
+        let chunks: Vec<&Prefab> = self.prefabs.iter()
+            .filter(|prefab| prefab.kind == PrefabKind::Chunk && prefab.tagged(state))
+            .collect();
+        let count = rng.gen_range(self.chunks_per_room.0..=self.chunks_per_room.1);
+        if chunks.is_empty() {
+            return Vec::new();
+        }
+        (0..count).filter_map(|_| chunks.choose(rng).copied()).collect()
+    }
+}
@@ -0,0 +1,266 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng};
+
+use crate::cuscuta_resources::TILE_SIZE;
+use crate::room_gen::{DoorType, InnerWall};
+
+/* REACHABILITY. every room has a grid of tile codes (RoomManager::grids,
+ * indexed [x][y] from the bottom left, outer walls included). inner walls
+ * are thrown down at random, so before one goes in we make sure it doesnt
+ * cut off a door, the spot players come in at or the middle of the room
+ * (where everyone spawns), and anything we spawn goes on floor you can
+ * actually walk to */
+
+// rerolls for an inner wall that would seal something off before we give up on it
+pub const WALL_PLACEMENT_TRIES: usize = 8;
+// items stay this many tiles off the outer walls
+pub const ITEM_EDGE_MARGIN: usize = 3;
+// same for enemies (used to be 128px)
+pub const ENEMY_EDGE_MARGIN: usize = 4;
+
+/* walls (inner ones too) are 1, doors are 2 and sit in the outer wall */
+pub fn is_blocking(code: u32) -> bool {
+    code == 1 || code == 2
+}
+
+/* pixel spot (room centered on 0,0) to the tile its in */
+pub fn pixel_to_tile(grid: &Vec<Vec<u32>>, spot: Vec2) -> (usize, usize) {
+    let max_x = grid.len() as f32 * TILE_SIZE as f32 / 2.;
+    let max_y = grid[0].len() as f32 * TILE_SIZE as f32 / 2.;
+    let x = ((spot.x + max_x) / TILE_SIZE as f32).floor().clamp(0., (grid.len() - 1) as f32);
+    let y = ((spot.y + max_y) / TILE_SIZE as f32).floor().clamp(0., (grid[0].len() - 1) as f32);
+    (x as usize, y as usize)
+}
+
+/* middle of a tile in pixels */
+pub fn tile_to_pixel(grid: &Vec<Vec<u32>>, tile: (usize, usize)) -> Vec2 {
+    Vec2::new(
+        tile.0 as f32 * TILE_SIZE as f32 - grid.len() as f32 * TILE_SIZE as f32 / 2. + TILE_SIZE as f32 / 2.,
+        tile.1 as f32 * TILE_SIZE as f32 - grid[0].len() as f32 * TILE_SIZE as f32 / 2. + TILE_SIZE as f32 / 2.,
+    )
+}
+
+/* spot `tiles_in` tiles inside a door, doors sit half a tile off center
+ * on their wall. 4 tiles in is where transition_map drops the players */
+pub fn inside_door(door_type: DoorType, max_x: f32, max_y: f32, tiles_in: f32) -> Vec2 {
+    let inset = TILE_SIZE as f32 * tiles_in;
+    match door_type {
+        DoorType::Left => Vec2::new(-max_x + inset, TILE_SIZE as f32 / 2.),
+        DoorType::Right => Vec2::new(max_x - inset, TILE_SIZE as f32 / 2.),
+        DoorType::Bottom => Vec2::new(TILE_SIZE as f32 / 2., -max_y + inset),
+        DoorType::Top => Vec2::new(TILE_SIZE as f32 / 2., max_y - inset),
+    }
+}
+
+/* outer wall tile a door sits in, for a room width x height tiles */
+pub fn door_tile(width: usize, height: usize, door_type: DoorType) -> (usize, usize) {
+    let grid = vec![vec![0; height]; width];
+    let max_x = width as f32 * TILE_SIZE as f32 / 2.;
+    let max_y = height as f32 * TILE_SIZE as f32 / 2.;
+    pixel_to_tile(&grid, inside_door(door_type, max_x, max_y, 0.5))
+}
+
+/* the tiles that all have to stay connected: room center, then the tile
+ * just inside each door and the entry spot in front of it */
+pub fn key_tiles(grid: &Vec<Vec<u32>>, doors: &[DoorType]) -> Vec<(usize, usize)> {
+    let max_x = grid.len() as f32 * TILE_SIZE as f32 / 2.;
+    let max_y = grid[0].len() as f32 * TILE_SIZE as f32 / 2.;
+    let mut keys = vec![pixel_to_tile(grid, Vec2::ZERO)];
+    for door_type in doors {
+        keys.push(pixel_to_tile(grid, inside_door(*door_type, max_x, max_y, 1.5)));
+        keys.push(pixel_to_tile(grid, inside_door(*door_type, max_x, max_y, 4.)));
+    }
+    keys
+}
+
+/* 4-way flood fill over everything thats not blocking */
+pub fn flood_fill(grid: &Vec<Vec<u32>>, from: (usize, usize)) -> Vec<Vec<bool>> {
+    let mut seen = vec![vec![false; grid[0].len()]; grid.len()];
+    if is_blocking(grid[from.0][from.1]) {
+        return seen;
+    }
+    seen[from.0][from.1] = true;
+    let mut stack = vec![from];
+    while let Some((x, y)) = stack.pop() {
+        let next = [
+            (x + 1, y),
+            (x.wrapping_sub(1), y),
+            (x, y + 1),
+            (x, y.wrapping_sub(1)),
+        ];
+        for (nx, ny) in next {
+            if nx < grid.len() && ny < grid[0].len() && !seen[nx][ny] && !is_blocking(grid[nx][ny]) {
+                seen[nx][ny] = true;
+                stack.push((nx, ny));
+            }
+        }
+    }
+    seen
+}
+
+/* can you walk between every key tile */
+pub fn keys_connected(grid: &Vec<Vec<u32>>, keys: &[(usize, usize)]) -> bool {
+    let Some(first) = keys.first() else {
+        return true;
+    };
+    let seen = flood_fill(grid, *first);
+    keys.iter().all(|(x, y)| seen[*x][*y])
+}
+
+/* tiles an inner wall covers, same walk draw_inner_wall does
+ * (start_pos is 1 based and the length has 2 extra on it) */
+pub fn wall_tiles(wall: &InnerWall) -> Vec<(usize, usize)> {
+    let x = wall.start_pos.x as i32 - 1;
+    let y = wall.start_pos.y as i32 - 1;
+    let (dir_x, dir_y) = wall.length_direction_vector;
+    let mut tiles = Vec::new();
+    if dir_y == 1 {
+        let run = if dir_x > 0 { x..=x + dir_x - 3 } else { x + dir_x + 3..=x };
+        tiles.extend(run.map(|tile_x| (tile_x, y)));
+    }
+    if dir_x == 1 {
+        let run = if dir_y > 0 { y..=y + dir_y - 3 } else { y + dir_y + 3..=y };
+        tiles.extend(run.map(|tile_y| (x, tile_y)));
+    }
+    tiles.into_iter()
+        .filter(|(tile_x, tile_y)| *tile_x >= 0 && *tile_y >= 0)
+        .map(|(tile_x, tile_y)| (tile_x as usize, tile_y as usize))
+        .collect()
+}
+
+/* would the room still be fully walkable between its key tiles with this wall in */
+pub fn wall_keeps_room_open(grid: &Vec<Vec<u32>>, wall: &InnerWall, keys: &[(usize, usize)]) -> bool {
+    walls_keep_room_open(grid, std::slice::from_ref(wall), keys)
+}
+
+/* same for a bunch of walls going in together, like a prefab chunk */
+pub fn walls_keep_room_open(grid: &Vec<Vec<u32>>, walls: &[InnerWall], keys: &[(usize, usize)]) -> bool {
+    let mut trial = grid.clone();
+    for (x, y) in walls.iter().flat_map(wall_tiles) {
+        if x < trial.len() && y < trial[0].len() {
+            trial[x][y] = 1;
+        }
+    }
+    keys_connected(&trial, keys)
+}
+
+/* floor tiles reachable from the room center, at least `margin` off the
+ * outer walls and not touching any wall so nothing spawns half inside one */
+pub fn reachable_spots(grid: &Vec<Vec<u32>>, keys: &[(usize, usize)], margin: usize) -> Vec<(usize, usize)> {
+    let Some(first) = keys.first() else {
+        return Vec::new();
+    };
+    let seen = flood_fill(grid, *first);
+    let (width, height) = (grid.len(), grid[0].len());
+    let mut spots = Vec::new();
+    for x in margin.max(1)..width.saturating_sub(margin.max(1)) {
+        for y in margin.max(1)..height.saturating_sub(margin.max(1)) {
+            if !seen[x][y] || grid[x][y] != 0 {
+                continue;
+            }
+            let clear = (x - 1..=x + 1).all(|nx| (y - 1..=y + 1).all(|ny| !is_blocking(grid[nx][ny])));
+            if clear {
+                spots.push((x, y));
+            }
+        }
+    }
+    spots
+}
+
+/* takes a random spot out of the list so two things dont stack up */
+pub fn take_spot(grid: &Vec<Vec<u32>>, spots: &mut Vec<(usize, usize)>, rng: &mut StdRng) -> Option<Vec2> {
+    if spots.is_empty() {
+        return None;
+    }
+    let tile = spots.swap_remove(rng.gen_range(0..spots.len()));
+    Some(tile_to_pixel(grid, tile))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use crate::room_gen::InnerWallStartPos;
+
+    /* width x height tiles, outer walls all round, floor inside */
+    fn room(width: usize, height: usize) -> Vec<Vec<u32>> {
+        let mut grid = vec![vec![0; height]; width];
+        for x in 0..width {
+            for y in 0..height {
+                if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                    grid[x][y] = 1;
+                }
+            }
+        }
+        grid
+    }
+
+    /* wall down column x from row y, same numbers create_inner_walls would make */
+    fn column_wall(x: usize, y: usize, length: i32) -> InnerWall {
+        InnerWall {
+            start_pos: InnerWallStartPos { x: x + 1, y: y + 1 },
+            length_direction_vector: (1, length + 2),
+        }
+    }
+
+    #[test]
+    fn flood_fill_stops_at_walls_and_doors() {
+        let mut grid = room(9, 7);
+        grid[0][3] = 2;
+        let seen = flood_fill(&grid, (4, 3));
+        assert!(seen[1][3]);
+        assert!(!seen[0][3]);
+        /* starting on a wall gets you nowhere */
+        assert!(flood_fill(&grid, (0, 0)).iter().flatten().all(|tile| !tile));
+    }
+
+    #[test]
+    fn flood_fill_cant_reach_a_blocked_door() {
+        let mut grid = room(9, 7);
+        grid[0][3] = 2;
+        for y in 1..6 {
+            grid[2][y] = 1;
+        }
+        let seen = flood_fill(&grid, (4, 3));
+        assert!(!seen[1][3]);
+        assert!(!keys_connected(&grid, &[(4, 3), (1, 3)]));
+    }
+
+    #[test]
+    fn wall_that_splits_the_room_is_turned_down() {
+        let grid = room(11, 9);
+        let keys = [(5, 4), (1, 4), (9, 4)];
+        let splitting = column_wall(3, 1, 7);
+        assert_eq!(wall_tiles(&splitting).len(), 7);
+        assert!(!wall_keeps_room_open(&grid, &splitting, &keys));
+        /* one short and there's a gap at the top */
+        assert!(wall_keeps_room_open(&grid, &column_wall(3, 1, 6), &keys));
+    }
+
+    #[test]
+    fn reachable_spots_skip_walled_off_floor() {
+        let mut grid = room(11, 9);
+        for (x, y) in wall_tiles(&column_wall(3, 1, 7)) {
+            grid[x][y] = 1;
+        }
+        let spots = reachable_spots(&grid, &[(5, 4)], 1);
+        /* right of the wall and a tile clear of it, inside the margin */
+        assert_eq!(spots.len(), 4 * 5);
+        assert!(spots.iter().all(|(x, y)| (5..=8).contains(x) && (2..=6).contains(y)));
+    }
+
+    #[test]
+    fn take_spot_runs_out() {
+        let grid = room(11, 9);
+        let mut spots = vec![(2, 2), (5, 4), (8, 6)];
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut taken = Vec::new();
+        for _ in 0..3 {
+            let spot = take_spot(&grid, &mut spots, &mut rng).unwrap();
+            assert!(!taken.contains(&spot));
+            taken.push(spot);
+        }
+        assert!(spots.is_empty());
+        assert_eq!(take_spot(&grid, &mut spots, &mut rng), None);
+    }
+}
@@ -1,11 +1,26 @@
+use std::fs;
+use std::time::SystemTime;
+
 use bevy::prelude::*;
-use rand::{Rng, distributions::{Distribution, WeightedIndex}};
+use rand::{Rng, rngs::StdRng, distributions::{Distribution, WeightedIndex}};
+use serde::{Deserialize, Serialize};
 use crate::collision::*;
+use crate::dungeon_map::{DungeonMap, MapRect};
+use crate::dungeon_rng::{DungeonRng, RngStream};
+use crate::reachability::*;
 use crate::cuscuta_resources::*;
 use crate::network::Sequence;
 use crate::player::*;
 use crate::enemies::*;
 use crate::markov_chains::*;
+use crate::prefabs::*;
+use crate::room_shapes::*;
+use crate::room_state::RoomState;
+use crate::floors::DepthScaling;
+use crate::special_rooms::SHOP_WARES;
+use crate::stealth::roll_stealth_props;
+use crate::traps::roll_traps;
+use crate::biomes::{roll_decor, Biome};
 use crate::server::send_player_to_self;
 use crate::ui::*;
 use crate::network::UDP;
@@ -19,6 +34,14 @@ pub struct ClientRoomManager{
     pub max_y: f32,
     pub width: f32,
     pub height: f32,
+    /* last markov roll the server told us about as (chain, state), f3 shows it */
+    pub states: Vec<(String, String)>,
+    pub carnage: f32,
+    /* floor we're on and whether its boss drops the chalice */
+    pub floor: u32,
+    pub last_floor: bool,
+    /* what the floor looks like, see biomes.rs */
+    pub biome: Biome,
 }
 
 impl ClientRoomManager{
@@ -28,6 +51,11 @@ impl ClientRoomManager{
             height: 40. * TILE_SIZE as f32,
             max_x: 0.,
             max_y: 0.,
+            states: Vec::new(),
+            carnage: 0.5,
+            floor: 0,
+            last_floor: false,
+            biome: Biome::Lab,
         }
     }
 }
@@ -41,6 +69,8 @@ pub struct Potion;
 pub struct RoomDimensions {
     pub width: usize,
     pub height: usize,
+    // which of RoomManager::grids is this room's
+    pub grid: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -76,7 +106,7 @@ pub struct InnerWallList {
     pub walls: Vec<Vec<InnerWall>>, 
 }
 
-// array that remembers rooms and their z indexes
+// array that remembers rooms that have been built, indexed by RoomId
 #[derive(Debug)]
 pub struct RoomArray {
     pub rooms: Vec<Option<RoomDimensions>>,
@@ -89,14 +119,9 @@ impl RoomArray {
         }
     }
 
-    // convert z index to absolute value
-    fn z_to_index(z: f32) -> usize {
-        z.abs() as usize
-    }
-
     // add room to array with width and height
-    pub fn add_room_to_storage(&mut self, z_index: f32, width: usize, height: usize) {
-        let index = Self::z_to_index(z_index);
+    pub fn add_room_to_storage(&mut self, room: RoomId, width: usize, height: usize, grid: usize) {
+        let index = room.0;
 
         // ensure array is large enough to hold index
         if index >= self.rooms.len() {
@@ -105,12 +130,12 @@ impl RoomArray {
         }
 
         // store room at correct index
-        self.rooms[index] = Some(RoomDimensions { width, height })
+        self.rooms[index] = Some(RoomDimensions { width, height, grid })
     }
 
     // get room dimensions at given index
-    pub fn get_room_from_storage(&self, z_index: f32) -> Option<&RoomDimensions> {
-        let index = Self::z_to_index(z_index);
+    pub fn get_room_from_storage(&self, room: RoomId) -> Option<&RoomDimensions> {
+        let index = room.0;
         if index < self.rooms.len() {
             return self.rooms[index].as_ref();
         }
@@ -118,13 +143,14 @@ impl RoomArray {
     }
     
     // Get room dimensions in PIXEL SIZE
-    pub fn get_room_from_storage_in_pixels(&self, z_index: f32) -> Option<RoomDimensions> {
-        let index = Self::z_to_index(z_index);
+    pub fn get_room_from_storage_in_pixels(&self, room: RoomId) -> Option<RoomDimensions> {
+        let index = room.0;
         if index < self.rooms.len() {
             if let Some(dimensions) = &self.rooms[index] {
                 return Some(RoomDimensions {
                     width: dimensions.width * 32,
                     height: dimensions.height * 32,
+                    grid: dimensions.grid,
                 });
             }
         }
@@ -139,17 +165,20 @@ impl RoomArray {
 
 #[derive(Component)]
 pub struct Door {
-    pub next: Option<f32>,
+    pub next: Option<RoomId>,
     pub door_type: DoorType,
 }
 
 #[derive(Component)]
 pub struct ClientDoor {
     pub door_type: DoorType,
+    /* what's past it, tints the door */
+    pub behind: RoomType,
+    pub locked: bool,
 }
 
 // enum to represent different door types
-#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DoorType {
     Right,
     Left,
@@ -157,10 +186,36 @@ pub enum DoorType {
     Bottom,
 }
 
+impl DoorType {
+    pub const ALL: [DoorType; 4] = [DoorType::Left, DoorType::Right, DoorType::Top, DoorType::Bottom];
+
+    /* slot in LayoutRoom::doors */
+    pub fn index(&self) -> usize {
+        match self {
+            DoorType::Left => 0,
+            DoorType::Right => 1,
+            DoorType::Top => 2,
+            DoorType::Bottom => 3,
+        }
+    }
+
+    /* the door you come out of on the other side */
+    pub fn opposite(&self) -> DoorType {
+        match self {
+            DoorType::Left => DoorType::Right,
+            DoorType::Right => DoorType::Left,
+            DoorType::Top => DoorType::Bottom,
+            DoorType::Bottom => DoorType::Top,
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct RoomManager {
-    // 2D array to store the room placement
-    pub room_map: Vec<Vec<i32>>,
+    // whole dungeon graph, which room is behind which door
+    pub layout: DungeonLayout,
+    // where every layout room sits, grows with the layout
+    pub map: DungeonMap,
     pub grids: Vec<Vec<Vec<u32>>>,
     pub current_room: usize,
     pub room_sizes: Vec<(f32, f32)>,
@@ -168,41 +223,41 @@ pub struct RoomManager {
     pub max_sizes: Vec<(f32, f32)>,  
     //MARKOV impl 2
     pub state_vector: Vec<(usize)>,
-    // z of room that player is currently in
-    pub current_z_index: f32,  
-    // z of room that was most recently generated (used so we can backtrack w/o screwing everything up)
-    pub global_z_index: f32,  
+    // layout room the party is standing in
+    pub current: RoomId,
+    // inner walls per room, indexed by RoomId
     pub inner_wall_list: InnerWallList,
+    // hand drawn rooms and chunks room gen can mix in
+    pub prefabs: PrefabLibrary,
+    // prefab spawn markers per room, indexed by RoomId like the inner walls
+    pub markers: Vec<RoomMarkers>,
+    // what was left in each room when the party walked out, indexed by RoomId too
+    pub room_states: Vec<Option<RoomState>>,
 }
 
 impl RoomManager {
     pub fn new() -> Self {
-        // initialize the 200x200 grid with 1s
-        let room_map = vec![vec![1; 400]; 400];
-
         Self {
-            room_map,
+            layout: DungeonLayout::new(),
+            map: DungeonMap::new(),
             grids: Vec::new(),
             current_room: 0,
             room_array: RoomArray::new(),
             room_sizes: Vec::new(),
             max_sizes: Vec::new(), 
             state_vector: Vec::new(),
-            current_z_index: -2.0,
-            global_z_index: -2.0,
+            current: RoomId(0),
             inner_wall_list: InnerWallList { walls: vec![Vec::new(); 100] },
+            prefabs: PrefabLibrary::new(),
+            markers: Vec::new(),
+            room_states: Vec::new(),
         }
 
     }
 
-     // Getter for current Z index
-    pub fn get_current_z_index(&self) -> f32 {
-        self.current_z_index
-    }
-
-    // Getter for global Z index
-    pub fn get_global_z_index(&self) -> f32 {
-        self.global_z_index
+    // room the party is in
+    pub fn current_room_id(&self) -> RoomId {
+        self.current
     }
 
     //MARKOV impl 3
@@ -217,7 +272,15 @@ impl RoomManager {
 
     // add new grid for new room 
     pub fn add_room(&mut self, width: usize, height: usize, room_width: f32, room_height: f32) {
-        let new_grid = vec![vec![0; height]; width];
+        let mut new_grid = vec![vec![0; height]; width];
+        // outer walls block, the flood fill in reachability leans on it
+        for x in 0..width {
+            for y in 0..height {
+                if x == 0 || y == 0 || x + 1 == width || y + 1 == height {
+                    new_grid[x][y] = 1;
+                }
+            }
+        }
         self.grids.push(new_grid);
         self.room_sizes.push((room_width, room_height));
         
@@ -230,234 +293,1055 @@ impl RoomManager {
         self.current_room = self.grids.len() - 1;
     }
 
-    // add rooms dimensions to map with z index at a random position (for start room)
-    pub fn add_start_room_to_map(&mut self, z_index: i32, width: usize, height: usize){
-        let mut rng = rand::thread_rng();
+    /* redraws the map from the layout, every slot and hallway box */
+    pub fn add_layout_to_map(&mut self) {
+        self.map.clear();
+        for room in self.layout.ids() {
+            self.draw_on_map(room);
+        }
+        if let Some(bounds) = self.map.bounds() {
+            println!("dungeon map: {} chunks, {}x{} tiles", self.map.chunks.len(), bounds.width, bounds.height);
+        }
+    }
+
+    fn draw_on_map(&mut self, room: RoomId) {
+        if let Some(slot) = self.layout.room(room) {
+            self.map.add_room(room, MapRect::centered(slot.x, slot.y, slot.width, slot.height));
+        }
+    }
 
-        let upper_width = 400 - width;
-        let upper_height = 400 - height;
+    /* rolls for the dungeon to grow off a room nobody has been in yet,
+     * see DungeonLayout::grow. has to go before the room is built so its
+     * walls leave the new door open */
+    pub fn grow_layout(&mut self, room: RoomId, rng: &mut StdRng) {
+        let Some((new, hallway)) = self.layout.grow(room, &self.map, rng) else {
+            return;
+        };
+        self.draw_on_map(new);
+        self.draw_on_map(hallway);
+        println!("dungeon grew room {} off room {} through hallway {}", new.0, room.0, hallway.0);
+        if let Some(bounds) = self.map.bounds() {
+            println!("dungeon map: {} chunks, {}x{} tiles", self.map.chunks.len(), bounds.width, bounds.height);
+        }
+    }
 
-        // Define the top-left corner for the start room placement randomly
-        let start_x = rng.gen_range(0..upper_width);
-        let start_y = rng.gen_range(0..upper_height);
+    pub fn set_current_room(&mut self, room: RoomId) {
+        self.current = room;
+    }
 
-        // Loop through the dimensions of the room and place the z_index in the grid
-        for x in start_x..(start_x + width) {
-            for y in start_y..(start_y + height) {
-                self.room_map[x][y] = z_index;
-            }
+    // render z of the current room, only for drawing
+    pub fn current_room_z_index(&self) -> f32 {
+        self.current.z()
+    }
+
+    // Get mutable reference to the current grid
+    pub fn current_grid(&mut self) -> &mut Vec<Vec<u32>> {
+        &mut self.grids[self.current_room]
+    }
+
+    // same but read only, for the reachability checks
+    pub fn current_grid_ref(&self) -> &Vec<Vec<u32>> {
+        &self.grids[self.current_room]
+    }
+
+    /* doors the current room has (or will have, walls go in before doors) */
+    pub fn current_doors(&self) -> Vec<DoorType> {
+        match self.current_layout_room() {
+            Some(room) => DoorType::ALL.into_iter().filter(|door| room.doors[door.index()].is_some()).collect(),
+            None => Vec::new(),
         }
     }
 
+    /* tiles in the current room that always have to be reachable */
+    pub fn current_key_tiles(&self) -> Vec<(usize, usize)> {
+        key_tiles(self.current_grid_ref(), &self.current_doors())
+    }
+    
+    // Get the size of the current room (width, height)
+    pub fn current_room_size(&self) -> (f32, f32) {
+        if let Some(room) = self.room_array.get_room_from_storage(self.current) {
+            let width = room.width as f32 * TILE_SIZE as f32;
+            let height = room.height as f32 * TILE_SIZE as f32;
+            return (width, height);
+        }else{
+            self.room_sizes[self.current_room]
+        }
+    }
 
-    pub fn add_room_to_map_from_top_door(
-        &mut self, 
-        z_index: i32, 
-        new_z_index: i32, 
-        new_width: usize, 
-        new_height: usize
-    ) {
-        // Find the bounds of the current room
-        if let Some((left_x, right_x, top_y, _bottom_y)) = self.find_room_bounds(z_index) {
-            let old_x = (left_x + right_x) / 2;
-            let old_y = top_y;
+    pub fn current_room_max(&self) -> (f32, f32) {
+        self.max_sizes[self.current_room]
+    }
 
-            let start_x = old_x - (new_width / 2);
-            let mut start_y = 0;
-            if (old_y >= new_height){
-                start_y = old_y - new_height;
-            }
-            // Loop through the dimensions of the room and place the z_index in the grid
-            for x in start_x..(start_x + new_width) {
-                for y in start_y..(start_y + new_height) {
-                    self.room_map[x][y] = new_z_index;
-                }
-            }
-            println!("add_room_to_map_from_left_door - ADDED ROOM z index: {}", new_z_index);
+    pub fn add_inner_wall(&mut self, room: RoomId, wall: InnerWall) {
+        let index = room.0;
+        // big layouts can have more than the first 100 rooms
+        if index >= self.inner_wall_list.walls.len() {
+            self.inner_wall_list.walls.resize(index + 1, Vec::new());
+        }
+        self.inner_wall_list.walls[index].push(wall);
+    }
 
-        } else {
-            println!("Error: TOP Could not find bounds for the current room with z_index {}", z_index);
+    /* layout entry for the room the party is standing in */
+    pub fn current_layout_room(&self) -> Option<&LayoutRoom> {
+        self.layout.room(self.current)
+    }
+
+    pub fn current_room_type(&self) -> RoomType {
+        self.current_layout_room().map_or(RoomType::Combat, |room| room.room_type)
+    }
+
+    pub fn get_inner_walls(&self, room: RoomId) -> Option<&Vec<InnerWall>> {
+        self.inner_wall_list.walls.get(room.0)
+    }
+
+    pub fn set_markers(&mut self, room: RoomId, markers: RoomMarkers) {
+        let index = room.0;
+        if index >= self.markers.len() {
+            self.markers.resize(index + 1, RoomMarkers::new());
+        }
+        self.markers[index] = markers;
+    }
+
+    pub fn get_markers(&self, room: RoomId) -> Option<&RoomMarkers> {
+        self.markers.get(room.0)
+    }
+
+    pub fn room_state(&self, room: RoomId) -> Option<&RoomState> {
+        self.room_states.get(room.0).and_then(|state| state.as_ref())
+    }
+
+    /* saved state for a room, started empty if there isnt one yet */
+    pub fn room_state_mut(&mut self, room: RoomId) -> &mut RoomState {
+        let index = room.0;
+        if index >= self.room_states.len() {
+            self.room_states.resize(index + 1, None);
+        }
+        self.room_states[index].get_or_insert_with(RoomState::new)
+    }
+}
+
+
+/* DUNGEON LAYOUT, the README method:
+ * 1. scatter a pile of room slots 2. push them apart 3. keep the big ones
+ * 4. delaunay the big ones 5. spanning tree over that + a few loops back in
+ * slots are just footprints, the markov roll decides what goes in each.
+ * slot x/y are plain floats around 0,0, RoomManager::add_layout_to_map
+ * draws them on the sparse DungeonMap so there's no edge to run off */
+pub const LAYOUT_CANDIDATES: usize = 30;
+// tiles, how far from center the first scatter goes
+pub const LAYOUT_SCATTER_RADIUS: f32 = 80.;
+// slot sizes in tiles, 40 is the smallest room RoomConfig makes
+pub const LAYOUT_SLOT_MIN: usize = 40;
+pub const LAYOUT_SLOT_MAX: usize = 64;
+// tiles kept clear between slots
+pub const LAYOUT_PADDING: f32 = 6.;
+// main rooms are bigger than mean area * this
+pub const LAYOUT_MAIN_RATIO: f32 = 1.1;
+// never fewer than this many main rooms, boss wants somewhere to hide
+pub const LAYOUT_MIN_MAIN: usize = 6;
+// chance each leftover delaunay edge comes back as a loop
+pub const LAYOUT_LOOP_CHANCE: f32 = 0.15;
+// give up pushing after this many passes
+const LAYOUT_SEPARATE_STEPS: usize = 500;
+// rooms this far off in line (tiles) still get a straight hallway, past it they bend
+pub const LAYOUT_STRAIGHT_SLACK: f32 = 12.;
+
+/* hallways, step 6. every connection gets a corridor room between the two
+ * rooms, straight when they line up and L shaped when they dont */
+// default corridor width in floor tiles, server can override with --hallway-width
+pub const HALLWAY_WIDTH: usize = 3;
+// hallway length (tiles) along each axis, whatever the gap between slots says
+pub const HALLWAY_MIN_LENGTH: usize = 12;
+pub const HALLWAY_MAX_LENGTH: usize = 40;
+// hallway patrols start at least this many tiles from a door
+pub const HALLWAY_DOOR_CLEARANCE: usize = 6;
+// whole layouts rolled before we give up on the floor rules
+pub const LAYOUT_TRIES: usize = 20;
+// treasure rooms get at least this many potions and pots each
+pub const TREASURE_MIN_ITEMS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayoutKind {
+    Room,
+    /* corridor, ends are the two sides it has doors on. opposite
+     * ends = straight, anything else bends in the middle */
+    Hallway { width: usize, ends: [DoorType; 2] },
+}
+
+/* what a room is for, picked with the layout (so before anyone gets
+ * there) unlike the markov attributes which roll on the way in. the
+ * markov rolls still shape the room, the type decides what's in it.
+ * everything past treasure plays out in special_rooms.rs */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomType {
+    /* the usual, whatever the markov roll says */
+    Combat,
+    /* guaranteed loot, see TREASURE_MIN_ITEMS */
+    Treasure,
+    /* no enemies, potions cost health */
+    Shop,
+    /* doors lock until every wave is dead */
+    Challenge,
+    /* no enemies, heals the party */
+    Rest,
+}
+
+impl RoomType {
+    /* door tint on the client, so you can see what's ahead */
+    pub fn marker(&self) -> Color {
+        match self {
+            RoomType::Combat => Color::WHITE,
+            RoomType::Treasure => Color::srgb(1.0, 0.85, 0.3),
+            RoomType::Shop => Color::srgb(0.4, 0.9, 0.5),
+            RoomType::Challenge => Color::srgb(0.85, 0.4, 1.0),
+            RoomType::Rest => Color::srgb(0.45, 0.75, 1.0),
+        }
+    }
+}
+
+/* a room's spot in the dungeon graph (its index in DungeonLayout::rooms).
+ * start is always 0 and ids grow the further you walk from it. everything
+ * kept per room (grids, walls, markers, saved state) is keyed on this,
+ * z is only where the room draws */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RoomId(pub usize);
+
+impl RoomId {
+    /* render depth, every room gets its own layer (start -2, next -4, ...) */
+    pub fn z(&self) -> f32 {
+        -2. * (self.0 as f32 + 1.)
+    }
+}
+
+/* one door in the graph, `from` walks through its `door` into `to` */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoomEdge {
+    pub from: RoomId,
+    pub door: DoorType,
+    pub to: RoomId,
+}
+
+/* one room in the dungeon graph. x/y is the slot center in tiles
+ * (y up, same as the world), width/height the slot footprint.
+ * for hallways width/height is the exact box the corridor sits in */
+#[derive(Clone, Debug)]
+pub struct LayoutRoom {
+    pub x: f32,
+    pub y: f32,
+    pub width: usize,
+    pub height: usize,
+    /* room behind each door, indexed by DoorType::index */
+    pub doors: [Option<RoomId>; 4],
+    pub kind: LayoutKind,
+    pub room_type: RoomType,
+}
+
+impl LayoutRoom {
+    fn new(x: f32, y: f32, width: usize, height: usize) -> Self {
+        Self {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+            doors: [None; 4],
+            kind: LayoutKind::Room,
+            room_type: RoomType::Combat,
+        }
+    }
+
+    fn center(&self) -> Vec2 {
+        Vec2::new(self.x, self.y)
+    }
+
+    pub fn is_hallway(&self) -> bool {
+        matches!(self.kind, LayoutKind::Hallway { .. })
+    }
+
+    /* is tile x, y (from the bottom left, outer walls included) walkable.
+     * hallway legs run from their door to the middle of the box, which is
+     * where doors sit on every wall, so two legs always meet up */
+    pub fn is_floor(&self, x: usize, y: usize) -> bool {
+        if x == 0 || y == 0 || x + 1 >= self.width || y + 1 >= self.height {
+            return false;
+        }
+        let LayoutKind::Hallway { width, ends } = self.kind else {
+            return true;
+        };
+        let (mid_x, mid_y) = (self.width / 2, self.height / 2);
+        let across = |pos: usize, mid: usize| pos + (width - 1) / 2 >= mid && pos <= mid + width / 2;
+        ends.iter().any(|end| match end {
+            DoorType::Left => x <= mid_x + width / 2 && across(y, mid_y),
+            DoorType::Right => x + (width - 1) / 2 >= mid_x && across(y, mid_y),
+            DoorType::Bottom => y <= mid_y + width / 2 && across(x, mid_x),
+            DoorType::Top => y + (width - 1) / 2 >= mid_y && across(x, mid_x),
+        })
+    }
+
+    /* somewhere down the middle of one of the hallway's legs plus the
+     * way that leg runs, in pixels with the room centered on 0,0 */
+    pub fn hallway_patrol_spot(&self, rng: &mut StdRng) -> Option<(Vec2, Vec2)> {
+        let LayoutKind::Hallway { ends, .. } = self.kind else {
+            return None;
+        };
+        let (mid_x, mid_y) = (self.width / 2, self.height / 2);
+        let far_x = self.width.saturating_sub(1 + HALLWAY_DOOR_CLEARANCE);
+        let far_y = self.height.saturating_sub(1 + HALLWAY_DOOR_CLEARANCE);
+        let (mut tiles, axis): (Vec<(usize, usize)>, Vec2) = match ends[rng.gen_range(0..2)] {
+            DoorType::Left => ((HALLWAY_DOOR_CLEARANCE..=mid_x).map(|x| (x, mid_y)).collect(), Vec2::X),
+            DoorType::Right => ((mid_x..=far_x).map(|x| (x, mid_y)).collect(), Vec2::X),
+            DoorType::Bottom => ((HALLWAY_DOOR_CLEARANCE..=mid_y).map(|y| (mid_x, y)).collect(), Vec2::Y),
+            DoorType::Top => ((mid_y..=far_y).map(|y| (mid_x, y)).collect(), Vec2::Y),
+        };
+        /* short hallway, the bend is all we've got */
+        if tiles.is_empty() {
+            tiles.push((mid_x, mid_y));
         }
+        let (x, y) = tiles[rng.gen_range(0..tiles.len())];
+        let spot = Vec2::new(
+            x as f32 * TILE_SIZE as f32 - self.width as f32 * TILE_SIZE as f32 / 2. + TILE_SIZE as f32 / 2.,
+            y as f32 * TILE_SIZE as f32 - self.height as f32 * TILE_SIZE as f32 / 2. + TILE_SIZE as f32 / 2.,
+        );
+        Some((spot, axis))
     }
+}
 
-    pub fn add_room_to_map_from_bottom_door(
-        &mut self, 
-        z_index: i32, 
-        new_z_index: i32, 
-        new_width: usize, 
-        new_height: usize
-    ) {
-        // Find the bounds of the current room
-        if let Some((left_x, right_x, _top_y, bottom_y)) = self.find_room_bounds(z_index) {
-            let old_x = (left_x + right_x) / 2;
-            let old_y = bottom_y + 1;
+#[derive(Clone, Debug)]
+pub struct DungeonLayout {
+    /* indexed by RoomId */
+    pub rooms: Vec<LayoutRoom>,
+    /* connections made while building, tree and loops. hallways get
+     * spliced into these, after that the doors are the graph */
+    links: Vec<(usize, usize)>,
+    /* start is always room 0, boss is the far end of the graph */
+    pub start: RoomId,
+    pub boss: RoomId,
+    /* corridor width the layout was built with, grown hallways match it */
+    hallway_width: usize,
+    /* (chance, rooms left) from FloorRules::growth */
+    growth: (f32, usize),
+}
 
-            let start_x = old_x - (new_width / 2);
-            let start_y = old_y;
+impl DungeonLayout {
+    pub fn new() -> Self {
+        Self {
+            rooms: Vec::new(),
+            links: Vec::new(),
+            start: RoomId(0),
+            boss: RoomId(0),
+            hallway_width: HALLWAY_WIDTH,
+            growth: (0., 0),
+        }
+    }
 
-            // Loop through the dimensions of the room and place the z_index in the grid
-            for x in start_x..(start_x + new_width) {
-                for y in start_y..(start_y + new_height) {
-                    self.room_map[x][y] = new_z_index;
+    /* rolls whole layouts until one fits the floor rules. if none do
+     * the boss just goes at the far end like it used to */
+    pub fn generate(rng: &mut StdRng, hallway_width: usize, rules: &FloorRules) -> Self {
+        for attempt in 0..LAYOUT_TRIES {
+            let mut layout = Self::generate_rooms(rng);
+            match layout.apply_floor_rules(rules, rng) {
+                Ok(()) => {
+                    layout.add_hallways(hallway_width);
+                    layout.growth = rules.growth;
+                    return layout;
                 }
+                Err(problem) => println!("layout {}: {}, rolling another", attempt, problem),
             }
-    
-        } else {
-            println!("Error: BOTTOM Could not find bounds for the current room with z_index {}", z_index);
-        }
-    }
-
-    pub fn add_room_to_map_from_left_door(
-        &mut self, 
-        z_index: i32, 
-        new_z_index: i32, 
-        new_width: usize, 
-        new_height: usize
-    ) {
-        // Find the bounds of the current room
-        if let Some((left_x, _right_x, top_y, bottom_y)) = self.find_room_bounds(z_index) {
-            let old_y = (top_y + bottom_y) / 2;
-            let old_x = left_x;
-            let start_y = old_y - (new_height / 2);
-            let start_x = old_x - new_width;
-
-            // Loop through the dimensions of the room and place the z_index in the grid
-            for x in start_x..(start_x + new_width) {
-                for y in start_y..(start_y + new_height) {
-                    self.room_map[x][y] = new_z_index;
+        }
+        println!("no layout fit the floor rules in {} tries, going without them", LAYOUT_TRIES);
+        let mut layout = Self::generate_rooms(rng);
+        let _ = layout.apply_floor_rules(&FloorRules::none(), rng);
+        layout.add_hallways(hallway_width);
+        layout.growth = rules.growth;
+        layout
+    }
+
+    /* steps 1-5, the rooms and how they connect. no start, boss or hallways yet */
+    fn generate_rooms(rng: &mut StdRng) -> Self {
+        // 1. SCATTER
+        let mut slots: Vec<LayoutRoom> = (0..LAYOUT_CANDIDATES)
+            .map(|_| {
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                let radius = LAYOUT_SCATTER_RADIUS * rng.gen::<f32>().sqrt();
+                let width = rng.gen_range(LAYOUT_SLOT_MIN..=LAYOUT_SLOT_MAX);
+                let height = rng.gen_range(LAYOUT_SLOT_MIN..=LAYOUT_SLOT_MAX);
+                LayoutRoom::new(radius * angle.cos(), radius * angle.sin(), width, height)
+            })
+            .collect();
+
+        // 2. PUSH APART
+        separate_slots(&mut slots);
+
+        // 3. MAIN ROOMS
+        let mean_area = slots.iter().map(|r| (r.width * r.height) as f32).sum::<f32>() / slots.len() as f32;
+        let mut rooms: Vec<LayoutRoom> = slots
+            .iter()
+            .filter(|r| (r.width * r.height) as f32 > mean_area * LAYOUT_MAIN_RATIO)
+            .cloned()
+            .collect();
+        if rooms.len() < LAYOUT_MIN_MAIN {
+            let mut by_area = slots.clone();
+            by_area.sort_by_key(|r| std::cmp::Reverse(r.width * r.height));
+            rooms = by_area.into_iter().take(LAYOUT_MIN_MAIN).collect();
+        }
+
+        // 4. DELAUNAY
+        let points: Vec<Vec2> = rooms.iter().map(|r| r.center()).collect();
+        let mut candidates = delaunay_edges(&points);
+        candidates.sort_by(|a, b| {
+            points[a.0].distance(points[a.1]).total_cmp(&points[b.0].distance(points[b.1]))
+        });
+
+        // 5. MST (kruskal), then loops. every link has to get a free door on both ends
+        let mut layout = Self { rooms: rooms, ..Self::new() };
+        let mut sets: Vec<usize> = (0..layout.rooms.len()).collect();
+        for &(a, b) in candidates.iter() {
+            if find_set(&mut sets, a) != find_set(&mut sets, b) && layout.link(a, b) {
+                let root = find_set(&mut sets, a);
+                sets[root] = find_set(&mut sets, b);
+            }
+        }
+        /* door sides can run out, patch any split pieces with whatever edge fits */
+        let mut all_pairs: Vec<(usize, usize)> = (0..points.len())
+            .flat_map(|a| (a + 1..points.len()).map(move |b| (a, b)))
+            .collect();
+        all_pairs.sort_by(|a, b| {
+            points[a.0].distance(points[a.1]).total_cmp(&points[b.0].distance(points[b.1]))
+        });
+        for &(a, b) in all_pairs.iter() {
+            if find_set(&mut sets, a) != find_set(&mut sets, b) && layout.link(a, b) {
+                let root = find_set(&mut sets, a);
+                sets[root] = find_set(&mut sets, b);
+            }
+        }
+        for &(a, b) in candidates.iter() {
+            if layout.rooms[a].doors.contains(&Some(RoomId(b))) {
+                continue;
+            }
+            if rng.gen::<f32>() < LAYOUT_LOOP_CHANCE {
+                layout.link(a, b);
+            }
+        }
+
+        layout.keep_largest_piece();
+        layout
+    }
+
+    /* 6. START + BOSS. start is one end of the longest path we can find,
+     * boss the farthest room from it that the rules allow. then the rooms
+     * that have to come before the boss get their types. 7 (hallways)
+     * comes after, numbered after every real room */
+    fn apply_floor_rules(&mut self, rules: &FloorRules, rng: &mut StdRng) -> Result<(), String> {
+        let from_any = self.distances(RoomId(0));
+        let start = farthest(&from_any);
+        let from_start = self.distances(RoomId(start));
+        let (min, max) = rules.boss_distance;
+
+        let mut candidates: Vec<usize> = (0..self.rooms.len())
+            .filter(|&room| room != start && from_start[room].map_or(false, |dist| dist >= min && dist <= max))
+            .collect();
+        candidates.sort_by_key(|&room| (std::cmp::Reverse(from_start[room]), room));
+        let boss = candidates
+            .into_iter()
+            .find(|&room| !rules.single_boss_entrance || self.seal_to_one_door(room, start, &from_start))
+            .ok_or(format!("no room {}-{} rooms from start can be the boss", min, max))?;
+
+        /* cutting doors can make the long way round the only way round */
+        let from_start = self.distances(RoomId(start));
+        let boss_dist = from_start[boss].unwrap_or(0);
+        let mut before: Vec<usize> = (0..self.rooms.len())
+            .filter(|&room| room != start && room != boss && from_start[room].map_or(false, |dist| dist < boss_dist))
+            .collect();
+        for (room_type, count) in rules.before_boss.iter() {
+            for _ in 0..*count {
+                if before.is_empty() {
+                    return Err(format!("ran out of rooms before the boss for {:?}", room_type));
                 }
+                let room = before.swap_remove(rng.gen_range(0..before.len()));
+                self.rooms[room].room_type = *room_type;
             }
-    
-        } else {
-            println!("Error: LEFT Could not find bounds for the current room with z_index {}", z_index);
-        }
-    }
-
-    pub fn add_room_to_map_from_right_door(
-        &mut self, 
-        z_index: i32, 
-        new_z_index: i32, 
-        new_width: usize, 
-        new_height: usize
-    ) {
-        // Find the bounds of the current room
-        if let Some((_left_x, right_x, top_y, bottom_y)) = self.find_room_bounds(z_index) {
-            let old_y = (top_y + bottom_y) / 2;
-            let old_x = right_x + 1;
-            let start_y = old_y - (new_height / 2);
-            let start_x = old_x ;
-
-            // Loop through the dimensions of the room and place the z_index in the grid
-            for x in start_x..(start_x + new_width) {
-                for y in start_y..(start_y + new_height) {
-                    self.room_map[x][y] = new_z_index;
+        }
+        /* the rest can go anywhere that isnt already something */
+        let mut open: Vec<usize> = (0..self.rooms.len())
+            .filter(|&room| room != start && room != boss && self.rooms[room].room_type == RoomType::Combat)
+            .collect();
+        for (room_type, count) in rules.quotas.iter() {
+            for _ in 0..*count {
+                if open.is_empty() {
+                    return Err(format!("ran out of rooms for {:?}", room_type));
                 }
+                let room = open.swap_remove(rng.gen_range(0..open.len()));
+                self.rooms[room].room_type = *room_type;
             }
-
-    
-        } else {
-            println!("Error: RIGHT Could not find bounds for the current room with z_index {}", z_index);
         }
+
+        /* renumber in walking order from start, so start is room 0
+         * and ids roughly grow the deeper you go */
+        let mut order: Vec<usize> = (0..self.rooms.len()).collect();
+        order.sort_by_key(|&room| (from_start[room].unwrap_or(usize::MAX), room));
+        self.reorder(&order);
+        self.start = RoomId(0);
+        self.boss = RoomId(order.iter().position(|&room| room == boss).unwrap_or(0));
+        Ok(())
     }
 
-    // method to find room bounds based on the current room z index
-    pub fn find_room_bounds(&self, z_index: i32) -> Option<(usize, usize, usize, usize)> {
-        let mut left_x = usize::MAX;
-        let mut right_x = 0;
-        let mut top_y = usize::MAX;
-        let mut bottom_y = 0;
+    /* cuts every door of room but the one on its shortest way back to
+     * start. false (and nothing cut) if that strands anybody */
+    fn seal_to_one_door(&mut self, room: usize, start: usize, from_start: &[Option<usize>]) -> bool {
+        let neighbors = self.neighbors(RoomId(room));
+        let Some(&(_, keep)) = neighbors.iter().min_by_key(|(_, next)| (from_start[next.0], next.0)) else {
+            return false;
+        };
+        let mut sealed = self.clone();
+        for (_, next) in neighbors.iter().filter(|(_, next)| *next != keep) {
+            sealed.unlink(room, next.0);
+        }
+        if sealed.distances(RoomId(start)).iter().any(|dist| dist.is_none()) {
+            return false;
+        }
+        *self = sealed;
+        true
+    }
 
-        for x in 0..self.room_map.len(){
-            for y in 0..self.room_map[x].len(){
-                if self.room_map[x][y] == z_index {
-                    if x < left_x {left_x = x; }
-                    if x > right_x {right_x = x; }
-                    if y < top_y {top_y = y; }
-                    if y > bottom_y {bottom_y = y; }
+    /* takes out the a-b connection, both doors and the link */
+    fn unlink(&mut self, a: usize, b: usize) {
+        for (room, other) in [(a, b), (b, a)] {
+            for door in self.rooms[room].doors.iter_mut() {
+                if *door == Some(RoomId(other)) {
+                    *door = None;
                 }
             }
         }
+        self.links.retain(|&link| link != (a, b) && link != (b, a));
+    }
 
-        if left_x != usize::MAX && right_x > 0 && top_y != usize::MAX && bottom_y > 0 {
-            Some((left_x, right_x, top_y, bottom_y))
+    /* hooks a up to b, each through a door facing the other. lined up
+     * rooms want facing doors (straight hallway), the rest want one door
+     * on each axis (L hallway). false if no pair of sides is free */
+    fn link(&mut self, a: usize, b: usize) -> bool {
+        let offset = self.rooms[b].center() - self.rooms[a].center();
+        let horizontal = if offset.x >= 0. { DoorType::Right } else { DoorType::Left };
+        let vertical = if offset.y >= 0. { DoorType::Top } else { DoorType::Bottom };
+        let (main, cross, off_line) = if offset.x.abs() >= offset.y.abs() {
+            (horizontal, vertical, offset.y.abs())
         } else {
-            None
+            (vertical, horizontal, offset.x.abs())
+        };
+        let straight = [(main, main.opposite()), (cross, cross.opposite())];
+        let bent = [(main, cross.opposite()), (cross, main.opposite())];
+        let options = if off_line <= LAYOUT_STRAIGHT_SLACK {
+            [straight[0], bent[0], bent[1], straight[1]]
+        } else {
+            [bent[0], bent[1], straight[0], straight[1]]
+        };
+        for (side_a, side_b) in options {
+            if self.rooms[a].doors[side_a.index()].is_none() && self.rooms[b].doors[side_b.index()].is_none() {
+                self.rooms[a].doors[side_a.index()] = Some(RoomId(b));
+                self.rooms[b].doors[side_b.index()] = Some(RoomId(a));
+                self.links.push((a, b));
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn room(&self, room: RoomId) -> Option<&LayoutRoom> {
+        self.rooms.get(room.0)
+    }
+
+    /* every room id, start first */
+    pub fn ids(&self) -> impl Iterator<Item = RoomId> {
+        (0..self.rooms.len()).map(RoomId)
+    }
+
+    /* room behind door_type of room */
+    pub fn neighbor(&self, room: RoomId, door_type: DoorType) -> Option<RoomId> {
+        self.room(room)?.doors[door_type.index()]
+    }
+
+    /* room you end up in through door_type, walking through the
+     * hallway if that's what the door opens onto */
+    pub fn room_beyond(&self, room: RoomId, door_type: DoorType) -> Option<RoomId> {
+        let next = self.neighbor(room, door_type)?;
+        if !self.room(next)?.is_hallway() {
+            return Some(next);
         }
+        self.neighbors(next).into_iter().map(|(_, far)| far).find(|far| *far != room)
     }
 
-    pub fn get_room_value(&self, x: usize, y: usize) -> Option<i32> {
-        // Check if indices are within bounds
-        if x < self.room_map.len() && y < self.room_map[x].len() {
-            Some(self.room_map[x][y])
+    /* every door of room and the type of room past it */
+    pub fn door_markers(&self, room: RoomId) -> Vec<(DoorType, RoomType)> {
+        self.neighbors(room).into_iter()
+            .map(|(door, _)| {
+                let far = self.room_beyond(room, door).and_then(|far| self.room(far));
+                (door, far.map_or(RoomType::Combat, |far| far.room_type))
+            })
+            .collect()
+    }
+
+    /* every door of room and who is behind it */
+    pub fn neighbors(&self, room: RoomId) -> Vec<(DoorType, RoomId)> {
+        DoorType::ALL.into_iter()
+            .filter_map(|door| Some((door, self.neighbor(room, door)?)))
+            .collect()
+    }
+
+    /* which door of room leads to other */
+    pub fn door_to(&self, room: RoomId, other: RoomId) -> Option<DoorType> {
+        DoorType::ALL.into_iter().find(|door| self.neighbor(room, *door) == Some(other))
+    }
+
+    /* every door in the dungeon, once from each side */
+    pub fn edges(&self) -> Vec<RoomEdge> {
+        self.ids()
+            .flat_map(|from| self.neighbors(from).into_iter().map(move |(door, to)| RoomEdge { from: from, door: door, to: to }))
+            .collect()
+    }
+
+    /* splits every a-b connection into a-hallway-b */
+    fn add_hallways(&mut self, width: usize) {
+        self.hallway_width = width.max(1);
+        let links = std::mem::take(&mut self.links);
+        for (a, b) in links {
+            self.add_hallway(a, b);
+        }
+    }
+
+    /* puts a hallway between a and b, sized off the gap between the two
+     * slots. straight ones sit in the middle of the gap so the box stays
+     * off both rooms on the map. returns its index, None if a and b
+     * arent linked through doors */
+    fn add_hallway(&mut self, a: usize, b: usize) -> Option<usize> {
+        let width = self.hallway_width;
+        let (Some(side_a), Some(side_b)) = (self.door_to(RoomId(a), RoomId(b)), self.door_to(RoomId(b), RoomId(a))) else {
+            return None;
+        };
+        let (room_a, room_b) = (&self.rooms[a], &self.rooms[b]);
+        let gap_x = (room_a.x - room_b.x).abs() - (room_a.width + room_b.width) as f32 / 2.;
+        let gap_y = (room_a.y - room_b.y).abs() - (room_a.height + room_b.height) as f32 / 2.;
+        /* the hallway's own doors face back at the rooms */
+        let ends = [side_a.opposite(), side_b.opposite()];
+        let clamp_len = |len: f32| (len.max(0.) as usize).clamp(HALLWAY_MIN_LENGTH, HALLWAY_MAX_LENGTH).max(width + 4);
+        let (mut x, mut y) = ((room_a.x + room_b.x) / 2., (room_a.y + room_b.y) / 2.);
+        let (box_w, box_h) = if side_a == side_b.opposite() {
+            /* shift toward the smaller room so both ends are the same way off */
+            match side_a {
+                DoorType::Left | DoorType::Right => {
+                    x += (room_b.x - room_a.x).signum() * (room_a.width as f32 - room_b.width as f32) / 4.;
+                    (clamp_len(gap_x), width + 4)
+                }
+                DoorType::Top | DoorType::Bottom => {
+                    y += (room_b.y - room_a.y).signum() * (room_a.height as f32 - room_b.height as f32) / 4.;
+                    (width + 4, clamp_len(gap_y))
+                }
+            }
         } else {
-            None // Return None if indices are out of bounds
+            /* each leg runs from its door to the middle, so twice the leg */
+            let (horizontal, vertical) = match side_a {
+                DoorType::Left | DoorType::Right => (room_a, room_b),
+                DoorType::Top | DoorType::Bottom => (room_b, room_a),
+            };
+            let leg_x = (room_a.x - room_b.x).abs() - horizontal.width as f32 / 2.;
+            let leg_y = (room_a.y - room_b.y).abs() - vertical.height as f32 / 2.;
+            (clamp_len(leg_x * 2.), clamp_len(leg_y * 2.))
+        };
+
+        let mut hallway = LayoutRoom::new(x, y, box_w, box_h);
+        hallway.kind = LayoutKind::Hallway { width: width, ends: ends };
+        let h = self.rooms.len();
+        hallway.doors[ends[0].index()] = Some(RoomId(a));
+        hallway.doors[ends[1].index()] = Some(RoomId(b));
+        self.rooms.push(hallway);
+        self.rooms[a].doors[side_a.index()] = Some(RoomId(h));
+        self.rooms[b].doors[side_b.index()] = Some(RoomId(h));
+        self.links.push((a, h));
+        self.links.push((h, b));
+        Some(h)
+    }
+
+    /* growing mid floor. each room the party walks into for the first
+     * time gets a roll (FloorRules::growth) to sprout one more combat
+     * room straight out of a free side, with a hallway between. the new
+     * slot plus padding and the gap the hallway runs through have to be
+     * empty on the map. never off a hallway or the boss, so the boss
+     * keeps its one way in. returns the new room and its hallway */
+    pub fn grow(&mut self, room: RoomId, map: &DungeonMap, rng: &mut StdRng) -> Option<(RoomId, RoomId)> {
+        let (chance, left) = self.growth;
+        if left == 0 || room == self.boss || self.room(room)?.is_hallway() || rng.gen::<f32>() >= chance {
+            return None;
+        }
+        let width = rng.gen_range(LAYOUT_SLOT_MIN..=LAYOUT_SLOT_MAX);
+        let height = rng.gen_range(LAYOUT_SLOT_MIN..=LAYOUT_SLOT_MAX);
+        let gap = rng.gen_range(HALLWAY_MIN_LENGTH..=HALLWAY_MAX_LENGTH);
+        let across = self.hallway_width + 4;
+        let mut sides: Vec<DoorType> = DoorType::ALL.into_iter()
+            .filter(|side| self.rooms[room.0].doors[side.index()].is_none())
+            .collect();
+        while !sides.is_empty() {
+            let side = sides.swap_remove(rng.gen_range(0..sides.len()));
+            let from = &self.rooms[room.0];
+            /* center to center along the side, and where the gap's middle is */
+            let (reach, half) = match side {
+                DoorType::Left | DoorType::Right => ((from.width + width) as f32 / 2. + gap as f32, from.width as f32 / 2. + gap as f32 / 2.),
+                DoorType::Top | DoorType::Bottom => ((from.height + height) as f32 / 2. + gap as f32, from.height as f32 / 2. + gap as f32 / 2.),
+            };
+            let (dir_x, dir_y) = match side {
+                DoorType::Left => (-1., 0.),
+                DoorType::Right => (1., 0.),
+                DoorType::Bottom => (0., -1.),
+                DoorType::Top => (0., 1.),
+            };
+            let (x, y) = (from.x + dir_x * reach, from.y + dir_y * reach);
+            /* a tile short each end so rounding cant touch either room */
+            let strip = match side {
+                DoorType::Left | DoorType::Right => MapRect::centered(from.x + dir_x * half, from.y, gap - 2, across),
+                DoorType::Top | DoorType::Bottom => MapRect::centered(from.x, from.y + dir_y * half, across, gap - 2),
+            };
+            let slot = MapRect::centered(x, y, width, height).padded(LAYOUT_PADDING as i32);
+            if !map.is_free(slot) || !map.is_free(strip) {
+                continue;
+            }
+            let new = self.rooms.len();
+            self.rooms.push(LayoutRoom::new(x, y, width, height));
+            self.rooms[room.0].doors[side.index()] = Some(RoomId(new));
+            self.rooms[new].doors[side.opposite().index()] = Some(room);
+            let hallway = self.add_hallway(room.0, new)?;
+            self.growth.1 -= 1;
+            return Some((RoomId(new), RoomId(hallway)));
         }
+        None
     }
 
-    pub fn set_current_z_index(&mut self, new_z_index: f32) {
-        self.current_z_index = new_z_index;
+    /* drops anything the spanning tree couldnt reach */
+    fn keep_largest_piece(&mut self) {
+        let mut best: Vec<usize> = Vec::new();
+        let mut seen = vec![false; self.rooms.len()];
+        for room in 0..self.rooms.len() {
+            if seen[room] {
+                continue;
+            }
+            let piece: Vec<usize> = self
+                .distances(RoomId(room))
+                .iter()
+                .enumerate()
+                .filter_map(|(other, dist)| dist.map(|_| other))
+                .collect();
+            for &other in piece.iter() {
+                seen[other] = true;
+            }
+            if piece.len() > best.len() {
+                best = piece;
+            }
+        }
+        if best.len() < self.rooms.len() {
+            println!("layout: dropping {} unreachable rooms", self.rooms.len() - best.len());
+        }
+        self.reorder(&best);
     }
 
-    // Print the 200x200 grid for debugging
-    pub fn print_room_map(&self) {
-        for row in &self.room_map {
-            println!("{:?}", row);
+    /* keeps only the rooms in order, renumbered to their spot in it */
+    fn reorder(&mut self, order: &[usize]) {
+        let mut new_index = vec![None; self.rooms.len()];
+        for (index, &room) in order.iter().enumerate() {
+            new_index[room] = Some(index);
+        }
+        let mut rooms: Vec<LayoutRoom> = order.iter().map(|&room| self.rooms[room].clone()).collect();
+        for room in rooms.iter_mut() {
+            for door in room.doors.iter_mut() {
+                *door = door.and_then(|next| new_index[next.0].map(RoomId));
+            }
         }
+        self.links = self
+            .links
+            .iter()
+            .filter_map(|&(a, b)| Some((new_index[a]?, new_index[b]?)))
+            .collect();
+        self.rooms = rooms;
     }
 
-    pub fn current_room_z_index(&self) -> f32 {
-        self.current_z_index
+    /* doors walked from room to every other room (indexed by RoomId),
+     * None if you cant get there */
+    pub fn distances(&self, from: RoomId) -> Vec<Option<usize>> {
+        let mut dist = vec![None; self.rooms.len()];
+        if from.0 >= self.rooms.len() {
+            return dist;
+        }
+        let mut queue = std::collections::VecDeque::new();
+        dist[from.0] = Some(0);
+        queue.push_back(from);
+        while let Some(room) = queue.pop_front() {
+            for (_, next) in self.neighbors(room) {
+                if dist[next.0].is_none() {
+                    dist[next.0] = Some(dist[room.0].unwrap() + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+        dist
     }
 
-    // Get the Z-index for the next room
-    pub fn next_room_z_index(&mut self) -> f32 {
-        self.global_z_index -= 2.0; // Always decrement the global Z by 2 for a new room
-        self.current_z_index = self.get_global_z_index();
-        self.global_z_index
+    /* doors walked from start, hallways count */
+    pub fn distance_from_start(&self, room: RoomId) -> Option<usize> {
+        self.distances(self.start).get(room.0).copied().flatten()
     }
 
+    /* shortest walk from one room to another, both ends included */
+    pub fn path(&self, from: RoomId, to: RoomId) -> Option<Vec<RoomId>> {
+        if from.0 >= self.rooms.len() || to.0 >= self.rooms.len() {
+            return None;
+        }
+        let mut came_from: Vec<Option<RoomId>> = vec![None; self.rooms.len()];
+        let mut queue = std::collections::VecDeque::new();
+        came_from[from.0] = Some(from);
+        queue.push_back(from);
+        while let Some(room) = queue.pop_front() {
+            if room == to {
+                break;
+            }
+            for (_, next) in self.neighbors(room) {
+                if came_from[next.0].is_none() {
+                    came_from[next.0] = Some(room);
+                    queue.push_back(next);
+                }
+            }
+        }
+        came_from[to.0]?;
+        let mut path = vec![to];
+        while *path.last().unwrap() != from {
+            path.push(came_from[path.last().unwrap().0]?);
+        }
+        path.reverse();
+        Some(path)
+    }
 
-    // Get mutable reference to the current grid
-    pub fn current_grid(&mut self) -> &mut Vec<Vec<u32>> {
-        &mut self.grids[self.current_room]
+    pub fn print_layout(&self) {
+        println!(
+            "layout: {} rooms, {} hallways, boss is room {} ({} doors from start)",
+            self.rooms.iter().filter(|room| !room.is_hallway()).count(),
+            self.rooms.iter().filter(|room| room.is_hallway()).count(),
+            self.boss.0,
+            self.distance_from_start(self.boss).unwrap_or(0),
+        );
+        for (index, room) in self.rooms.iter().enumerate() {
+            let doors: Vec<String> = self.neighbors(RoomId(index)).iter()
+                .map(|(door, next)| format!("{:?} {}", door, next.0))
+                .collect();
+            println!("  room {} at ({:.0}, {:.0}) {}x{} doors [{}] {:?} {:?}",
+                index, room.x, room.y, room.width, room.height, doors.join(", "), room.kind, room.room_type);
+        }
     }
-    
-    // Get the size of the current room (width, height)
-    pub fn current_room_size(&self) -> (f32, f32) {
-        let current_player_z = self.get_current_z_index();
-        if let Some(room) = self.room_array.get_room_from_storage(current_player_z) {
-            let width = room.width as f32 * TILE_SIZE as f32;
-            let height = room.height as f32 * TILE_SIZE as f32;
-            return (width, height);
-        }else{
-            self.room_sizes[self.current_room]
+}
+
+/* shove overlapping slots apart along whichever axis overlaps least */
+fn separate_slots(slots: &mut [LayoutRoom]) {
+    for _ in 0..LAYOUT_SEPARATE_STEPS {
+        let mut moved = false;
+        for a in 0..slots.len() {
+            for b in (a + 1)..slots.len() {
+                let offset = slots[b].center() - slots[a].center();
+                let overlap_x = (slots[a].width + slots[b].width) as f32 / 2. + LAYOUT_PADDING - offset.x.abs();
+                let overlap_y = (slots[a].height + slots[b].height) as f32 / 2. + LAYOUT_PADDING - offset.y.abs();
+                if overlap_x <= 0. || overlap_y <= 0. {
+                    continue;
+                }
+                /* stacked dead on top of each other, pick a way for them */
+                let push_x = if offset.x >= 0. { 1. } else { -1. };
+                let push_y = if offset.y >= 0. { 1. } else { -1. };
+                if overlap_x < overlap_y {
+                    slots[a].x -= push_x * overlap_x / 2.;
+                    slots[b].x += push_x * overlap_x / 2.;
+                } else {
+                    slots[a].y -= push_y * overlap_y / 2.;
+                    slots[b].y += push_y * overlap_y / 2.;
+                }
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
         }
     }
+    /* whole tiles from here on */
+    for slot in slots.iter_mut() {
+        slot.x = slot.x.round();
+        slot.y = slot.y.round();
+    }
+}
 
-    pub fn current_room_max(&self) -> (f32, f32) {
-        self.max_sizes[self.current_room]
+/* bowyer-watson. returns each triangle edge once as (low, high).
+ * under 3 points (or all in a line) everything just connects to everything */
+fn delaunay_edges(points: &[Vec2]) -> Vec<(usize, usize)> {
+    let all_pairs = || -> Vec<(usize, usize)> {
+        (0..points.len()).flat_map(|a| (a + 1..points.len()).map(move |b| (a, b))).collect()
+    };
+    if points.len() < 3 {
+        return all_pairs();
     }
 
-    pub fn add_inner_wall(&mut self, index: usize, wall: InnerWall) {
-        if index < self.inner_wall_list.walls.len() {
-            self.inner_wall_list.walls[index].push(wall);
-        } else {
-            println!("Error: Index {} is out of bounds for InnerWallList.", index);
+    /* big triangle around everything, its corners go at the end of verts */
+    let min = points.iter().fold(Vec2::splat(f32::MAX), |acc, p| acc.min(*p));
+    let max = points.iter().fold(Vec2::splat(f32::MIN), |acc, p| acc.max(*p));
+    let span = (max - min).max_element().max(1.) * 20.;
+    let mid = (min + max) / 2.;
+    let mut verts = points.to_vec();
+    verts.push(mid + Vec2::new(-span, -span));
+    verts.push(mid + Vec2::new(span, -span));
+    verts.push(mid + Vec2::new(0., span));
+    let n = points.len();
+    let mut triangles: Vec<[usize; 3]> = vec![[n, n + 1, n + 2]];
+
+    for p in 0..n {
+        let (bad, good): (Vec<[usize; 3]>, Vec<[usize; 3]>) = triangles
+            .into_iter()
+            .partition(|tri| in_circumcircle(&verts, tri, verts[p]));
+        triangles = good;
+        /* edges of the hole are the ones only one bad triangle has */
+        let mut hole: Vec<(usize, usize)> = Vec::new();
+        for tri in bad.iter() {
+            for (a, b) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+                let shared = bad.iter().filter(|other| other.contains(&a) && other.contains(&b)).count() > 1;
+                if !shared {
+                    hole.push((a, b));
+                }
+            }
+        }
+        for (a, b) in hole {
+            triangles.push([a, b, p]);
         }
     }
 
-    pub fn get_inner_walls(&self, index: usize) -> Option<&Vec<InnerWall>> {
-        self.inner_wall_list.walls.get(index)
+    let mut edges: Vec<(usize, usize)> = Vec::new();
+    for tri in triangles.iter().filter(|tri| tri.iter().all(|&v| v < n)) {
+        for (a, b) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+            let edge = (a.min(b), a.max(b));
+            if !edges.contains(&edge) {
+                edges.push(edge);
+            }
+        }
     }
+    if edges.is_empty() {
+        return all_pairs();
+    }
+    edges
+}
+
+fn in_circumcircle(verts: &[Vec2], tri: &[usize; 3], p: Vec2) -> bool {
+    /* f64, the super triangle makes f32 squares too coarse */
+    let [a, b, c, p] = [verts[tri[0]], verts[tri[1]], verts[tri[2]], p].map(|v| v.as_dvec2());
+    let d = 2. * (a.x * (b.y - c.y) + b.x * (c.y - a.y) + c.x * (a.y - b.y));
+    /* flat triangle, nothing is inside it */
+    if d.abs() < f64::EPSILON {
+        return false;
+    }
+    let center = bevy::math::DVec2::new(
+        (a.length_squared() * (b.y - c.y) + b.length_squared() * (c.y - a.y) + c.length_squared() * (a.y - b.y)) / d,
+        (a.length_squared() * (c.x - b.x) + b.length_squared() * (a.x - c.x) + c.length_squared() * (b.x - a.x)) / d,
+    );
+    p.distance_squared(center) < a.distance_squared(center)
+}
+
+fn find_set(sets: &mut [usize], room: usize) -> usize {
+    let mut root = room;
+    while sets[root] != root {
+        root = sets[root];
+    }
+    sets[room] = root;
+    root
+}
+
+/* lowest numbered room the most doors away, ties go to the lower index */
+fn farthest(dist: &[Option<usize>]) -> usize {
+    let mut best = 0;
+    for (room, d) in dist.iter().enumerate() {
+        if d.unwrap_or(0) > dist[best].unwrap_or(0) {
+            best = room;
+        }
+    }
+    best
 }
 
 
-#[derive(Component, Clone, Debug, Resource)]
+/* where the server looks if --room-config isnt given.
+ * relative to wherever the server was launched from */
+pub const ROOM_CONFIG_PATH: &str = "room_config.ron";
+/* room_config.ron gets rechecked this often (seconds) and swapped in if it changed */
+pub const ROOM_CONFIG_RELOAD_SECS: u64 = 1;
+// create_inner_walls rolls its start 7 tiles in from each side
+pub const ROOM_MIN_SIZE: usize = 16;
+// create_boss_room_walls puts the arena walls out to tile 69
+pub const BOSS_ROOM_MIN_SIZE: usize = 72;
+// enemy types server_spawn_enemies knows how to spawn
+pub const ENEMY_TYPES: (usize, usize) = (1, 5);
+
+#[derive(Component, Clone, Debug, Resource, Serialize, Deserialize)]
 pub struct RoomConfig {
+    /* stealth, neutral, carnage, boss */
     states: Vec<StateConfig>,
+    /* every markov chain rolled per room, the built in seven plus whatever else */
+    #[serde(default = "default_chains")]
+    chains: Vec<MarkovChain>,
+    /* where the boss goes and what has to come before it */
+    #[serde(default = "FloorRules::new")]
+    floor: FloorRules,
+    /* how much tougher every floor down gets */
+    #[serde(default = "DepthScaling::new")]
+    depth: DepthScaling,
+    /* chance a plain combat room rolls a co-op puzzle, only with more
+     * than one player. see teamwork.rs */
+    #[serde(default = "default_teamwork_chance")]
+    teamwork_chance: f32,
+    /* floor the party is on (0 is the first), not in the file. the
+     * enemy getters below come out scaled for it */
+    #[serde(skip)]
+    current_depth: u32,
+}
+
+/* how the dungeon graph has to come out. only read when the layout is
+ * built (server startup), reloading the file doesnt move the boss */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FloorRules {
+    /* rooms walked from start to boss, hallways dont count. (min, max) */
+    pub boss_distance: (usize, usize),
+    /* (type, how many) that have to be closer to start than the boss is */
+    pub before_boss: Vec<(RoomType, usize)>,
+    /* (type, how many) more per floor, anywhere but start and boss */
+    #[serde(default)]
+    pub quotas: Vec<(RoomType, usize)>,
+    /* cut the boss down to one door so there's one way in */
+    pub single_boss_entrance: bool,
+    /* (chance, most rooms) the floor grows past the layout while the
+     * party walks it, rolled once per new room. see DungeonLayout::grow */
+    #[serde(default = "default_growth")]
+    pub growth: (f32, usize),
 }
 
-#[derive(Component, Clone, Debug)]
+impl FloorRules {
+    pub fn new() -> Self {
+        Self {
+            boss_distance: (3, 10),
+            before_boss: vec![(RoomType::Treasure, 1)],
+            quotas: vec![(RoomType::Shop, 1), (RoomType::Challenge, 1), (RoomType::Rest, 1)],
+            single_boss_entrance: true,
+            growth: default_growth(),
+        }
+    }
+
+    /* anything goes, boss ends up the farthest room from start */
+    pub fn none() -> Self {
+        Self {
+            boss_distance: (0, usize::MAX),
+            before_boss: Vec::new(),
+            quotas: Vec::new(),
+            single_boss_entrance: false,
+            growth: (0., 0),
+        }
+    }
+}
+
+fn default_growth() -> (f32, usize) {
+    (0.35, 4)
+}
+
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct StateConfig {
     width_range: (usize, usize),
     height_range: (usize, usize),
@@ -465,8 +1349,24 @@ pub struct StateConfig {
     enemy_count: (usize, usize),
     enemy_type: (usize, usize),
     item_count: (usize, usize),
+    /* clumps of stealth props (see stealth.rs), leave it off for none */
+    #[serde(default)]
+    stealth_props: (usize, usize),
+    /* clumps of traps (see traps.rs), leave it off for none */
+    #[serde(default)]
+    traps: (usize, usize),
+    /* (shape, weight) to pick from, boss arenas stay Rect */
+    #[serde(default = "default_shapes")]
+    shapes: Vec<(RoomShape, f32)>,
 }
 
+fn default_teamwork_chance() -> f32 {
+    0.3
+}
+
+fn default_shapes() -> Vec<(RoomShape, f32)> {
+    vec![(RoomShape::Rect, 1.)]
+}
 
 impl RoomConfig {
     pub fn new() -> Self {
@@ -481,6 +1381,9 @@ impl RoomConfig {
                     enemy_count: (4, 8),
                     enemy_type: (1, 2),
                     item_count: (1, 2),
+                    stealth_props: (5, 8),
+                    traps: (1, 3),
+                    shapes: vec![(RoomShape::Rect, 2.), (RoomShape::Cave, 2.), (RoomShape::L, 1.), (RoomShape::Circle, 1.)],
                 },
                 /* neutral */
                 StateConfig {
@@ -490,6 +1393,9 @@ impl RoomConfig {
                     enemy_count: (6, 10),
                     enemy_type: (1, 4),
                     item_count: (2, 3),
+                    stealth_props: (3, 5),
+                    traps: (2, 4),
+                    shapes: vec![(RoomShape::Rect, 3.), (RoomShape::L, 1.), (RoomShape::T, 1.), (RoomShape::Plus, 1.)],
                 },
                 /* carnage */
                 /* carnage */
@@ -500,6 +1406,9 @@ impl RoomConfig {
                     enemy_count: (5, 12),
                     enemy_type: (3, 4),
                     item_count: (3, 4),
+                    stealth_props: (1, 3),
+                    traps: (4, 7),
+                    shapes: vec![(RoomShape::Rect, 2.), (RoomShape::Circle, 2.), (RoomShape::Plus, 1.), (RoomShape::Cave, 1.)],
                 },
                 StateConfig {
                     width_range: (79, 79),
@@ -508,11 +1417,162 @@ impl RoomConfig {
                     enemy_count: (1, 1),
                     enemy_type: (5, 5),
                     item_count: (4, 4),
+                    stealth_props: (0, 0),
+                    traps: (0, 0),
+                    shapes: default_shapes(),
                 },
             ],
+            chains: default_chains(),
+            floor: FloorRules::new(),
+            depth: DepthScaling::new(),
+            teamwork_chance: default_teamwork_chance(),
+            current_depth: 0,
+        }
+    }
+
+    /* bad or missing file isnt worth killing the server over,
+     * yell and fall back to the defaults */
+    pub fn load(path: &str) -> Self {
+        match Self::read(path) {
+            Ok(config) => {
+                println!("loaded room config from {}", path);
+                config
+            }
+            Err(problems) => {
+                for problem in problems {
+                    println!("{}: {}", path, problem);
+                }
+                println!("using default room config");
+                Self::new()
+            }
+        }
+    }
+
+    /* parsed and checked, or everything wrong with it */
+    pub fn read(path: &str) -> Result<Self, Vec<String>> {
+        let text = fs::read_to_string(path).map_err(|e| vec![format!("couldnt read it: {}", e)])?;
+        let config = ron::from_str::<RoomConfig>(&text).map_err(|e| vec![format!("couldnt parse it: {}", e)])?;
+        let problems = config.validate();
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems)
+        }
+    }
+
+    /* everything room gen would trip over later, in plain words */
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let names = ["stealth", "neutral", "carnage", "boss"];
+        if self.states.len() < names.len() {
+            problems.push(format!("need {} states ({}), found {}", names.len(), names.join(", "), self.states.len()));
+        }
+        for (index, state) in self.states.iter().enumerate() {
+            let name = names.get(index).copied().unwrap_or("extra");
+            let ranges = [
+                ("width_range", state.width_range),
+                ("height_range", state.height_range),
+                ("inner_wall_count", state.inner_wall_count),
+                ("enemy_count", state.enemy_count),
+                ("enemy_type", state.enemy_type),
+                ("item_count", state.item_count),
+                ("stealth_props", state.stealth_props),
+                ("traps", state.traps),
+            ];
+            for (field, (min, max)) in ranges {
+                if min > max {
+                    problems.push(format!("state {} ({}) {} is backwards: ({}, {})", index, name, field, min, max));
+                }
+            }
+            let min_size = if index == 3 { BOSS_ROOM_MIN_SIZE } else { ROOM_MIN_SIZE };
+            if state.width_range.0 < min_size || state.height_range.0 < min_size {
+                problems.push(format!("state {} ({}) rooms must be at least {} tiles each way", index, name, min_size));
+            }
+            if state.enemy_type.0 < ENEMY_TYPES.0 || state.enemy_type.1 > ENEMY_TYPES.1 {
+                problems.push(format!("state {} ({}) enemy_type must stay within {}-{}", index, name, ENEMY_TYPES.0, ENEMY_TYPES.1));
+            }
+            if state.shapes.iter().any(|(_, weight)| *weight < 0.) || state.shapes.iter().map(|(_, weight)| weight).sum::<f32>() <= 0. {
+                problems.push(format!("state {} ({}) shapes need weights of 0 or more with at least one above 0", index, name));
+            }
+            if index == 3 && state.shapes.iter().any(|(shape, weight)| *shape != RoomShape::Rect && *weight > 0.) {
+                problems.push(format!("state {} ({}) can only be Rect, the arena walls are placed for a box", index, name));
+            }
+        }
+
+        for name in BUILT_IN_CHAINS {
+            match self.chains.iter().find(|chain| chain.name == name) {
+                Some(chain) if chain.states.len() != 3 => {
+                    problems.push(format!("chain {} is read by room gen and has to keep 3 states, found {}", name, chain.states.len()));
+                }
+                Some(_) => {}
+                None => problems.push(format!("missing chain {}, room gen needs it", name)),
+            }
+        }
+        for (index, chain) in self.chains.iter().enumerate() {
+            if self.chains[..index].iter().any(|other| other.name == chain.name) {
+                problems.push(format!("chain {} shows up more than once", chain.name));
+            }
+            problems.extend(chain.validate());
+        }
+
+        let (min, max) = self.floor.boss_distance;
+        if min < 1 || min > max {
+            problems.push(format!("floor boss_distance has to be at least 1 and not backwards: ({}, {})", min, max));
+        }
+        if !(0. ..=1.).contains(&self.floor.growth.0) {
+            problems.push(format!("floor growth chance has to be between 0 and 1, not {}", self.floor.growth.0));
+        }
+        if !(0. ..=1.).contains(&self.teamwork_chance) {
+            problems.push(format!("teamwork_chance has to be between 0 and 1, not {}", self.teamwork_chance));
+        }
+        if self.floor.quotas.iter().any(|(room_type, _)| *room_type == RoomType::Combat) {
+            problems.push(String::from("floor quotas cant ask for Combat rooms, every other room already is one"));
+        }
+        problems.extend(self.depth.validate());
+        problems
+    }
+
+    pub fn chains(&self) -> &[MarkovChain] {
+        &self.chains
+    }
+
+    pub fn floor_rules(&self) -> &FloorRules {
+        &self.floor
+    }
+
+    pub fn teamwork_chance(&self) -> f32 {
+        self.teamwork_chance
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.current_depth
+    }
+
+    /* biome of the floor the party is on */
+    pub fn biome(&self) -> Biome {
+        self.depth.biomes[self.current_depth as usize % self.depth.biomes.len()]
+    }
+
+    pub fn set_depth(&mut self, depth: u32) {
+        self.current_depth = depth;
+    }
+
+    pub fn last_floor(&self) -> bool {
+        self.current_depth + 1 >= self.depth.floors
+    }
+
+    pub fn scale_enemy_health(&self, health: Health) -> Health {
+        let scale = DepthScaling::scale(self.depth.enemy_health, self.current_depth);
+        Health {
+            max: health.max * scale,
+            current: health.current * scale,
         }
     }
 
+    pub fn scale_enemy_speed(&self, speed: f32) -> f32 {
+        speed * DepthScaling::scale(self.depth.enemy_speed, self.current_depth)
+    }
+
     pub fn get_width_range(&self, state: u8) -> (usize, usize) {
         self.states.get(state as usize).expect("Invalid state").width_range
     }
@@ -525,16 +1585,82 @@ impl RoomConfig {
         self.states.get(state as usize).expect("Invalid state").inner_wall_count
     }
 
+    /* both ends grow with depth */
     pub fn get_enemy_count(&self, state: u8) -> (usize, usize) {
-        self.states.get(state as usize).expect("Invalid state").enemy_count
+        let (min, max) = self.states.get(state as usize).expect("Invalid state").enemy_count;
+        let scale = DepthScaling::scale(self.depth.enemy_count, self.current_depth);
+        ((min as f32 * scale).round() as usize, (max as f32 * scale).round() as usize)
     }
 
+    /* bottom end creeps up with depth */
     pub fn get_enemy_type(&self, state: u8) -> (usize, usize) {
-        self.states.get(state as usize).expect("Invalid state").enemy_type
+        let (min, max) = self.states.get(state as usize).expect("Invalid state").enemy_type;
+        ((min + self.depth.enemy_type * self.current_depth as usize).min(max), max)
+    }
+
+    pub fn get_item_count(&self, state: u8) -> (usize, usize) {
+        self.states.get(state as usize).expect("Invalid state").item_count
+    }
+
+    pub fn get_stealth_props(&self, state: u8) -> (usize, usize) {
+        self.states.get(state as usize).expect("Invalid state").stealth_props
+    }
+
+    pub fn get_traps(&self, state: u8) -> (usize, usize) {
+        self.states.get(state as usize).expect("Invalid state").traps
+    }
+
+    pub fn get_shapes(&self, state: u8) -> &[(RoomShape, f32)] {
+        &self.states.get(state as usize).expect("Invalid state").shapes
+    }
+}
+
+/* which file RoomConfig came from and when it last changed,
+ * so reload_room_config can tell when someone saves over it */
+#[derive(Resource)]
+pub struct RoomConfigFile {
+    pub path: String,
+    pub modified: Option<SystemTime>,
+}
+
+impl RoomConfigFile {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: String::from(path),
+            modified: modified_time(path),
+        }
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/* server side hot reload. a broken save keeps the old config running
+ * and says whats wrong with the new one. rooms already built keep
+ * whatever they rolled, the next new room uses the new numbers */
+pub fn reload_room_config(
+    mut file: ResMut<RoomConfigFile>,
+    mut room_config: ResMut<RoomConfig>,
+) {
+    let modified = modified_time(&file.path);
+    if modified.is_none() || modified == file.modified {
+        return;
     }
+    file.modified = modified;
 
-    pub fn get_item_count(&self, state: u8) -> (usize, usize) {
-        self.states.get(state as usize).expect("Invalid state").item_count
+    match RoomConfig::read(&file.path) {
+        Ok(mut config) => {
+            config.set_depth(room_config.depth());
+            *room_config = config;
+            println!("reloaded room config from {}", file.path);
+        }
+        Err(problems) => {
+            for problem in problems {
+                println!("{}: {}", file.path, problem);
+            }
+            println!("keeping the old room config");
+        }
     }
 }
 
@@ -543,49 +1669,72 @@ pub fn spawn_items_in_room(
     room_manager: &RoomManager,
     last_attribute_array: &LastAttributeArray,
     room_config: &RoomConfig,
+    dungeon_rng: &DungeonRng,
 ) {
-    // Get room boundaries for spawning
-    let (room_width, room_height) = room_manager.current_room_size();
-    let max_x = (room_width / 2.0) - (TILE_SIZE as f32 * 2.0);
-    let max_y = (room_height / 2.0) - (TILE_SIZE as f32 *2.0);
-    let z_index = room_manager.current_room_z_index();
+    let room = room_manager.current_room_id();
+    let z_index = room.z();
 
-    let mut rng = rand::thread_rng();
+    let mut rng = dungeon_rng.stream(RngStream::Items, room);
 
-    let item_count_attribute_value = last_attribute_array.get_attribute(4).unwrap_or(1);
+    let item_count_attribute_value = last_attribute_array.get_attribute(ITEM_COUNT).unwrap_or(1);
 
     // get spawn range from last attribute
     let num_items_to_spawn_range = room_config.get_item_count(item_count_attribute_value);
 
     // get random count from roon config
-    let potion_count = rng.gen_range(num_items_to_spawn_range.0..=num_items_to_spawn_range.1);
-    let coin_pot_count = rng.gen_range(num_items_to_spawn_range.0..=num_items_to_spawn_range.1);
+    let mut potion_count = rng.gen_range(num_items_to_spawn_range.0..=num_items_to_spawn_range.1);
+    let mut coin_pot_count = rng.gen_range(num_items_to_spawn_range.0..=num_items_to_spawn_range.1);
+
+    match room_manager.current_room_type() {
+        // treasure rooms never come up short
+        RoomType::Treasure => {
+            potion_count = potion_count.max(TREASURE_MIN_ITEMS);
+            coin_pot_count = coin_pot_count.max(TREASURE_MIN_ITEMS);
+        }
+        // the shelves, nothing to smash
+        RoomType::Shop => {
+            potion_count = SHOP_WARES;
+            coin_pot_count = 0;
+        }
+        _ => {}
+    }
+    if room_manager.current_room_type() != RoomType::Combat {
+        println!("room {} is a {:?} room", room.0, room_manager.current_room_type());
+    }
+
+    // only on floor you can walk to from the middle, one thing per tile
+    let grid = room_manager.current_grid_ref();
+    let mut spots = reachable_spots(grid, &room_manager.current_key_tiles(), ITEM_EDGE_MARGIN);
 
     // spawn potions
     for _ in 0..potion_count {
-        // Randomize position within room boundaries
-        let x_position = rng.gen_range(-max_x + TILE_SIZE as f32..max_x - TILE_SIZE as f32);
-        let y_position = rng.gen_range(-max_y + TILE_SIZE as f32..max_y - TILE_SIZE as f32);
+        let Some(spot) = take_spot(grid, &mut spots, &mut rng) else {
+            println!("room {} ran out of open floor for potions", room.0);
+            break;
+        };
 
         // Spawn the potion
         commands.spawn((
-            Transform::from_xyz(x_position, y_position, z_index + 0.1),
+            Transform::from_xyz(spot.x, spot.y, z_index + 0.1),
             Potion,
+            Room,
         ));
     }
 
 
     for _ in 0..coin_pot_count {
-        // Randomize position within room boundaries
-        let x_position = rng.gen_range(-max_x + TILE_SIZE as f32..max_x - TILE_SIZE as f32);
-        let y_position = rng.gen_range(-max_y + TILE_SIZE as f32..max_y - TILE_SIZE as f32);
+        let Some(spot) = take_spot(grid, &mut spots, &mut rng) else {
+            println!("room {} ran out of open floor for pots", room.0);
+            break;
+        };
 
         // Spawn the coin pot
         commands.spawn((
-            Transform::from_xyz(x_position, y_position, z_index + 0.1),
+            Transform::from_xyz(spot.x, spot.y, z_index + 0.1),
             Pot{
                 touch: 0
-            }
+            },
+            Room,
         ));
     }
 }
@@ -594,59 +1743,46 @@ pub fn spawn_items_in_room(
 #[derive(Component)]
 pub struct Room;
 
+/* one markov step for every chain. each walks its own rows (keyed on
+ * what it rolled before) skewed by the party's carnage, see
+ * LastAttributeArray::roll. boss rooms skip the dice */
+pub fn roll_room_attributes(
+    last_attribute_array: &mut LastAttributeArray,
+    carnage_percent: f32,
+    boss: bool,
+    room_config: &RoomConfig,
+    rng: &mut StdRng,
+) {
+    last_attribute_array.roll(room_config.chains(), carnage_percent, boss, rng);
+}
+
+/* debug readout so you can watch the carnage bar push rooms around */
+pub fn log_room_roll(room: RoomId, last_attribute_array: &LastAttributeArray) {
+    let rolled: Vec<String> = last_attribute_array.labels().iter()
+        .map(|(chain, state)| format!("{} {}", chain, state))
+        .collect();
+    println!("room {} @ carnage {:.2}: {}", room.0, last_attribute_array.carnage, rolled.join(" | "));
+}
+
 pub fn spawn_start_room(
     commands: &mut Commands, 
     room_manager: &mut RoomManager,
     carnage_percent: f32,
     last_attribute_array: &mut LastAttributeArray,
     room_config: &RoomConfig,
+    dungeon_rng: &DungeonRng,
 ) {
-    // repeat for rest
-    let mut rng = rand::thread_rng();
-
-    // initialize the next attributes array
-    let mut next_attribute_array = NextAttributeArray::new();
-
-
-    // iterate through each attribute
-    for i in 0..5 {
-        // map index to Room_Attributes enum
-        let room_attribute = match i {
-            0 => Room_Attributes::Room_Size,
-            1 => Room_Attributes::Inner_Walls,
-            2 => Room_Attributes::Enemy_Count,
-            3 => Room_Attributes::Enemy_Type,
-            4 => Room_Attributes::Item_Count,
-            _ => panic!("Invalid attribute index!"),
-        };
-
-        // get the last attribute value (1)
-        let last_attribute_value = last_attribute_array.get_attribute(i).unwrap_or(1);
-
-        // retrieve the corresponding matrix for the attribute
-        let base_matrix = Room_Attributes::get_matrix_for_attribute(&room_attribute);
-
-        // apply skew to the matrix
-        let skewed_matrix = Skew_Row(base_matrix, carnage_percent, last_attribute_value as usize);
-
-        let rand_percent: f32 = rng.gen_range(0.0..1.0);
-
-        let next_state = if rand_percent < skewed_matrix[0] {
-            //stealth state
-            0
-        } else if rand_percent < skewed_matrix[1] {
-            //normal
-            1
-        } else {
-            //carnage
-            2
-        };
+    // layout start room, the layout has to be generated before this
+    let start = room_manager.layout.start;
+    room_manager.set_current_room(start);
 
-        next_attribute_array.set_next_attribute(i, next_state);
-    }
+    // repeat for rest
+    let mut rng = dungeon_rng.stream(RngStream::Layout, start);
+    let mut wall_rng = dungeon_rng.stream(RngStream::Walls, start);
 
-    // copy values from next attribute array into last attribute array
-    last_attribute_array.attributes = next_attribute_array.attributes;
+    // MARKOV CHAIN, start room is always 40x40 but the rest follows the party
+    roll_room_attributes(last_attribute_array, carnage_percent, false, room_config, &mut rng);
+    log_room_roll(start, last_attribute_array);
 
     // Room width & height as a multiple of 32
     // * 32d = pixel count
@@ -666,18 +1802,8 @@ pub fn spawn_start_room(
     let z_index = room_manager.current_room_z_index();
 
     // ADD THIS TO FIX SPAWN ROOM
-    room_manager.room_array.add_room_to_storage(z_index as f32, random_width as usize, random_height as usize);
-
-    // add start room to map at a random position
-    room_manager.add_start_room_to_map(z_index as i32, random_width as usize, random_height as usize);
-
-
-    // find the bounds of the start room and print them
-    if let Some(_room) = room_manager.find_room_bounds(z_index as i32) {
-        //println!("Start room bounds: Left: {}, Right: {}, Top: {}, Bottom: {}", left_x, right_x, top_y, bottom_y);
-    } else {
-        println!("Error: Could not find bounds for the start room.");
-    }
+    let grid = room_manager.current_room;
+    room_manager.room_array.add_room_to_storage(start, random_width as usize, random_height as usize, grid);
 
     // offset for spawning tiles
     let mut x_offset = -max_x + ((TILE_SIZE / 2) as f32);
@@ -738,16 +1864,16 @@ pub fn spawn_start_room(
     }
     // GET WALL COUNT FROM MARKOV CHAIN
 
-    let wall_count_attribute_value = last_attribute_array.get_attribute(1).unwrap_or(1);
+    let wall_count_attribute_value = last_attribute_array.get_attribute(INNER_WALLS).unwrap_or(1);
 
     // get spawn range from last attribute
-    let num_walls_spawn_range = room_config.get_item_count(wall_count_attribute_value);
+    let num_walls_spawn_range = room_config.get_inner_wall_count(wall_count_attribute_value);
 
     // get random count from roon config
-    let inner_wall_count = rng.gen_range(num_walls_spawn_range.0..=num_walls_spawn_range.1);
+    let inner_wall_count = wall_rng.gen_range(num_walls_spawn_range.0..=num_walls_spawn_range.1);
 
     for _ in 0..inner_wall_count {
-        create_inner_walls(commands, room_manager, random_width, random_height, z_index as isize);
+        create_inner_walls(commands, room_manager, random_width, random_height, &mut wall_rng);
     }
 
     // end new fn
@@ -756,25 +1882,22 @@ pub fn spawn_start_room(
         room_manager,
         max_x,
         max_y,
-        z_index,
+        start,
     );
 
-    spawn_items_in_room(commands, &room_manager, &last_attribute_array, &room_config);
+    spawn_items_in_room(commands, &room_manager, &last_attribute_array, &room_config, dungeon_rng);
+    roll_stealth_props(room_manager, &last_attribute_array, &room_config, dungeon_rng);
+    roll_decor(room_manager, &room_config, dungeon_rng);
 
 }
 
 
 
-fn create_inner_walls(
-    commands: &mut Commands, 
-    room_manager: &mut RoomManager,
+fn roll_inner_wall(
     room_width: usize,
     room_height: usize,
-    z_index: isize,
-){
-    let z_abs = z_index.abs() as usize;
-    let mut rng = rand::thread_rng();
-    
+    rng: &mut StdRng,
+) -> Option<InnerWall> {
     let mid_point_x = room_width / 2;
     let mid_point_y = room_height / 2;
 
@@ -793,13 +1916,11 @@ fn create_inner_walls(
         }
     };
 
-    // Determine if the wall will be horizontal or vertical
     // Determine if the wall will be horizontal or vertical
     let horizon_or_vert = rng.gen_range(0..=1);
 
     // HORIZONTAL WALL
-    let wall = if horizon_or_vert == 0 {
-        // Get wall length
+    if horizon_or_vert == 0 {
         // Get wall length
         let wall_length = rng.gen_range(3..=(room_width / 2) - 1);
 
@@ -810,25 +1931,21 @@ fn create_inner_walls(
             (wall_length as i32, 1)
         };
 
-        // Create a new inner wall
-
         // Ensure the wall does not overlap the midpoint
         if (start_pos_x as i32 + length_direction_vector.0).abs() == mid_point_x as i32 {
-            return;
+            return None;
         }
 
         // Create a new inner wall
-        InnerWall {
+        Some(InnerWall {
             start_pos: InnerWallStartPos { x: start_pos_x, y: start_pos_y },
             length_direction_vector,
-        }
+        })
     } 
     // VERTICAL WALL
     else {
-
         let wall_height = rng.gen_range(3..=(room_height / 2) - 1);
 
-
         // Determine length direction vector avoiding the midpoint
         let length_direction_vector = if start_pos_y >= mid_point_y {
             (1, -(wall_height as i32))
@@ -838,38 +1955,137 @@ fn create_inner_walls(
 
         // Ensure the wall does not overlap the midpoint
         if (start_pos_y as i32 + length_direction_vector.1).abs() == mid_point_y as i32 {
-            return;
-        }
-
-        // Create a new inner wall
-
-        // Ensure the wall does not overlap the midpoint
-        if (start_pos_y as i32 + length_direction_vector.1).abs() == mid_point_y as i32 {
-            return;
+            return None;
         }
 
         // Create a new inner wall
-        InnerWall {
+        Some(InnerWall {
             start_pos: InnerWallStartPos { x: start_pos_x, y: start_pos_y },
             length_direction_vector,
+        })
+    }
+}
+
+/* rolls one random inner wall into the current room. walls that would
+ * cut off a door, an entry spot or the middle get rerolled, and after
+ * WALL_PLACEMENT_TRIES we just go without */
+fn create_inner_walls(
+    commands: &mut Commands, 
+    room_manager: &mut RoomManager,
+    room_width: usize,
+    room_height: usize,
+    rng: &mut StdRng,
+){
+    let room = room_manager.current_room_id();
+    let keys = room_manager.current_key_tiles();
+
+    for _ in 0..WALL_PLACEMENT_TRIES {
+        let Some(wall) = roll_inner_wall(room_width, room_height, rng) else {
+            continue;
+        };
+        if !wall_keeps_room_open(room_manager.current_grid_ref(), &wall, &keys) {
+            continue;
         }
-    };
 
-    // Add inner wall to inner wall list
+        // Add inner wall to inner wall list, only the new one needs drawing
+        room_manager.add_inner_wall(room, wall.clone());
+        draw_inner_wall(commands, &wall, room.z(), room_width, room_height, room_manager);
+        return;
+    }
+    println!("no inner wall fit in room {} without sealing something off, skipping it", room.0);
+}
 
-    // Add inner wall to inner wall list
-    room_manager.add_inner_wall(z_abs, wall);
+/* fills everything outside `shape` with inner walls */
+fn carve_room_shape(
+    commands: &mut Commands,
+    room_manager: &mut RoomManager,
+    shape: RoomShape,
+    room_width: usize,
+    room_height: usize,
+    rng: &mut StdRng,
+) {
+    let room = room_manager.current_room_id();
+    println!("room {} is shaped {:?}", room.0, shape);
+    let mask = floor_mask(shape, room_width, room_height, &room_manager.current_doors(), rng);
+    for wall in solid_runs(1, 1, room_width - 1, room_height - 1, |x, y| !mask[x][y]) {
+        room_manager.add_inner_wall(room, wall.clone());
+        draw_inner_wall(commands, &wall, room.z(), room_width, room_height, room_manager);
+    }
+}
 
-    // Loop through inner wall list at current z index
-    if let Some(walls) = room_manager.get_inner_walls(z_abs) {
-        let walls_to_draw: Vec<_> = walls.clone(); // Clone to avoid mutable borrowing issues
-        for wall in walls_to_draw.iter() {
-            draw_inner_wall(commands, wall, z_abs, room_width, room_height, room_manager);
+/* stamps a few prefab chunks for this inner_walls state into the
+ * current room. each gets WALL_PLACEMENT_TRIES spots to land on clear
+ * floor without sealing anything off, otherwise it's left out */
+fn stamp_chunks(
+    commands: &mut Commands,
+    room_manager: &mut RoomManager,
+    state: &str,
+    room_width: usize,
+    room_height: usize,
+    rng: &mut StdRng,
+) -> RoomMarkers {
+    let room = room_manager.current_room_id();
+    let mut markers = RoomMarkers::new();
+    let chunks: Vec<Prefab> = room_manager.prefabs.pick_chunks(state, rng).into_iter().cloned().collect();
+    let keys = room_manager.current_key_tiles();
+
+    for chunk in chunks {
+        let (width, height) = (chunk.width(), chunk.height());
+        if width + 2 * CHUNK_EDGE_MARGIN > room_width || height + 2 * CHUNK_EDGE_MARGIN > room_height {
+            continue;
+        }
+        let mut placed = false;
+        for _ in 0..WALL_PLACEMENT_TRIES {
+            let x = rng.gen_range(CHUNK_EDGE_MARGIN..=room_width - CHUNK_EDGE_MARGIN - width);
+            let y = rng.gen_range(CHUNK_EDGE_MARGIN..=room_height - CHUNK_EDGE_MARGIN - height);
+            // only onto open floor, never over another wall
+            let grid = room_manager.current_grid_ref();
+            let clear = (0..width).all(|tile_x| (0..height).all(|tile_y| {
+                chunk.tile(tile_x, tile_y) == '~' || grid[x + tile_x][y + tile_y] == 0
+            }));
+            let walls = chunk.walls(x, y);
+            let mut chunk_keys = keys.clone();
+            chunk_keys.extend(chunk.marker_tiles(x, y));
+            if !clear || !walls_keep_room_open(grid, &walls, &chunk_keys) {
+                continue;
+            }
+            let chunk_markers = chunk.markers(grid, x, y);
+            for wall in walls {
+                room_manager.add_inner_wall(room, wall.clone());
+                draw_inner_wall(commands, &wall, room.z(), room_width, room_height, room_manager);
+            }
+            markers.enemies.extend(chunk_markers.enemies);
+            markers.potions.extend(chunk_markers.potions);
+            markers.pots.extend(chunk_markers.pots);
+            placed = true;
+            break;
+        }
+        if !placed {
+            println!("chunk {} didnt fit in room {}, leaving it out", chunk.name, room.0);
         }
-    } else {
-        println!("No inner walls found for Z index {}", z_abs);
     }
+    markers
+}
 
+/* potions and pots a prefab drew in. only on the first visit, same as
+ * random items they dont come back once picked up */
+fn spawn_marker_items(commands: &mut Commands, markers: &RoomMarkers, z_index: f32) {
+    for spot in markers.potions.iter() {
+        commands.spawn((
+            Transform::from_xyz(spot.x, spot.y, z_index + 0.1),
+            Potion,
+            Room,
+        ));
+    }
+    for spot in markers.pots.iter() {
+        commands.spawn((
+            Transform::from_xyz(spot.x, spot.y, z_index + 0.1),
+            Pot{
+                touch: 0
+            },
+            Room,
+        ));
+    }
 }
 
 fn create_boss_room_walls(
@@ -877,9 +2093,9 @@ fn create_boss_room_walls(
     room_manager: &mut RoomManager,
     room_width: usize,
     room_height: usize,
-    z_index: isize,
 ){
-    let z_abs = z_index.abs() as usize;
+    let room = room_manager.current_room_id();
+    let mut boss_walls = Vec::new();
     let mut start_pos_x = 10;
     let mut start_pos_y = 20;
     let wall_height = 45 as usize;
@@ -895,7 +2111,7 @@ fn create_boss_room_walls(
         start_pos: InnerWallStartPos { x: start_pos_x, y: start_pos_y },
         length_direction_vector,
     };
-    room_manager.add_inner_wall(z_abs, wall);
+    boss_walls.push(wall);
 
     start_pos_x = 69; //hehe
     start_pos_y = 20;
@@ -909,7 +2125,7 @@ fn create_boss_room_walls(
         start_pos: InnerWallStartPos { x: start_pos_x, y: start_pos_y },
         length_direction_vector,
     };
-    room_manager.add_inner_wall(z_abs, wall);
+    boss_walls.push(wall);
 
     start_pos_x = 11;
     start_pos_y = 19; //execute order 
@@ -925,7 +2141,7 @@ fn create_boss_room_walls(
         start_pos: InnerWallStartPos { x: start_pos_x, y: start_pos_y },
         length_direction_vector,
     };
-    room_manager.add_inner_wall(z_abs, wall);
+    boss_walls.push(wall);
 
     start_pos_x = 68;
     start_pos_y = 19; //execute order 
@@ -941,18 +2157,19 @@ fn create_boss_room_walls(
         start_pos: InnerWallStartPos { x: start_pos_x, y: start_pos_y },
         length_direction_vector,
     };
-    room_manager.add_inner_wall(z_abs, wall);
+    boss_walls.push(wall);
 
 
 
-    // loop through inner wall list at current z index
-    if let Some(walls) = room_manager.get_inner_walls(z_abs) {
-        let walls_to_draw: Vec<_> = walls.clone(); // Clone to avoid mutable borrowing issues
-        for wall in walls_to_draw.iter() {
-            draw_inner_wall(commands, wall, z_abs, room_width, room_height, room_manager);
+    // hand placed, but still dont let one wall off a door
+    let keys = room_manager.current_key_tiles();
+    for wall in boss_walls {
+        if !wall_keeps_room_open(room_manager.current_grid_ref(), &wall, &keys) {
+            println!("boss wall {:?} would seal off part of room {}, leaving it out", wall.start_pos, room.0);
+            continue;
         }
-    } else {
-        println!("No inner walls found for Z index {}", z_abs);
+        room_manager.add_inner_wall(room, wall.clone());
+        draw_inner_wall(commands, &wall, room.z(), room_width, room_height, room_manager);
     }
 }
 
@@ -960,10 +2177,10 @@ fn create_boss_room_walls(
 
 
 
-fn draw_inner_wall(
+pub fn draw_inner_wall(
     commands: &mut Commands,
     inner_wall: &InnerWall,
-    z_index: usize,
+    z_index: f32,
     room_width: usize,
     room_height: usize,
     room_manager: &mut RoomManager,
@@ -984,15 +2201,11 @@ fn draw_inner_wall(
 
             while current_x <= end_value {
                 commands.spawn((
-                    Transform::from_xyz(current_x, current_y, z_index as f32),
+                    Transform::from_xyz(current_x, current_y, z_index),
                     Wall,
                     Room,
                     inner_wall.clone(),
                 ));
-                 // call set_collide for this wall segment
-                 let grid_x = ((current_x + (room_width as f32 * TILE_SIZE as f32) / 2.0) / TILE_SIZE as f32).floor() as usize;
-                 let grid_y = ((current_y + (room_height as f32 * TILE_SIZE as f32) / 2.0) / TILE_SIZE as f32).floor() as usize;
-                 set_collide(room_manager, grid_x, grid_y, 1);
 
                 current_x += TILE_SIZE as f32;
             }
@@ -1004,15 +2217,11 @@ fn draw_inner_wall(
 
             while current_x >= end_value {
                 commands.spawn((
-                    Transform::from_xyz(current_x, current_y, z_index as f32),
+                    Transform::from_xyz(current_x, current_y, z_index),
                     Wall,
                     Room,
                     inner_wall.clone(),
                 ));
-                 // Call set_collide for this wall segment
-                 let grid_x = ((current_x + (room_width as f32 * TILE_SIZE as f32) / 2.0) / TILE_SIZE as f32).floor() as usize;
-                 let grid_y = ((current_y + (room_height as f32 * TILE_SIZE as f32) / 2.0) / TILE_SIZE as f32).floor() as usize;
-                 set_collide(room_manager, grid_x, grid_y, 1);
 
                 current_x -= TILE_SIZE as f32;
             }
@@ -1030,16 +2239,11 @@ fn draw_inner_wall(
 
             while current_y <= end_value {
                 commands.spawn((
-                    Transform::from_xyz(current_x, current_y, z_index as f32),
+                    Transform::from_xyz(current_x, current_y, z_index),
                     Wall,
                     Room,
                     inner_wall.clone(),
                 ));
-                // Call set_collide for this wall segment
-                let grid_x = ((current_x + (room_width as f32 * TILE_SIZE as f32) / 2.0) / TILE_SIZE as f32).floor() as usize;
-                let grid_y = ((current_y + (room_height as f32 * TILE_SIZE as f32) / 2.0) / TILE_SIZE as f32).floor() as usize;
-                set_collide(room_manager, grid_x, grid_y, 1);
- 
 
                 current_y += TILE_SIZE as f32;
             }
@@ -1051,27 +2255,27 @@ fn draw_inner_wall(
 
             while current_y >= end_value {
                 commands.spawn((
-                    Transform::from_xyz(current_x, current_y, z_index as f32),
+                    Transform::from_xyz(current_x, current_y, z_index),
                     Wall,
                     Room,
                     inner_wall.clone(),
                 ));
-                // Call set_collide for this wall segment
-                let grid_x = ((current_x + (room_width as f32 * TILE_SIZE as f32) / 2.0) / TILE_SIZE as f32).floor() as usize;
-                let grid_y = ((current_y + (room_height as f32 * TILE_SIZE as f32) / 2.0) / TILE_SIZE as f32).floor() as usize;
-                set_collide(room_manager, grid_x, grid_y, 1);
- 
 
                 current_y -= TILE_SIZE as f32;
             }
         }
     }
+
+    // mark it in the room grid, set_collide wants pixels from the bottom left
+    for (tile_x, tile_y) in wall_tiles(inner_wall) {
+        set_collide(room_manager, tile_x * TILE_SIZE as usize, tile_y * TILE_SIZE as usize, 1);
+    }
 }
 
 fn regen_draw_inner_wall(
     commands: &mut Commands,
     inner_wall: &InnerWall,
-    z_index: usize,
+    z_index: f32,
     room_width: usize,
     room_height: usize,
 ){
@@ -1090,7 +2294,7 @@ fn regen_draw_inner_wall(
 
             while current_x <= end_value {
                 commands.spawn((
-                    Transform::from_xyz(current_x, current_y, z_index as f32),
+                    Transform::from_xyz(current_x, current_y, z_index),
                     Wall,
                     Room,
                 ));
@@ -1103,7 +2307,7 @@ fn regen_draw_inner_wall(
 
             while current_x >= end_value {
                 commands.spawn((
-                    Transform::from_xyz(current_x, current_y, z_index as f32),
+                    Transform::from_xyz(current_x, current_y, z_index),
                     Wall,
                     Room,
                 ));
@@ -1123,7 +2327,7 @@ fn regen_draw_inner_wall(
 
             while current_y <= end_value {
                 commands.spawn((
-                    Transform::from_xyz(current_x, current_y, z_index as f32),
+                    Transform::from_xyz(current_x, current_y, z_index),
                     Wall,
                     Room,
                 ));
@@ -1136,7 +2340,7 @@ fn regen_draw_inner_wall(
 
             while current_y >= end_value {
                 commands.spawn((
-                    Transform::from_xyz(current_x, current_y, z_index as f32),
+                    Transform::from_xyz(current_x, current_y, z_index),
                     Wall,
                     Room,
                 ));
@@ -1204,251 +2408,113 @@ fn generate_walls_and_floors(
 }
 
 /// Generates doors for the room and sets up their collisions.
-// take in the correct door type
+/// one door per neighbor the layout gave this room
 fn generate_doors(
     commands: &mut Commands,
     room_manager: &mut RoomManager,
     max_x: f32,
     max_y: f32,
-    z_index: f32,
+    room: RoomId,
 ) {
-    println!("generating doors - checking bounds of current room: {}", z_index);
-    if let Some((left_x, right_x, top_y, bottom_y)) = room_manager.find_room_bounds(z_index as i32) {
-        let height = bottom_y - top_y;
-        let width = right_x - left_x;
-        let half_height = height / 2;
-        let half_width = width / 2;
-
-        println!("found width: {} height: {} for current room", width, height);
-
-
-        // LEFT DOOR
-        if room_manager.get_room_value(left_x - 1, top_y + half_height) != Some(1) {
-            // Left door
-            let door_left_x = -max_x + (3.0 * TILE_SIZE as f32 / 2.0) - TILE_SIZE as f32;
-            let door_left_y = TILE_SIZE as f32 / 2.0;
-            commands.spawn((
-                Transform::from_xyz(door_left_x, door_left_y, z_index + 0.1),
-                Door {
-                    next: Some(room_manager.global_z_index),
-                    door_type: DoorType::Left,
-                },
-                Room,
-            ));
-            let xcoord_left = ((-max_x * 2.0 + (3.0 * TILE_SIZE as f32 / 2.0)) - TILE_SIZE as f32) as usize;
-            let ycoord_left = (door_left_y + max_y) as usize;
-            set_collide(room_manager, xcoord_left, ycoord_left, 2);
-
-        } else if (left_x as i32 - 81 > 0) && ((top_y + half_height) as i32 + 41 < 400) && ((bottom_y - half_height) as i32 - 41 > 0) {
-             // Left door
-             let door_left_x = -max_x + (3.0 * TILE_SIZE as f32 / 2.0) - TILE_SIZE as f32;
-             let door_left_y = TILE_SIZE as f32 / 2.0;
-             commands.spawn((
-                Transform::from_xyz(door_left_x, door_left_y, z_index + 0.1),
-                Door {
-                     next: Some(room_manager.global_z_index),
-                     door_type: DoorType::Left,
-                },
-                Room,
-             ));
-            let xcoord_left = ((-max_x * 2.0 + (3.0 * TILE_SIZE as f32 / 2.0)) - TILE_SIZE as f32) as usize;
-            let ycoord_left = (door_left_y + max_y) as usize;
-            set_collide(room_manager, xcoord_left, ycoord_left, 2);
-        }
-      
-
-        // RIGHT DOOR
-        if room_manager.get_room_value(right_x + 1, top_y + half_height) != Some(1) {
-            // Right door
-            let door_x = max_x - (3.0 * (TILE_SIZE as f32) / 2.0) + TILE_SIZE as f32;
-            let door_y = TILE_SIZE as f32 / 2.0;  
-            commands.spawn((
-                Transform::from_xyz(door_x, door_y, z_index + 0.1),
-                Door {
-                    next: Some(room_manager.global_z_index),
-                    door_type: DoorType::Right,
-                },
-                Room,
-            ));
-            
-            let xcoord_right = ((max_x * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) + TILE_SIZE as f32) as usize;
-            let ycoord_right = (door_y + max_y) as usize;
-            set_collide(room_manager, xcoord_right, ycoord_right, 2);
-        } 
-        else if (right_x as i32 + 81 < 400) && ((top_y + half_height) as i32 + 41 < 400) && (bottom_y - half_height) as i32 - 41 > 0
-        {
-             // Right door
-             let door_x = max_x - (3.0 * (TILE_SIZE as f32) / 2.0) + TILE_SIZE as f32;
-             let door_y = TILE_SIZE as f32 / 2.0;  
-             commands.spawn((
-                Transform::from_xyz(door_x, door_y, z_index + 0.1),
-                Door {
-                     next: Some(room_manager.global_z_index),
-                     door_type: DoorType::Right,
-                },
-                Room,
-             ));
-             
-             let xcoord_right = ((max_x * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) + TILE_SIZE as f32) as usize;
-             let ycoord_right = (door_y + max_y) as usize;
-             set_collide(room_manager, xcoord_right, ycoord_right, 2);
-        }
-
-        // TOP DOOR
-        if room_manager.get_room_value(left_x + half_width, top_y - 1) != Some(1) {
-             // Top door
-             let door_top_x = TILE_SIZE as f32 / 2.0;
-             let door_top_y = max_y - (3.0 * TILE_SIZE as f32 / 2.0) + TILE_SIZE as f32;
-             commands.spawn((
-                Transform::from_xyz(door_top_x, door_top_y, z_index + 0.1),
-                
-                 Door {
-                     next: Some(room_manager.global_z_index),
-                     door_type: DoorType::Top,
-                 },
-                 Room,
-             ));
- 
-             let xcoord_top = (door_top_x + max_x) as usize;
-             let ycoord_top = ((max_y * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) + TILE_SIZE as f32) as usize;
-             set_collide(room_manager, xcoord_top, ycoord_top, 2);
-        }
-        else if top_y as i32 - 81 > 0 && ((left_x + half_width) as i32 + 41 < 400) && (right_x - half_width) as i32 - 41> 0{
-            // Top door
-            let door_top_x = TILE_SIZE as f32 / 2.0;
-            let door_top_y = max_y - (3.0 * TILE_SIZE as f32 / 2.0) + TILE_SIZE as f32;
-            commands.spawn((
-                Transform::from_xyz(door_top_x, door_top_y, z_index + 0.1),
-               
-                Door {
-                    next: Some(room_manager.global_z_index),
-                    door_type: DoorType::Top,
-                },
-                Room,
-            ));
+    println!("generating doors - checking layout for room: {}", room.0);
+    let z_index = room.z();
 
-            let xcoord_top = (door_top_x + max_x) as usize;
-            let ycoord_top = ((max_y * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) + TILE_SIZE as f32) as usize;
-            set_collide(room_manager, xcoord_top, ycoord_top, 2); 
+    for (door_type, next) in room_manager.layout.neighbors(room) {
+        if room_manager.map.room_bounds(next).is_none() {
+            println!("room {} isnt on the dungeon map, no {:?} door", next.0, door_type);
+            continue;
         }
+        // door position, then its collision cell
+        let (door_x, door_y, collide_x, collide_y) = match door_type {
+            DoorType::Left => {
+                let door_x = -max_x + (3.0 * TILE_SIZE as f32 / 2.0) - TILE_SIZE as f32;
+                let door_y = TILE_SIZE as f32 / 2.0;
+                (door_x, door_y,
+                    ((-max_x * 2.0 + (3.0 * TILE_SIZE as f32 / 2.0)) - TILE_SIZE as f32) as usize,
+                    (door_y + max_y) as usize)
+            }
+            DoorType::Right => {
+                let door_x = max_x - (3.0 * (TILE_SIZE as f32) / 2.0) + TILE_SIZE as f32;
+                let door_y = TILE_SIZE as f32 / 2.0;
+                (door_x, door_y,
+                    ((max_x * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) + TILE_SIZE as f32) as usize,
+                    (door_y + max_y) as usize)
+            }
+            DoorType::Top => {
+                let door_x = TILE_SIZE as f32 / 2.0;
+                let door_y = max_y - (3.0 * TILE_SIZE as f32 / 2.0) + TILE_SIZE as f32;
+                (door_x, door_y,
+                    (door_x + max_x) as usize,
+                    ((max_y * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) + TILE_SIZE as f32) as usize)
+            }
+            DoorType::Bottom => {
+                let door_x = TILE_SIZE as f32 / 2.0;
+                let door_y = -max_y + (3.0 * TILE_SIZE as f32 / 2.0) - TILE_SIZE as f32;
+                (door_x, door_y,
+                    (door_x + max_x) as usize,
+                    ((-max_y * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) - TILE_SIZE as f32) as usize)
+            }
+        };
 
-        // BOTTOM DOOR
-        if room_manager.get_room_value(left_x + half_width, bottom_y + 1) != Some(1) {
-            // Bottom door
-            let door_bottom_x = TILE_SIZE as f32 / 2.0;
-            let door_bottom_y = -max_y + (3.0 * TILE_SIZE as f32 / 2.0) - TILE_SIZE as f32;
-            commands.spawn((
-                Transform::from_xyz(door_bottom_x, door_bottom_y, z_index + 0.1),
-                
-                Door {
-                    next: Some(room_manager.global_z_index),
-                    door_type: DoorType::Bottom,
-                },
-                Room,
-            ));
-            let xcoord_bottom = (door_bottom_x + max_x) as usize;
-            let ycoord_bottom = ((-max_y * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) - TILE_SIZE as f32) as usize;
-            set_collide(room_manager, xcoord_bottom, ycoord_bottom, 2);
-        } else if bottom_y as i32 + 81 < 400 && ((left_x + half_width) as i32 + 41 < 400) && (right_x - half_width) as i32 - 41 > 0{
-            // Bottom door
-            let door_bottom_x = TILE_SIZE as f32 / 2.0;
-            let door_bottom_y = -max_y + (3.0 * TILE_SIZE as f32 / 2.0) - TILE_SIZE as f32;
-            commands.spawn((
-                Transform::from_xyz(door_bottom_x, door_bottom_y, z_index + 0.1),
-                
-                Door {
-                    next: Some(room_manager.global_z_index),
-                    door_type: DoorType::Bottom,
-                },
-                Room,
-            ));
-            let xcoord_bottom = (door_bottom_x + max_x) as usize;
-            let ycoord_bottom = ((-max_y * 2.0 - (3.0 * TILE_SIZE as f32 / 2.0)) - TILE_SIZE as f32) as usize;
-            set_collide(room_manager, xcoord_bottom, ycoord_bottom, 2);
-        }
+        commands.spawn((
+            Transform::from_xyz(door_x, door_y, z_index + 0.1),
+            Door {
+                next: Some(next),
+                door_type: door_type,
+            },
+            Room,
+        ));
+        set_collide(room_manager, collide_x, collide_y, 2);
     }
 }
 
+/* builds layout room `room` for the first time */
 pub fn generate_random_room_with_bounds(
     commands: &mut Commands, 
     room_manager: &mut RoomManager,
-    carnage_query: &mut Query<&mut CarnageBar>, 
+    room: RoomId,
+    carnage_percent: f32, 
     last_attribute_array: &mut LastAttributeArray, 
     room_config: &RoomConfig,
+    dungeon_rng: &DungeonRng,
 ) -> (usize,usize, f32, f32, f32) {
-    let mut rng = rand::thread_rng();
-
-    let mut next_attribute_array = NextAttributeArray::new();
-
-    // GET CARNAGE PERCENT FROM UI VALUE
-    
-    let carnage_percent: f32 = 0.;
-    for carnage in carnage_query.iter(){
-        carnage.get_overall_percentage();
-    }
-    // Determine the next state for each attribute
-    let mut next_state: u8 = 0;
-
-    let z_index = room_manager.get_global_z_index() - 2.;
-    println!("Z INDEX FOR CHECKING BOSS ROOM: {}", z_index);
-
-    // MARKOV CHAIN
-    // iterate through each attribute
-    for i in 0..5 {
-        // map index to Room_Attributes enum
-        let room_attribute = match i {
-            0 => Room_Attributes::Room_Size,
-            1 => Room_Attributes::Inner_Walls,
-            2 => Room_Attributes::Enemy_Count,
-            3 => Room_Attributes::Enemy_Type,
-            4 => Room_Attributes::Item_Count,
-            _ => panic!("Invalid attribute index!"),
-        };
-
-        // get the last attribute value (1)
-        let last_attribute_value = last_attribute_array.get_attribute(i).unwrap_or(1);
-
-        // retrieve the corresponding matrix for the attribute
-        let base_matrix = Room_Attributes::get_matrix_for_attribute(&room_attribute);
-
-
-        // apply skew to the matrix
-        let skewed_matrix = Skew_Row(base_matrix, carnage_percent, last_attribute_value as usize);
-
-        let rand_percent: f32 = rng.gen_range(0.0..1.0);
-
-        // determine next state
-        if rand_percent < skewed_matrix[0] {
-            //stealth state
-            next_state = 0;
-        } else if rand_percent < skewed_matrix[1] {
-            //normal
-            next_state = 1;
-        } else {
-            //carnage
-            next_state = 2;
-        };
-
-        // boss room state
-        if z_index == -10.{
-            next_state = 3;
-            println!("overriding");
-        }
-
-
-        next_attribute_array.set_next_attribute(i, next_state);
-    }
-
-    // copy values from next attribute array into last attribute array
-    last_attribute_array.attributes = next_attribute_array.attributes;
+    let slot = room_manager.layout.rooms[room.0].clone();
+    let boss = room == room_manager.layout.boss;
+    let z_index = room.z();
+    println!("generating layout room {} (z {}), boss: {}", room.0, z_index, boss);
+    let mut rng = dungeon_rng.stream(RngStream::Layout, room);
+    let mut wall_rng = dungeon_rng.stream(RngStream::Walls, room);
+    let mut prefab_rng = dungeon_rng.stream(RngStream::Prefabs, room);
+    let mut shape_rng = dungeon_rng.stream(RngStream::Shapes, room);
+
+    // MARKOV CHAIN, carnage_percent is the party value (see PartyCarnage)
+    roll_room_attributes(last_attribute_array, carnage_percent, boss, room_config, &mut rng);
+    log_room_roll(room, last_attribute_array);
+    let size_state = last_attribute_array.get_attribute(ROOM_SIZE).unwrap_or(1);
 
     // ROOM SIZE RANGE
-    let width_range = room_config.get_width_range(next_state);
-    let height_range = room_config.get_height_range(next_state);
+    let width_range = room_config.get_width_range(size_state);
+    let height_range = room_config.get_height_range(size_state);
+
+    // generate random size within the bounds, squeezed into the layout slot.
+    // boss arena walls are hand placed for its size so it keeps it
+    let mut random_width = rng.gen_range(width_range.0..=width_range.1);
+    let mut random_height = rng.gen_range(height_range.0..=height_range.1);
+    if !boss {
+        random_width = random_width.min(slot.width);
+        random_height = random_height.min(slot.height);
+    }
 
-    // generate random size within the bounds
-    let random_width = rng.gen_range(width_range.0..=width_range.1);
-    let random_height = rng.gen_range(height_range.0..=height_range.1);
+    // maybe a whole hand drawn room instead, it brings its own size
+    let size_label = last_attribute_array.find(ROOM_SIZE).map(|attribute| attribute.label.clone()).unwrap_or_default();
+    let doors: Vec<DoorType> = DoorType::ALL.into_iter().filter(|door| slot.doors[door.index()].is_some()).collect();
+    let (fit_width, fit_height) = if boss { (usize::MAX, usize::MAX) } else { (slot.width, slot.height) };
+    let template = room_manager.prefabs.pick_room(&size_label, &doors, fit_width, fit_height, &mut prefab_rng).cloned();
+    if let Some(prefab) = template.as_ref() {
+        println!("room {} is prefab {}", room.0, prefab.name);
+        random_width = prefab.width();
+        random_height = prefab.height();
+    }
 
 
     let room_width = random_width as f32 * TILE_SIZE as f32;  
@@ -1461,18 +2527,12 @@ pub fn generate_random_room_with_bounds(
     // add the room to the room manager
     room_manager.add_room(random_width, random_height, room_width, room_height);
 
-    // get z-index for this room
-    let z_index = room_manager.next_room_z_index();
+    // this room is where we are now
+    room_manager.set_current_room(room);
 
     // add room to rooms array
-    room_manager.room_array.add_room_to_storage(z_index, random_width, random_height);
-    
-
-    // GET CURRENT Z
-    let current_z_index = room_manager.current_z_index;
-
-    // global Z
-    let global_z_index = room_manager.get_global_z_index();
+    let grid = room_manager.current_room;
+    room_manager.room_array.add_room_to_storage(room, random_width, random_height, grid);
 
     // Generate walls and floors
     generate_walls_and_floors(
@@ -1481,40 +2541,134 @@ pub fn generate_random_room_with_bounds(
         random_height as f32,
         max_x as f32,
         max_y as f32,
-        current_z_index,
+        z_index,
     );
 
-    let wall_count = rng.gen_range(1..=3);
+    let wall_count_range = room_config.get_inner_wall_count(last_attribute_array.get_attribute(INNER_WALLS).unwrap_or(1));
+    let wall_count = wall_rng.gen_range(wall_count_range.0..=wall_count_range.1);
     
-    for _ in 0..wall_count {
-        if last_attribute_array.get_attribute(0).unwrap() != 3 {
-            // Create inner walls for non-boss rooms
-            create_inner_walls(commands, room_manager, random_width, random_height, global_z_index as isize);
-        } else {
-            // Create walls specific to boss rooms
-            create_boss_room_walls(commands, room_manager, random_width, random_height, global_z_index as isize);
+    let markers = if let Some(prefab) = template {
+        // prefab walls already leave every socket reachable, checked on load
+        for wall in prefab.walls(0, 0) {
+            room_manager.add_inner_wall(room, wall.clone());
+            draw_inner_wall(commands, &wall, z_index, random_width, random_height, room_manager);
+        }
+        prefab.markers(room_manager.current_grid_ref(), 0, 0)
+    } else if last_attribute_array.get_attribute(ROOM_SIZE) != Some(BOSS_STATE) {
+        // shape first so the random walls and chunks only go where there's floor
+        let shape = roll_shape(room_config.get_shapes(size_state), &mut shape_rng);
+        if shape != RoomShape::Rect {
+            carve_room_shape(commands, room_manager, shape, random_width, random_height, &mut shape_rng);
+        }
+        // Create inner walls for non-boss rooms
+        for _ in 0..wall_count {
+            create_inner_walls(commands, room_manager, random_width, random_height, &mut wall_rng);
         }
+        let wall_label = last_attribute_array.find(INNER_WALLS).map(|attribute| attribute.label.clone()).unwrap_or_default();
+        stamp_chunks(commands, room_manager, &wall_label, random_width, random_height, &mut prefab_rng)
+    } else {
+        // Create walls specific to boss rooms, the arena only needs building once
+        create_boss_room_walls(commands, room_manager, random_width, random_height);
+        RoomMarkers::new()
+    };
+    spawn_marker_items(commands, &markers, z_index);
+    room_manager.set_markers(room, markers);
+
+    // plain combat rooms only get what their markers put down, shops and
+    // treasure rooms stock up like the start room does
+    if matches!(slot.room_type, RoomType::Treasure | RoomType::Shop) {
+        spawn_items_in_room(commands, room_manager, last_attribute_array, room_config, dungeon_rng);
     }
+    roll_stealth_props(room_manager, last_attribute_array, room_config, dungeon_rng);
+    roll_traps(room_manager, last_attribute_array, room_config, dungeon_rng);
+    roll_decor(room_manager, room_config, dungeon_rng);
+
+   return (random_width, random_height, max_x as f32, max_y as f32, z_index);
+}
+
 
-   return (random_width, random_height, max_x as f32, max_y as f32, current_z_index);
+/* one inner wall per run of solid tiles in each row of x0..x1, y0..y1
+ * (see draw_inner_wall for the +1/+2). hallways and prefabs both
+ * turn tile pictures into walls with it */
+pub fn solid_runs(x0: usize, y0: usize, x1: usize, y1: usize, is_wall: impl Fn(usize, usize) -> bool) -> Vec<InnerWall> {
+    let mut walls = Vec::new();
+    for y in y0..y1 {
+        let mut x = x0;
+        while x < x1 {
+            if !is_wall(x, y) {
+                x += 1;
+                continue;
+            }
+            let run_start = x;
+            while x < x1 && is_wall(x, y) {
+                x += 1;
+            }
+            walls.push(InnerWall {
+                start_pos: InnerWallStartPos { x: run_start + 1, y: y + 1 },
+                length_direction_vector: ((x - run_start) as i32 + 2, 1),
+            });
+        }
+    }
+    walls
 }
 
+/* builds layout hallway `room` for the first time. no markov roll, the
+ * box gets walls + floor like any room and everything off the corridor
+ * is filled with inner walls so it draws, collides and regens for free */
+pub fn generate_hallway(
+    commands: &mut Commands,
+    room_manager: &mut RoomManager,
+    room: RoomId,
+) -> (usize, usize, f32, f32, f32) {
+    let hallway = room_manager.layout.rooms[room.0].clone();
+    let z_index = room.z();
+    println!("generating hallway {} (z {}) {:?}", room.0, z_index, hallway.kind);
+
+    let (width, height) = (hallway.width, hallway.height);
+    let room_width = width as f32 * TILE_SIZE as f32;
+    let room_height = height as f32 * TILE_SIZE as f32;
+    let max_x = room_width / 2.;
+    let max_y = room_height / 2.;
+
+    room_manager.add_room(width, height, room_width, room_height);
+    room_manager.set_current_room(room);
+    let grid = room_manager.current_room;
+    room_manager.room_array.add_room_to_storage(room, width, height, grid);
+
+    generate_walls_and_floors(commands, room_width, room_height, max_x, max_y, z_index);
+
+    for wall in solid_runs(1, 1, width - 1, height - 1, |x, y| !hallway.is_floor(x, y)) {
+        room_manager.add_inner_wall(room, wall);
+    }
+    if let Some(walls) = room_manager.get_inner_walls(room) {
+        let walls_to_draw: Vec<_> = walls.clone();
+        for wall in walls_to_draw.iter() {
+            draw_inner_wall(commands, wall, z_index, width, height, room_manager);
+        }
+    }
+
+    (width, height, max_x, max_y, z_index)
+}
 
 pub fn regenerate_existing_room(
     commands: &mut Commands, 
     room_manager: &mut RoomManager,
     width: usize,
     height: usize,
-    z_for_regen: f32,
+    room: RoomId,
 ) {
-    let z_abs = z_for_regen.abs() as usize;
+    let z_index = room.z();
     // Manually calculate the room width and height in pixels
     let room_width = width as f32 * TILE_SIZE as f32;
     let room_height = height as f32 * TILE_SIZE as f32;
     let max_x = room_width / 2.0;
     let max_y = room_height / 2.0;
 
-    room_manager.set_current_z_index(z_for_regen);
+    room_manager.set_current_room(room);
+    // point back at this room's grid so collisions and spawns use the right one
+    if let Some(dimensions) = room_manager.room_array.get_room_from_storage(room) {
+        room_manager.current_room = dimensions.grid;
+    }
 
     // Generate walls and floors
     generate_walls_and_floors(
@@ -1523,7 +2677,7 @@ pub fn regenerate_existing_room(
         room_height,
         max_x,
         max_y,
-        z_for_regen,
+        z_index,
     );
 
     generate_doors(
@@ -1531,392 +2685,97 @@ pub fn regenerate_existing_room(
         room_manager,
         max_x,
         max_y,
-        z_for_regen,
+        room,
     );
 
     // retrieve and spawn inner walls for the current room from `InnerWallList`
-    if let Some(walls) = room_manager.get_inner_walls(z_abs as usize) {
+    if let Some(walls) = room_manager.get_inner_walls(room) {
         let walls_to_draw: Vec<_> = walls.clone(); // Clone walls to a temporary variable
         for wall in walls_to_draw.iter() {
-            draw_inner_wall(commands, wall, z_abs, width, height, room_manager);
+            draw_inner_wall(commands, wall, z_index, width, height, room_manager);
         }
     } else {
-        println!("No inner walls found for Z index {}", z_abs);
+        println!("No inner walls found for room {}", room.0);
     }
 }
 
-/* transitions room to room. I have made this an amalgamous mess. Lots going
- * on here, we despawn old room, check which door we're on to generate a new room,
- * and send out player packets to let the youngins know where they should be */
+/* transitions room to room. despawn the old room, look up who is behind
+ * the door in the layout, build it (or rebuild it if we've been there)
+ * and drop the players just inside the door they came through */
 pub fn transition_map(
     commands: &mut Commands,
     room_manager: &mut RoomManager,
     room_query: &mut Query<Entity, With<Room>>, 
     door_type: DoorType, 
-    mut carnage_query: &mut Query<&mut CarnageBar>, 
+    carnage_percent: f32, 
     last_attribute_array: &mut LastAttributeArray, 
     room_config: &RoomConfig,
     player : &mut Query<(&mut Transform), With<Player>>,
+    dungeon_rng: &DungeonRng,
 ) {
-    // println!("global z: {}", room_manager.global_z_index);
-    // if room_manager.global_z_index > 20.0 {
-    //     println!("SPAWN BOSS BWAHAHAHAHA");
-    //     return;
-    // }
-    let mut right_x_out = 0;
-    let mut left_x_out = 0;
-    let mut top_y_out = 0;
-    let mut bottom_y_out = 0;
+    let here = room_manager.current_room_id();
+    let Some(next) = room_manager.layout.neighbor(here, door_type) else {
+        println!("Error: nothing behind the {:?} door of room {}", door_type, here.0);
+        return;
+    };
 
     // Despawn old room
     for entity in room_query.iter_mut() {
         commands.entity(entity).despawn();
     }
-    
-    // get room bounds of current room
-    // get room bounds of current room
-    let z_in: f32 = room_manager.get_current_z_index();
-    println!("checking bounds of current room: {} ", z_in);
-    if let Some((left_x, right_x, top_y, bottom_y)) = room_manager.find_room_bounds(z_in as i32) {
-        right_x_out = right_x;
-        left_x_out = left_x;
-        top_y_out = top_y;
-        bottom_y_out = bottom_y;
-    } else {
-        println!("Error: Could not find bounds for the current room. {}", z_in);
-    }
-
-    // Adjust the player's position based on the door they entered
-    match door_type {
-        DoorType::Right => {
-            // get new z index
-            let x_to_check = right_x_out + 1;
-            let y_to_check =(top_y_out + bottom_y_out) / 2;
-            let room_val = room_manager.get_room_value(x_to_check, y_to_check);
-            if room_val == Some(1) {
-                
-                
-                // generate the room with random bounds
-                let (room_width, room_height, max_x, max_y, _z_index) = generate_random_room_with_bounds(
-                    commands,
-                    room_manager,
-                    &mut carnage_query, 
-                    last_attribute_array, 
-                    &room_config,
-                );
-                
-                let new_z_index: f32 = room_manager.get_global_z_index();
-                let current_z = room_manager.get_current_z_index()+2.;
-
-                // add new room to map relative to current room top door
-                room_manager.add_room_to_map_from_right_door(
-                    current_z as i32,
-                    new_z_index as i32,
-                    room_width as usize,
-                    room_height as usize,
-                );
-
-                println!("global z: {}", room_manager.global_z_index);
-                if room_manager.global_z_index < -8.0{
-                    println!("SPAWN BOSS BWAHAHAHAHA");
-                    return;
-                }
 
-                // generate doors
-                generate_doors(
-                    commands,
-                    room_manager,
-                    max_x as f32,
-                    max_y as f32,
-                    new_z_index,
-                );
-
-                for mut transform in player.iter_mut(){
-                    /* *4.0 for not standing on door immesdiately */
-                    transform.translation = Vec3::new(-max_x + (TILE_SIZE as f32 * 4.0), TILE_SIZE as f32 / 2.0, room_manager.current_z_index);
-                }
-            }else{
-                if let Some(room_val_unwrapped) = room_val {
-                    println!("found existing room val: {}", room_val_unwrapped);
-                    // room_manager.room_array.get_room_from_storage(room_val_unwrapped as f32);
-                    if let Some(room_dimensions) = room_manager.room_array.get_room_from_storage(room_val_unwrapped as f32) {
-                        let width = room_dimensions.width;
-                        let height = room_dimensions.height;
-                        let max_x = room_dimensions.width as f32 / 2.0;
-                        let max_y = room_dimensions.height as f32 / 2.0;
-
-                        //generate room with set bounds
-                        regenerate_existing_room(
-                            commands,
-                            room_manager,
-                            width as usize,
-                            height as usize,
-                            room_val_unwrapped as f32,
-                        );
-
-                        println!("hit right door into existing room into room: {}", room_val_unwrapped);
-                        // spawn at left door
-                        for mut transform in player.iter_mut(){
-                            transform.translation = Vec3::new(-(max_x * TILE_SIZE as f32) +( TILE_SIZE as f32 * 4.0), TILE_SIZE as f32 / 2.0, room_val_unwrapped as f32);
-                        }
-                    } else {
-                        println!("Error: Room not found in storage.");
-                    }
-                } else {
-                    println!("Error: room_val is None, cannot retrieve room dimensions.");
-                }
-                // room_manager.room_array.get_room_from_storage(room_val);
-            }
-        },
-        DoorType::Left => {
-
-            let x_to_check = left_x_out - 1;
-            let y_to_check =(top_y_out + bottom_y_out) / 2;
-            let room_val = room_manager.get_room_value(x_to_check,y_to_check);
-            if room_val == Some(1) {
-                
-                // generate the room with random bounds
-                let (room_width, room_height, max_x, max_y, _z_index) = generate_random_room_with_bounds(
-                    commands,
-                    room_manager,
-                    &mut carnage_query, 
-                    last_attribute_array, 
-                    &room_config,
-                );
-                
-                let new_z_index: f32 = room_manager.get_global_z_index();
-                let current_z = room_manager.get_current_z_index()+2.;
-
-                // add new room to map relative to current room top door
-                room_manager.add_room_to_map_from_left_door(
-                    current_z as i32,
-                    new_z_index as i32,
-                    room_width as usize,
-                    room_height as usize,
-                );
-
-                println!("global z: {}", room_manager.global_z_index);
-                if room_manager.global_z_index < -8.0{
-                    println!("SPAWN BOSS BWAHAHAHAHA");
-                    return;
-                }
-
-
-                // generate doors
-                generate_doors(
-                    commands,
-                    room_manager,
-                    max_x as f32,
-                    max_y as f32,
-                    new_z_index,
-                );
-
-
-                println!("hit left door into new room: {}", new_z_index);
-                // Spawn the player a little away from the right door
-                for mut transform in player.iter_mut(){
-                    transform.translation = Vec3::new(max_x - (TILE_SIZE as f32 * 4.0), TILE_SIZE as f32 / 2.0, room_manager.current_z_index);
-                }
-            }else{
-                if let Some(room_val_unwrapped) = room_val {
-                    // room_manager.room_array.get_room_from_storage(room_val_unwrapped as f32);
-                    if let Some(room_dimensions) = room_manager.room_array.get_room_from_storage(room_val_unwrapped as f32) {
-                        let width = room_dimensions.width;
-                        let height = room_dimensions.height;
-                        let max_x = room_dimensions.width as f32 / 2.0;
-                        let max_y = room_dimensions.height as f32 / 2.0;
-
-                        regenerate_existing_room(
-                            commands,
-                            room_manager,
-                            width as usize,
-                            height as usize,
-                            room_val_unwrapped as f32,
-                        );
-
-                        println!("hit left door into existing room into room: {}", room_val_unwrapped);
-                        for mut transform in player.iter_mut(){
-                            transform.translation = Vec3::new((max_x* TILE_SIZE as f32) - TILE_SIZE as f32 * 4.0, TILE_SIZE as f32 / 2.0, room_manager.current_z_index);
-                        }
-                    } else {
-                        println!("Error: Room not found in storage.");
-                    }
-                } else {
-                    println!("Error: room_val is None, cannot retrieve room dimensions.");
-                }
-                // room_manager.room_array.get_room_from_storage(room_val);
-            }
-
-            // Spawn the player a little away from the right door
-            
-        },
-        DoorType::Top => {
-            let x_to_check = (left_x_out + right_x_out) / 2;
-            let y_to_check = top_y_out - 1;
-            let room_val = room_manager.get_room_value(x_to_check,y_to_check);
-            if room_val == Some(1) {
-                
-                
-                // generate the room with random bounds
-                let (room_width, room_height, max_x, max_y, _z_index) = generate_random_room_with_bounds(
-                    commands,
-                    room_manager,
-                    &mut carnage_query, 
-                    last_attribute_array, 
-                    &room_config,
-                );
-                
-                let new_z_index: f32 = room_manager.get_global_z_index();
-                let current_z = room_manager.get_current_z_index()+2.;
-
-                // add new room to map relative to current room top door
-                room_manager.add_room_to_map_from_top_door(
-                    current_z as i32,
-                    new_z_index as i32,
-                    room_width as usize,
-                    room_height as usize,
-                );
-
-
-                println!("global z: {}", room_manager.global_z_index);
-                if room_manager.global_z_index < -8.0{
-                    println!("SPAWN BOSS BWAHAHAHAHA");
-                    return;
-                }
-            
-
-                // generate doors
-                generate_doors(
-                    commands,
-                    room_manager,
-                    max_x as f32,
-                    max_y as f32,
-                    new_z_index,
-                );
-
-                println!("hit top door into new room: {}", new_z_index);
-                // Spawn the player a little away from the bottom door
-                for mut transform in player.iter_mut(){
-                    transform.translation = Vec3::new(TILE_SIZE as f32 / 2.0, -max_y + TILE_SIZE as f32 * 4.0, room_manager.current_z_index);
-                }
-            }else{
-                if let Some(room_val_unwrapped) = room_val {
-                    println!("found existing room val: {}", room_val_unwrapped);
-                    if let Some(room_dimensions) = room_manager.room_array.get_room_from_storage(room_val_unwrapped as f32) {
-                        let width = room_dimensions.width;
-                        let height = room_dimensions.height;
-                        let max_x = room_dimensions.width as f32 / 2.0;
-                        let max_y = room_dimensions.height as f32 / 2.0;
-    
-                        regenerate_existing_room(
-                            commands,
-                            room_manager,
-                            width as usize,
-                            height as usize,
-                            room_val_unwrapped as f32,
-                        );
-
-                        println!("hit top door into existing room into room: {}", room_val_unwrapped);
-                        for mut transform in player.iter_mut(){
-                            transform.translation = Vec3::new(TILE_SIZE as f32 / 2.0 , -(max_y * TILE_SIZE as f32) + TILE_SIZE as f32 * 4.0, room_manager.current_z_index);
-                        }
-                    } else {
-                        println!("Error: Room not found in storage.");
-                    }
-                } else {
-                    println!("Error: room_val is None, cannot retrieve room dimensions.");
-                }
-                // room_manager.room_array.get_room_from_storage(room_val);
-            }
+    // pixel half sizes of wherever we end up
+    let (max_x, max_y) = if let Some(room_dimensions) = room_manager.room_array.get_room_from_storage_in_pixels(next) {
+        let max_x = room_dimensions.width as f32 / 2.0;
+        let max_y = room_dimensions.height as f32 / 2.0;
+
+        //generate room with set bounds
+        regenerate_existing_room(
+            commands,
+            room_manager,
+            room_dimensions.width / TILE_SIZE as usize,
+            room_dimensions.height / TILE_SIZE as usize,
+            next,
+        );
+        println!("hit {:?} door into existing room: {}", door_type, next.0);
+        (max_x, max_y)
+    } else {
+        // first time in, the dungeon might grow a room off this one
+        room_manager.grow_layout(next, &mut dungeon_rng.stream(RngStream::Growth, next));
 
-            // Spawn the player a little below the top door
-            //pt.translation = Vec3::new(TILE_SIZE as f32 / 2.0, -max_y + TILE_SIZE as f32 * 2.0, room_manager.current_z_index);
-
-
-        },
-        DoorType::Bottom => { 
-            let x_to_check = (left_x_out + right_x_out) / 2;
-            let y_to_check = bottom_y_out + 1;
-            let room_val = room_manager.get_room_value(x_to_check,y_to_check);
-            println!("checking x: {} checking y: {} got room val: {}", x_to_check, y_to_check, room_val.unwrap());
-            if room_val == Some(1) {
-                
-                // generate the room with random bounds
-                let (room_width, room_height, max_x, max_y, _z_index) = generate_random_room_with_bounds(
-                    commands,
-                    room_manager,
-                    &mut carnage_query, 
-                    last_attribute_array, 
-                    &room_config,
-                );
-                
-                let new_z_index: f32 = room_manager.get_global_z_index();
-                let current_z = room_manager.get_current_z_index()+2.;
-
-                println!("room width going into map: {}, height: {}", room_width, room_height);
-                // add new room to map relative to current room top door
-                room_manager.add_room_to_map_from_bottom_door(
-                    current_z as i32,
-                    new_z_index as i32,
-                    room_width as usize,
-                    room_height as usize,
-                );
-
-                println!("global z: {}", room_manager.global_z_index);
-                if room_manager.global_z_index < -8.0{
-                    println!("SPAWN BOSS BWAHAHAHAHA");
-                    return;
-                }
+        // generate the room with random bounds, hallways are fixed by the layout
+        let (_room_width, _room_height, max_x, max_y, _z_index) = if room_manager.layout.rooms[next.0].is_hallway() {
+            generate_hallway(commands, room_manager, next)
+        } else {
+            generate_random_room_with_bounds(
+                commands,
+                room_manager,
+                next,
+                carnage_percent, 
+                last_attribute_array, 
+                &room_config,
+                dungeon_rng,
+            )
+        };
 
-                // generate doors
-                generate_doors(
-                    commands,
-                    room_manager,
-                    max_x as f32,
-                    max_y as f32,
-                    new_z_index,
-                );
-
-               // spawn_items_in_room(commands, &room_manager, &last_attribute_array, &room_config);
-
-                println!("hit bottom door into new room: {}", new_z_index);
-                // Spawn the player a little away from the right door
-                for mut transform in player.iter_mut(){
-                    transform.translation = Vec3::new(TILE_SIZE as f32 / 2.0 , max_y - TILE_SIZE as f32 * 4.0, room_manager.current_z_index);
-                }
-            }else{
-                if let Some(room_val_unwrapped) = room_val {
-                    println!("found existing room val: {}", room_val_unwrapped);
-                    if let Some(room_dimensions) = room_manager.room_array.get_room_from_storage(room_val_unwrapped as f32) {
-                        let width = room_dimensions.width;
-                        let height = room_dimensions.height;
-                        let max_x = room_dimensions.width as f32 / 2.0;
-                        let max_y = room_dimensions.height as f32 / 2.0;
-
-                        regenerate_existing_room(
-                            commands,
-                            room_manager,
-                            width as usize,
-                            height as usize,
-                            room_val_unwrapped as f32,
-                        );
-                    
-                        println!("hit bottom door into existing room into room: {}", room_val_unwrapped);
-                        for mut transform in player.iter_mut(){
-                            transform.translation = Vec3::new(TILE_SIZE as f32 / 2.0, (max_y * TILE_SIZE as f32)- TILE_SIZE as f32 * 4.0, room_manager.current_z_index);
-                        }
-                    } else {
-                        println!("Error: Room not found in storage.");
-                    }
-                } else {
-                    println!("Error: room_val is None, cannot retrieve room dimensions.");
-                }
-                // room_manager.room_array.get_room_from_storage(room_val);
-            }
+        // generate doors
+        generate_doors(
+            commands,
+            room_manager,
+            max_x,
+            max_y,
+            next,
+        );
+        println!("hit {:?} door into new room: {}", door_type, next.0);
+        (max_x, max_y)
+    };
 
-            // Spawn the player a little above the bottom door
-            //pt.translation = Vec3::new(TILE_SIZE as f32 / 2.0, max_y - TILE_SIZE as f32 * 2.0, room_manager.current_z_index);
-        },
+    /* just inside whichever door leads back where we came from,
+     * *4.0 for not standing on it immediately */
+    let arrival = room_manager.layout.door_to(next, here).unwrap_or(door_type.opposite());
+    let entry = inside_door(arrival, max_x, max_y, 4.0).extend(next.z());
+    for mut transform in player.iter_mut(){
+        transform.translation = entry;
     }
 }
 
@@ -1941,4 +2800,104 @@ pub fn translate_coords_to_grid(aabb: &Aabb, room_manager: &mut RoomManager) ->
     let bottomright = current_grid[arr_x_max as usize][arr_y_min as usize];
 
     (topleft, topright, bottomleft, bottomright)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    const SEEDS: [u64; 8] = [1, 2, 3, 42, 99, 1234, 5150, 90210];
+
+    #[test]
+    fn layouts_are_connected() {
+        for seed in SEEDS {
+            let mut rng = DungeonRng::new(seed).dungeon();
+            let layout = DungeonLayout::generate(&mut rng, HALLWAY_WIDTH, &FloorRules::new());
+            let from_start = layout.distances(layout.start);
+            assert!(from_start.iter().all(|dist| dist.is_some()), "seed {} left a room cut off", seed);
+            /* single_boss_entrance is on by default */
+            assert_eq!(layout.neighbors(layout.boss).len(), 1, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn separated_slots_dont_overlap() {
+        for seed in SEEDS {
+            let mut rng = StdRng::seed_from_u64(seed);
+            /* everything piled on the middle, worst case for the pushing */
+            let mut slots: Vec<LayoutRoom> = (0..LAYOUT_CANDIDATES)
+                .map(|_| LayoutRoom::new(
+                    rng.gen_range(-4.0..4.0),
+                    rng.gen_range(-4.0..4.0),
+                    rng.gen_range(LAYOUT_SLOT_MIN..=LAYOUT_SLOT_MAX),
+                    rng.gen_range(LAYOUT_SLOT_MIN..=LAYOUT_SLOT_MAX),
+                ))
+                .collect();
+            separate_slots(&mut slots);
+            for a in 0..slots.len() {
+                for b in (a + 1)..slots.len() {
+                    let offset = slots[b].center() - slots[a].center();
+                    let clear_x = offset.x.abs() >= (slots[a].width + slots[b].width) as f32 / 2.;
+                    let clear_y = offset.y.abs() >= (slots[a].height + slots[b].height) as f32 / 2.;
+                    assert!(clear_x || clear_y, "seed {}: slots {} and {} overlap", seed, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tree_and_loops_link_every_room() {
+        for seed in SEEDS {
+            let mut rng = DungeonRng::new(seed).dungeon();
+            let layout = DungeonLayout::generate_rooms(&mut rng);
+            assert!(layout.rooms.len() >= 2, "seed {}", seed);
+            for (index, room) in layout.rooms.iter().enumerate() {
+                assert!(room.doors.iter().any(|door| door.is_some()), "seed {}: room {} has no doors", seed, index);
+                assert!(layout.links.iter().any(|&(a, b)| a == index || b == index), "seed {}: room {} never linked", seed, index);
+            }
+            /* every link is a door on both ends */
+            for &(a, b) in layout.links.iter() {
+                assert!(layout.door_to(RoomId(a), RoomId(b)).is_some() && layout.door_to(RoomId(b), RoomId(a)).is_some());
+            }
+            assert!(layout.distances(RoomId(0)).iter().all(|dist| dist.is_some()), "seed {}", seed);
+        }
+    }
+    #[test]
+    fn grown_rooms_land_on_free_map_and_never_touch_the_boss() {
+        let mut grown = 0;
+        for seed in SEEDS {
+            let dungeon_rng = DungeonRng::new(seed);
+            let mut rules = FloorRules::new();
+            rules.growth = (1., 3);
+            let mut room_manager = RoomManager::new();
+            room_manager.layout = DungeonLayout::generate(&mut dungeon_rng.dungeon(), HALLWAY_WIDTH, &rules);
+            room_manager.add_layout_to_map();
+            let boss_doors = room_manager.layout.neighbors(room_manager.layout.boss);
+            let before = room_manager.layout.rooms.len();
+
+            for room in 0..before {
+                let mut rng = dungeon_rng.stream(RngStream::Growth, RoomId(room));
+                let map = room_manager.map.clone();
+                let Some((new, hallway)) = room_manager.layout.grow(RoomId(room), &map, &mut rng) else {
+                    continue;
+                };
+                let slot = &room_manager.layout.rooms[new.0];
+                assert!(map.is_free(MapRect::centered(slot.x, slot.y, slot.width, slot.height)), "seed {}: room {} grew onto the map", seed, new.0);
+                assert!(room_manager.layout.rooms[hallway.0].is_hallway());
+                assert_eq!(room_manager.layout.room_beyond(RoomId(room), room_manager.layout.door_to(RoomId(room), hallway).unwrap()), Some(new));
+                room_manager.draw_on_map(new);
+                room_manager.draw_on_map(hallway);
+                grown += 1;
+            }
+
+            /* 3 rooms at most, each with a hallway */
+            assert!(room_manager.layout.rooms.len() <= before + 3 * 2, "seed {}", seed);
+            assert_eq!(room_manager.layout.neighbors(room_manager.layout.boss), boss_doors, "seed {}", seed);
+            let from_start = room_manager.layout.distances(room_manager.layout.start);
+            assert!(from_start.iter().all(|dist| dist.is_some()), "seed {}", seed);
+        }
+        /* with the chance at 1 most floors find somewhere to grow */
+        assert!(grown >= SEEDS.len(), "only grew {} rooms", grown);
+    }
+}
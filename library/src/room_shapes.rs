@@ -0,0 +1,185 @@
+use rand::{rngs::StdRng, distributions::{Distribution, WeightedIndex}, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::reachability::door_tile;
+use crate::room_gen::DoorType;
+
+/* ROOM SHAPES. every room is still a width x height box with its outer
+ * wall and doors in the middle of each side, shapes just say which tiles
+ * inside the box are floor. everything else gets filled with inner walls
+ * (same as hallways) so it draws, collides, regens and goes out in the
+ * map packet like any other wall */
+
+// caves start out this much wall before smoothing
+pub const CAVE_FILL: f32 = 0.45;
+// smoothing passes, more is rounder and emptier
+pub const CAVE_STEPS: usize = 5;
+// tiles around the middle a cave always keeps open, players spawn there
+pub const CAVE_CLEARING: usize = 3;
+// door paths are this many tiles either side of the door tile
+pub const DOOR_PATH_HALF_WIDTH: usize = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomShape {
+    /* the plain box, how it always was */
+    Rect,
+    /* one corner cut out */
+    L,
+    /* two corners on the same side cut out */
+    T,
+    /* all four corners cut out */
+    Plus,
+    /* biggest ellipse that fits the box */
+    Circle,
+    /* cellular automata blob */
+    Cave,
+}
+
+/* picks a shape off (shape, weight) pairs */
+pub fn roll_shape(shapes: &[(RoomShape, f32)], rng: &mut StdRng) -> RoomShape {
+    match WeightedIndex::new(shapes.iter().map(|(_, weight)| *weight)) {
+        Ok(weights) => shapes[weights.sample(rng)].0,
+        Err(_) => RoomShape::Rect,
+    }
+}
+
+/* which tiles are floor, indexed [x][y] like the room grids. outer wall
+ * is never floor, the middle always is, every door in `doors` has a
+ * straight path to the middle and nothing is cut off from it */
+pub fn floor_mask(shape: RoomShape, width: usize, height: usize, doors: &[DoorType], rng: &mut StdRng) -> Vec<Vec<bool>> {
+    let mut mask = match shape {
+        RoomShape::Cave => cave_mask(width, height, rng),
+        _ => {
+            /* which corner/side gets cut, rolled for every shape so the rng lines up */
+            let side = rng.gen_range(0..4);
+            let mut mask = vec![vec![false; height]; width];
+            for x in 1..width - 1 {
+                for y in 1..height - 1 {
+                    mask[x][y] = shape_has(shape, side, x, y, width, height);
+                }
+            }
+            mask
+        }
+    };
+    carve_door_paths(&mut mask, doors);
+    keep_middle_region(&mut mask);
+    mask
+}
+
+/* is x, y inside one of the geometric shapes */
+fn shape_has(shape: RoomShape, side: usize, x: usize, y: usize, width: usize, height: usize) -> bool {
+    let (mid_x, mid_y) = (width / 2, height / 2);
+    let (third_w, third_h) = (width / 3, height / 3);
+    let left = x < third_w;
+    let right = x >= width - third_w;
+    let bottom = y < third_h;
+    let top = y >= height - third_h;
+    match shape {
+        RoomShape::Rect | RoomShape::Cave => true,
+        /* quarter cut, stops a couple tiles short of the middle */
+        RoomShape::L => {
+            let cut_x = if side % 2 == 0 { x > mid_x + 2 } else { x + 2 < mid_x };
+            let cut_y = if side / 2 == 0 { y > mid_y + 2 } else { y + 2 < mid_y };
+            !(cut_x && cut_y)
+        }
+        RoomShape::T => match side {
+            0 => !(bottom && (left || right)),
+            1 => !(top && (left || right)),
+            2 => !(left && (bottom || top)),
+            _ => !(right && (bottom || top)),
+        },
+        RoomShape::Plus => !((left || right) && (bottom || top)),
+        RoomShape::Circle => {
+            let radius_x = (width - 2) as f32 / 2.;
+            let radius_y = (height - 2) as f32 / 2.;
+            let dx = (x as f32 + 0.5 - width as f32 / 2.) / radius_x;
+            let dy = (y as f32 + 0.5 - height as f32 / 2.) / radius_y;
+            dx * dx + dy * dy <= 1.
+        }
+    }
+}
+
+/* random fill, then every tile goes with the crowd around it a few times */
+fn cave_mask(width: usize, height: usize, rng: &mut StdRng) -> Vec<Vec<bool>> {
+    let inside = |x: usize, y: usize| x > 0 && y > 0 && x + 1 < width && y + 1 < height;
+    let mut mask = vec![vec![false; height]; width];
+    for x in 1..width - 1 {
+        for y in 1..height - 1 {
+            mask[x][y] = rng.gen_range(0.0..1.0) >= CAVE_FILL;
+        }
+    }
+    for _ in 0..CAVE_STEPS {
+        clear_middle(&mut mask);
+        let last = mask.clone();
+        for x in 1..width - 1 {
+            for y in 1..height - 1 {
+                /* outer wall counts as wall */
+                let mut walls = 0;
+                for nx in x - 1..=x + 1 {
+                    for ny in y - 1..=y + 1 {
+                        if (nx, ny) != (x, y) && (!inside(nx, ny) || !last[nx][ny]) {
+                            walls += 1;
+                        }
+                    }
+                }
+                if walls >= 5 {
+                    mask[x][y] = false;
+                } else if walls < 4 {
+                    mask[x][y] = true;
+                }
+            }
+        }
+    }
+    clear_middle(&mut mask);
+    mask
+}
+
+fn clear_middle(mask: &mut Vec<Vec<bool>>) {
+    let (width, height) = (mask.len(), mask[0].len());
+    let (mid_x, mid_y) = (width / 2, height / 2);
+    for x in mid_x.saturating_sub(CAVE_CLEARING).max(1)..=(mid_x + CAVE_CLEARING).min(width - 2) {
+        for y in mid_y.saturating_sub(CAVE_CLEARING).max(1)..=(mid_y + CAVE_CLEARING).min(height - 2) {
+            mask[x][y] = true;
+        }
+    }
+}
+
+/* straight run of floor from just inside each door to the middle */
+fn carve_door_paths(mask: &mut Vec<Vec<bool>>, doors: &[DoorType]) {
+    let (width, height) = (mask.len(), mask[0].len());
+    let (mid_x, mid_y) = (width / 2, height / 2);
+    for door_type in doors {
+        let (door_x, door_y) = door_tile(width, height, *door_type);
+        let (xs, ys) = match door_type {
+            DoorType::Left | DoorType::Right => (door_x.min(mid_x)..=door_x.max(mid_x), door_y - DOOR_PATH_HALF_WIDTH..=door_y + DOOR_PATH_HALF_WIDTH),
+            DoorType::Top | DoorType::Bottom => (door_x - DOOR_PATH_HALF_WIDTH..=door_x + DOOR_PATH_HALF_WIDTH, door_y.min(mid_y)..=door_y.max(mid_y)),
+        };
+        for x in xs {
+            for y in ys.clone() {
+                if x > 0 && y > 0 && x + 1 < width && y + 1 < height {
+                    mask[x][y] = true;
+                }
+            }
+        }
+    }
+}
+
+/* floor you cant walk to from the middle turns back into wall */
+fn keep_middle_region(mask: &mut Vec<Vec<bool>>) {
+    let (width, height) = (mask.len(), mask[0].len());
+    let mut seen = vec![vec![false; height]; width];
+    let middle = (width / 2, height / 2);
+    if mask[middle.0][middle.1] {
+        seen[middle.0][middle.1] = true;
+        let mut stack = vec![middle];
+        while let Some((x, y)) = stack.pop() {
+            for (nx, ny) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                if mask[nx][ny] && !seen[nx][ny] {
+                    seen[nx][ny] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+    }
+    *mask = seen;
+}
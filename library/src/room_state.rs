@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+use crate::cuscuta_resources::{Health, Pot, TILE_SIZE};
+use crate::enemies::{Enemy, EnemyKind};
+use crate::floors::Stairs;
+use crate::stealth::PropKind;
+use crate::teamwork::Puzzle;
+use crate::traps::Trap;
+use crate::room_gen::{DoorType, Potion, Room};
+
+/* ROOM STATE. rooms only get built once, after that whatever the party
+ * left behind (who is still up and how hurt, which pots got smashed,
+ * which potions got drunk, which doors got used) is saved when everyone
+ * walks out and put back when they walk in again. nothing respawns, so
+ * backtracking is safe but there's nothing to farm either */
+
+/* one enemy still standing when the party left */
+#[derive(Clone, Debug)]
+pub struct SavedEnemy {
+    pub kind: EnemyKind,
+    pub enemy: Enemy,
+    pub translation: Vec3,
+    pub health: Health,
+    pub patrol: Vec2,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RoomState {
+    pub enemies: Vec<SavedEnemy>,
+    /* pixels, room centered on 0,0 */
+    pub potions: Vec<Vec2>,
+    /* spot and whether it's been smashed */
+    pub pots: Vec<(Vec2, bool)>,
+    /* doors the party has been through, either direction */
+    pub opened: Vec<DoorType>,
+    /* nothing was left alive last time out, the clear has been scored */
+    pub cleared: bool,
+    /* where the floor boss died, if this is its room and it's dead */
+    pub stairs: Option<Vec2>,
+    /* challenge rooms: every wave is dead and the doors are open */
+    pub challenge_done: bool,
+    /* rest rooms: the party already healed here */
+    pub rested: bool,
+    /* already had its shot at a co-op puzzle, and the puzzle if it got one */
+    pub teamwork_rolled: bool,
+    pub puzzle: Option<Puzzle>,
+    /* stealth props, rolled with the room and never moved. pixels */
+    pub props: Vec<(Vec2, PropKind)>,
+    /* traps, sprung tripwires stay sprung */
+    pub traps: Vec<Trap>,
+    /* the biome's floor clutter, just for looks. pixels */
+    pub decor: Vec<Vec2>,
+}
+
+impl RoomState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open_door(&mut self, door_type: DoorType) {
+        if !self.opened.contains(&door_type) {
+            self.opened.push(door_type);
+        }
+    }
+
+    pub fn door_opened(&self, door_type: DoorType) -> bool {
+        self.opened.contains(&door_type)
+    }
+}
+
+/* puts a saved room's potions, pots and stairs back, enemies go through
+ * enemies::server_restore_enemies */
+pub fn restore_room_items(commands: &mut Commands, state: &RoomState, z_index: f32) {
+    for spot in state.potions.iter() {
+        commands.spawn((
+            Transform::from_xyz(spot.x, spot.y, z_index + 0.1),
+            Potion,
+            Room,
+        ));
+    }
+    for (spot, smashed) in state.pots.iter() {
+        commands.spawn((
+            Transform::from_xyz(spot.x, spot.y, z_index + 0.1),
+            Pot{
+                touch: if *smashed { 1 } else { 0 }
+            },
+            Room,
+        ));
+    }
+    if let Some(spot) = state.stairs {
+        commands.spawn((
+            Transform::from_xyz(spot.x, spot.y, z_index + 0.1),
+            Stairs,
+            Room,
+        ));
+    }
+}
+
+/* is a server side thing at `translation` the one a client says was at `pos` */
+pub fn same_spot(translation: Vec3, pos: Vec2) -> bool {
+    translation.truncate().distance(pos) < TILE_SIZE as f32 / 2.
+}
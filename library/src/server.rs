@@ -7,15 +7,23 @@ use serde::{Deserialize, Serialize};
 use crate::markov_chains::LastAttributeArray;
 
 use crate::player;
-use crate::{cuscuta_resources::{self, AddressList, Background, EnemiesToKill, Health, PlayerCount, Pot, Velocity, Wall, TILE_SIZE}, enemies::{Enemy, EnemyId, EnemyMovement}, network, player::{check_door_collision, Attack, Crouch, NetworkId, Player, Roll, ServerPlayerBundle, Sprint, Trackable}, room_gen::{transition_map, Door, DoorType, Potion, Room, RoomManager}, ui::CarnageBar};
-use crate::room_gen::{InnerWall, RoomChangeEvent, RoomConfig};
+use crate::{cuscuta_resources::{self, AddressList, Background, EnemiesToKill, Health, PlayerCount, Pot, Velocity, Wall, TILE_SIZE}, enemies::{Enemy, EnemyId, EnemyMovement}, network, player::{check_door_collision, Attack, Crouch, NetworkId, Player, Roll, ServerPlayerBundle, Sprint, Trackable}, room_gen::{transition_map, Door, DoorType, Potion, Room, RoomManager}, ui::{CarnageBar, PartyCarnage}};
+use crate::room_gen::{InnerWall, RoomChangeEvent, RoomConfig, RoomType};
 use crate::ui::CarnageChangeEvent;
-use crate::enemies::server_spawn_enemies;
+use crate::enemies::{server_restore_enemies, server_spawn_enemies, BossKillEvent, EnemyKind, PatrolAxis};
+use crate::room_state::{restore_room_items, same_spot, SavedEnemy};
+use crate::dungeon_rng::DungeonRng;
+use crate::autotile::autotile;
+use crate::floors::Stairs;
+use crate::special_rooms::{doors_locked, SHOP_PRICE};
+use crate::teamwork::PuzzleKind;
+use crate::carnage::{apply_carnage_event, CarnageCooldowns, CarnageEvent, CarnageRules, CarnageTrigger, Detection};
 
 
 
 /* Upon request, sends an id to client, spawns a player, and
- * punts player state off to client via the packet queue */
+ * punts player state off to client via the packet queue.
+ * If the party is full we answer with a FullPacket instead */
 pub fn send_id(
     source_addr : SocketAddr,
     n_p: &mut PlayerCount,
@@ -25,6 +33,19 @@ pub fn send_id(
     udp: & UDP,
     mut carnage_event: &mut EventWriter<CarnageChangeEvent>
 ) {
+    /* asked twice (first reply got lost)? same id again, no new player.
+     * ids are handed out in address order so index+1 is theirs */
+    if let Some(index) = addresses.list.iter().position(|addr| *addr == source_addr){
+        send_id_packet(index as u8 + 1, source_addr, server_seq, udp);
+        return;
+    }
+
+    if n_p.full(){
+        println!("party full ({}/{}), turning away {}", n_p.count, n_p.max, source_addr);
+        send_full_packet(n_p.max, source_addr, server_seq, udp);
+        return;
+    }
+
     /* assign id, update player count */
     n_p.count += 1;
     let player_id: u8 = n_p.count;
@@ -32,15 +53,8 @@ pub fn send_id(
    // println!("pushing addresss");
     commands.spawn(NetworkId::new_s(player_id, source_addr));
 
-    server_seq.nums.push(0);
-    let id_send = ServerPacket::IdPacket(IdPacket{
-        head: Header::new(player_id,server_seq.clone())});
-
-    /* put idpacket into 'to-send' queue */
-    let mut serializer = flexbuffers::FlexbufferSerializer::new();
-    id_send.serialize(&mut serializer).unwrap();
-    let packet: &[u8] = serializer.view();
-    udp.socket.send_to(&packet, source_addr).unwrap();
+    server_seq.grow_to(player_id as usize);
+    send_id_packet(player_id, source_addr, server_seq, udp);
 
     /* now we must spawn in a new player */
     commands.spawn(ServerPlayerBundle{
@@ -55,18 +69,51 @@ pub fn send_id(
         sprinting: Sprint::new(),
         attacking: Attack::new(),
         player: Player,
-        track: Trackable
+        track: Trackable,
+        carnage: CarnageBar::new(),
     });
     carnage_event.send(CarnageChangeEvent(true));
 }
 
+fn send_id_packet(
+    player_id: u8,
+    source_addr: SocketAddr,
+    server_seq: &Sequence,
+    udp: &UDP,
+){
+    let id_send = ServerPacket::IdPacket(IdPacket{
+        head: Header::new(player_id,server_seq.clone())});
+
+    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+    id_send.serialize(&mut serializer).unwrap();
+    let packet: &[u8] = serializer.view();
+    udp.socket.send_to(&packet, source_addr).unwrap();
+}
+
+fn send_full_packet(
+    max: u8,
+    source_addr: SocketAddr,
+    server_seq: &Sequence,
+    udp: &UDP,
+){
+    let full = ServerPacket::FullPacket(FullPacket{
+        head: Header::new(0, server_seq.clone()),
+        max: max,
+    });
+
+    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+    full.serialize(&mut serializer).unwrap();
+    let packet: &[u8] = serializer.view();
+    udp.socket.send_to(&packet, source_addr).unwrap();
+}
+
 /* Server side listener for packets,  */
 // go thru again and make sure that every function fits within new framework
 pub fn listen(
     udp: Res<UDP>,
     mut commands: Commands,
     mut players_q: Query<(&mut Velocity, &mut Transform, &mut Health,
-         &mut Crouch, &mut Roll, &mut Sprint, &mut Attack, &mut NetworkId), 
+         &mut Crouch, &mut Roll, &mut Sprint, &mut Attack, &NetworkId), 
          (With<Player>, Without<Enemy>, Without<Potion>, Without<Door>, Without<Wall>, Without<Background>, Without<DoorType>, Without<Pot>,Without<InnerWall>)>,//eek a lot
     mut n_p: ResMut<PlayerCount>,
     mut addresses: ResMut<AddressList>,
@@ -74,9 +121,11 @@ pub fn listen(
     mut enemies_to_kill: ResMut<EnemiesToKill>,
     mut enemies: Query<(Entity, &mut EnemyId, &mut EnemyMovement, &mut Transform, &mut Health), (With<Enemy>, Without<Player>, Without<InnerWall>)>,
     mut carnage_event: EventWriter<CarnageChangeEvent>,
-    mut carnage: Query<&mut CarnageBar>,
-    mut map_change: EventWriter<RoomChangeEvent>,
-
+    mut carnage_rules: EventWriter<CarnageEvent>,
+    (rules, detection, time): (Res<CarnageRules>, Res<Detection>, Res<Time>),
+    (mut map_change, mut boss_kill): (EventWriter<RoomChangeEvent>, EventWriter<BossKillEvent>),
+    (mut pots, potions): (Query<(&Transform, &mut Pot), (Without<Player>, Without<Enemy>)>, Query<(Entity, &Transform), (With<Potion>, Without<Player>, Without<Enemy>)>),
+    room_manager: Res<RoomManager>,
 ) {
 
     /*^ god we so should have made each listen an  EVENT and then dont need
@@ -111,11 +160,21 @@ pub fn listen(
                 update_player_state(src, &mut players_q, player_packet, &mut commands);
             }  
             ClientPacket::KillEnemyPacket(kill_enemy) => {
-                update_despawn(kill_enemy, &mut enemies_to_kill, &mut commands, &mut enemies); 
-                for mut carnage in carnage.iter_mut(){
-                    carnage.up_carnage(2.5);
+                /* floor bosses leave stairs, see floors.rs */
+                if let Some(spot) = update_despawn(kill_enemy, &mut enemies_to_kill, &mut commands, &mut enemies){
+                    boss_kill.send(BossKillEvent(spot));
+                }
+                /* only the one who swung gets the blood on their hands */
+                let now = time.elapsed_seconds();
+                for (_, _, _, _, _, _, _, id) in players_q.iter(){
+                    if id.addr != src{
+                        continue;
+                    }
+                    carnage_rules.send(CarnageEvent{player: id.id, trigger: CarnageTrigger::Kill});
+                    if !detection.seen_recently(id.id, now, rules.undetected_window){
+                        carnage_rules.send(CarnageEvent{player: id.id, trigger: CarnageTrigger::KillUndetected});
+                    }
                 }
-                carnage_event.send(CarnageChangeEvent(true));
             }
 
             ClientPacket::DecreaseEnemyHealthPacket(decrease_enemy_health_packet) => {
@@ -124,12 +183,97 @@ pub fn listen(
             ClientPacket::MonkeyPacket(monkey_packet) => {
                 update_monkey(&mut commands, monkey_packet, &addresses, &udp, &mut server_seq);
             }
+            ClientPacket::PingPacket(ping_packet) => {
+                send_pong(src, ping_packet, &udp);
+            }
+            ClientPacket::PotPacket(pot_packet) => {
+                /* credit whoever sent it, not whoever the header says. no
+                 * player at that address, no pot */
+                let Some(player) = players_q.iter().map(|(.., id)| id).find(|id| id.addr == src).map(|id| id.id) else {
+                    continue;
+                };
+                /* remember it's smashed for next visit. only a whole pot
+                 * scores, so old rooms cant be farmed */
+                let mut smashed = false;
+                for (transform, mut pot) in pots.iter_mut(){
+                    if pot.touch == 0 && same_spot(transform.translation, pot_packet.pos){
+                        pot.touch = 1;
+                        smashed = true;
+                    }
+                }
+                if smashed{
+                    carnage_rules.send(CarnageEvent{player: player, trigger: CarnageTrigger::PotBroken});
+                }
+            }
+            ClientPacket::PotionPacket(potion_packet) => {
+                /* shop potions arent free, the client only asked */
+                if room_manager.current_room_type() == RoomType::Shop{
+                    sell_potion(src, potion_packet, &mut players_q, &potions, &mut commands, &server_seq, &udp);
+                    continue;
+                }
+                for (entity, transform) in potions.iter(){
+                    if same_spot(transform.translation, potion_packet.pos){
+                        commands.entity(entity).despawn();
+                    }
+                }
+            }
 
         }
     }
 }
 
 
+/* someone wants a potion off the shop shelf. it has to still be there
+ * and they have to be able to pay without dying, then it comes off the
+ * shelf and the price off our copy of their health. they hear back
+ * either way, health is theirs to keep so they charge themselves */
+fn sell_potion(
+    src: SocketAddr,
+    potion_packet: PotionPacket,
+    players_q: &mut Query<(&mut Velocity, &mut Transform, &mut Health,
+         &mut Crouch, &mut Roll, &mut Sprint, &mut Attack, &NetworkId), 
+         (With<Player>, Without<Enemy>, Without<Potion>, Without<Door>, Without<Wall>, Without<Background>, Without<DoorType>, Without<Pot>,Without<InnerWall>)>,
+    potions: &Query<(Entity, &Transform), (With<Potion>, Without<Player>, Without<Enemy>)>,
+    commands: &mut Commands,
+    server_seq: &Sequence,
+    udp: &UDP,
+){
+    let Some((_, _, mut health, _, _, _, _, id)) = players_q.iter_mut().find(|(.., id)| id.addr == src) else {
+        return;
+    };
+    let potion = potions.iter().find(|(_, transform)| same_spot(transform.translation, potion_packet.pos));
+    let bought = health.current > SHOP_PRICE && potion.is_some();
+    if let Some((entity, _)) = potion.filter(|_| bought){
+        commands.entity(entity).despawn();
+        health.current -= SHOP_PRICE;
+    }
+    println!("player {} {} the shop potion at {:?}", id.id, if bought { "bought" } else { "couldnt buy" }, potion_packet.pos);
+
+    let sale = ServerPacket::ShopPacket(ShopPacket{
+        head: Header::new(id.id, server_seq.clone()),
+        pos: potion_packet.pos,
+        bought: bought,
+        health: health.current,
+    });
+    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+    sale.serialize(&mut serializer).unwrap();
+    udp.socket.send_to(serializer.view(), src).unwrap();
+}
+
+/* bounce a ping right back at whoever asked. no queueing,
+ * we want the latency of the tick we got it on */
+fn send_pong(
+    src: SocketAddr,
+    ping: PingPacket,
+    udp: &UDP,
+){
+    let pong = ServerPacket::PingPacket(ping);
+    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+    pong.serialize(&mut serializer).unwrap();
+    let packet: &[u8] = serializer.view();
+    udp.socket.send_to(&packet, src).unwrap();
+}
+
 pub fn send_enemies(
     enemies: Query<(& EnemyId, & EnemyMovement, &Transform, &Health), 
         (With<Enemy>, Without<Player>)>,
@@ -226,7 +370,7 @@ pub fn update_monkey (
 fn update_player_state(
     src: SocketAddr,
     mut players_q: &mut Query<(&mut Velocity, &mut Transform, &mut Health,
-        &mut Crouch, &mut Roll, &mut Sprint, &mut Attack, &mut NetworkId), 
+        &mut Crouch, &mut Roll, &mut Sprint, &mut Attack, &NetworkId), 
         (With<Player>, Without<Enemy>, Without<Potion>, Without<Door>, Without<Wall>, Without<Background>, Without<DoorType>, Without<Pot>,Without<InnerWall>)>,//eek a lot
     player_struct: PlayerSendable,
     mut commands: &mut Commands
@@ -270,6 +414,7 @@ fn update_player_state(
             attacking: Attack::new_set(player_struct.attack), 
             player: Player,
             track: Trackable,
+            carnage: CarnageBar::new(),
         });
     }
 }
@@ -279,14 +424,20 @@ fn update_despawn(
     enemies_to_kill: &mut EnemiesToKill,
     commands: &mut Commands,
     enemies: &mut Query<(Entity, &mut EnemyId, &mut EnemyMovement, &mut Transform, &mut Health), (With<Enemy>, Without<Player>, Without<InnerWall>)>,
-){
+) -> Option<Vec2> {
+    /* where the boss was, if that was the boss */
+    let mut boss = None;
     enemies_to_kill.list.push(kill_enemy.clone());
-    for(entity, id, _movement, _transform, _health) in enemies.iter(){
+    for(entity, id, _movement, transform, _health) in enemies.iter(){
         if id.id == kill_enemy.enemy_id.id{
             commands.entity(entity).despawn();
             println!("despawning enemy");
+            if let EnemyKind::Boss(_) = id.kind {
+                boss = Some(transform.translation.truncate());
+            }
         }
     }
+    boss
 }
 
 /* runs to send off 'despawn this hoe' messages to clients
@@ -401,27 +552,45 @@ pub fn send_player_to_self(
 
 /** INDEX TO USE
 0 - floor
-1 - left wall
-2 - right wall
+1 - outer wall (which piece is up to autotile, see the tiles layer)
+2 - unused, was right wall
 3 - chest/pot
 4 - left door
 5 - right door
 6 - top door
 7 - bottom door 
-8 - top wall
-9 - bottom wall 
-10 - pot */
+8/9 - unused, were top/bottom wall
+10 - pot
+11 - inner wall
+12 - smashed pot
+13 - stairs down
+14 - potion for sale (shop rooms)
+15/16 - pressure plate, up/held
+17/18 - lever, up/held
+19 - low cover
+20 - tall grass
+21 - shadow
+22 - creaky boards
+23 - spikes
+24 - acid
+25 - spore vent
+26 - tripwire (gone once it's tripped)
+27 - decor (biome floor clutter) */
 fn send_map_packet (
     door_query: &mut Query<(&mut Transform, &Door), (Without<Wall>, Without<Background>, Without<Potion>, Without<Enemy>, Without<Pot>,Without<InnerWall>)>,  
     wall_query: &mut Query<&mut Transform, (With<Wall>, Without<Door>, Without<Background>, Without<Potion>, Without<Enemy>, Without<Pot>,Without<InnerWall>)>, 
     background_query: &mut Query<&mut Transform, (With<Background>, Without<Potion>, Without<Enemy>, Without<Pot>,Without<InnerWall>)>,
     potion_query: &mut Query<&mut Transform, (With<Potion>, Without<Pot>, Without<Enemy>,Without<InnerWall>)>,
-    pot_query: &mut Query<&mut Transform, (With<Pot>, Without<Enemy>,Without<InnerWall>)>,
+    pot_query: &mut Query<(&mut Transform, &Pot), (With<Pot>, Without<Enemy>,Without<InnerWall>)>,
     inner_wall_query: &mut Query<&mut Transform, (With<InnerWall>)>,
+    stairs_query: &Query<&Transform, (With<Stairs>, Without<Player>, Without<Door>, Without<Wall>, Without<Background>, Without<Potion>, Without<Pot>, Without<InnerWall>)>,
     server_seq: &Sequence,
     roomman: &mut RoomManager,
     udp: &UDP,
     addresses: &AddressList,
+    last_attribute_array: &LastAttributeArray,
+    room_config: &RoomConfig,
+    dungeon_rng: &DungeonRng,
 ) {
 
     let (room_w,room_h):(f32, f32) = RoomManager::current_room_size(&roomman);
@@ -442,14 +611,12 @@ fn send_map_packet (
         map_array[arr_x][arr_y] = 0;
     }
 
+    /* every outer wall is just wall, autotile works out which piece */
     for tile in wall_query.iter()
     {
         let arr_x:usize = (tile.translation.x + max_x - 16.0) as usize / 32;
         let arr_y:usize = (tile.translation.y + max_y - 16.0) as usize / 32;
-        if arr_x == 0 {map_array[arr_x][arr_y as usize] = 1;}
-        else if arr_y == 0 {map_array[arr_x][arr_y] = 9;}
-        else if arr_x == (room_w as usize/32)-1 {map_array[arr_x][arr_y] = 2;}
-        else {map_array[arr_x][arr_y] = 8;}
+        map_array[arr_x][arr_y] = 1;
     }
 
     for tile in inner_wall_query.iter(){
@@ -458,18 +625,63 @@ fn send_map_packet (
         map_array[arr_x][arr_y as usize] = 11;
     }
 
+    /* clutter first, anything else on the tile wins */
+    if let Some(state) = roomman.room_state(roomman.current_room_id()){
+        for spot in state.decor.iter(){
+            let arr_x: usize = (spot.x + max_x - 16.0) as usize / 32;
+            let arr_y: usize = (spot.y + max_y - 16.0) as usize / 32;
+            map_array[arr_x][arr_y] = 27;
+        }
+    }
+
+    /* stealth props under everything you can pick up */
+    if let Some(state) = roomman.room_state(roomman.current_room_id()){
+        for (spot, kind) in state.props.iter(){
+            let arr_x: usize = (spot.x + max_x - 16.0) as usize / 32;
+            let arr_y: usize = (spot.y + max_y - 16.0) as usize / 32;
+            map_array[arr_x][arr_y] = kind.code();
+        }
+    }
+
+    /* traps on top of props, there's never both on one tile anyway */
+    if let Some(state) = roomman.room_state(roomman.current_room_id()){
+        for trap in state.traps.iter().filter(|trap| !trap.sprung){
+            let arr_x: usize = (trap.spot.x + max_x - 16.0) as usize / 32;
+            let arr_y: usize = (trap.spot.y + max_y - 16.0) as usize / 32;
+            map_array[arr_x][arr_y] = trap.kind.code();
+        }
+    }
+
+    let shop = roomman.current_room_type() == RoomType::Shop;
     for tile in potion_query.iter()
     {
         let arr_x: usize = (tile.translation.x + max_x - 16.0) as usize / 32;
         let arr_y: usize = (tile.translation.y + max_y - 16.0) as usize / 32;
-        map_array[arr_x][arr_y] = 3;
+        map_array[arr_x][arr_y] = if shop { 14 } else { 3 };
+    }
+
+    for (tile, pot) in pot_query.iter()
+    {
+        let arr_x: usize = (tile.translation.x + max_x - 16.0) as usize / 32;
+        let arr_y: usize = (tile.translation.y + max_y - 16.0) as usize / 32;
+        map_array[arr_x][arr_y] = if pot.touch == 0 { 10 } else { 12 };
+    }
+
+    /* co-op puzzle bits, they only live in the room's state */
+    if let Some(puzzle) = roomman.room_state(roomman.current_room_id()).and_then(|state| state.puzzle.as_ref()){
+        let code = if puzzle.kind == PuzzleKind::Lever { 17 } else { 15 };
+        for (spot, held) in puzzle.spots.iter().zip(puzzle.held.iter()){
+            let arr_x: usize = (spot.x + max_x - 16.0) as usize / 32;
+            let arr_y: usize = (spot.y + max_y - 16.0) as usize / 32;
+            map_array[arr_x][arr_y] = if *held || puzzle.solved { code + 1 } else { code };
+        }
     }
 
-    for tile in pot_query.iter()
+    for tile in stairs_query.iter()
     {
         let arr_x: usize = (tile.translation.x + max_x - 16.0) as usize / 32;
         let arr_y: usize = (tile.translation.y + max_y - 16.0) as usize / 32;
-        map_array[arr_x][arr_y] = 10;
+        map_array[arr_x][arr_y] = 13;
     }
 
     /* grab doors */
@@ -487,12 +699,22 @@ fn send_map_packet (
         }
     }
     //println!("{:?},", map_array);
+    let here = roomman.current_room_id();
+    let tiles = autotile(&map_array, room_tile_w as usize, room_tile_h as usize, dungeon_rng, here);
     let mappy = ServerPacket::MapPacket(MapS2C{
         head: Header::new(0,server_seq.clone()),// server id == 0
         matrix: map_array,
+        tiles: tiles,
         size: RoomManager::current_room_size(&roomman),
         max: RoomManager::current_room_max(&roomman),
-        z: roomman.current_z_index,
+        z: roomman.current_room_z_index(),
+        states: last_attribute_array.labels(),
+        carnage: last_attribute_array.carnage,
+        floor: room_config.depth(),
+        last_floor: room_config.last_floor(),
+        doors: roomman.layout.door_markers(here),
+        locked: doors_locked(roomman),
+        biome: room_config.biome(),
     });
 
     
@@ -506,10 +728,77 @@ fn send_map_packet (
     
 }
 
+/* how long the party has to pile onto a door once someone steps on it */
+pub const DOOR_VOTE_TIME: f32 = 5.;
+
+/* in progress room transition. first player on a door picks it,
+ * everyone else has DOOR_VOTE_TIME to join before we go anyway */
+#[derive(Resource)]
+pub struct DoorVote{
+    pub door: Option<DoorType>,
+    pub timer: Timer,
+    pub voters: Vec<u8>,
+    /* whole seconds left when we last told clients, so we
+     * arent blasting a packet every tick */
+    pub last_sent: u32,
+}
+
+impl DoorVote{
+    pub fn new() -> Self{
+        Self{
+            door: None,
+            timer: Timer::from_seconds(DOOR_VOTE_TIME, TimerMode::Once),
+            voters: Vec::new(),
+            last_sent: 0,
+        }
+    }
+
+    pub fn start(&mut self, door: DoorType){
+        self.door = Some(door);
+        self.timer.reset();
+        self.voters.clear();
+        self.last_sent = 0;
+    }
+
+    pub fn clear(&mut self){
+        self.door = None;
+        self.voters.clear();
+    }
+
+    pub fn remaining(&self) -> f32{
+        self.timer.remaining_secs()
+    }
+}
+
+fn send_door_vote(
+    vote: &DoorVote,
+    addresses: &AddressList,
+    server_seq: &Sequence,
+    udp: &UDP,
+){
+    let packet = ServerPacket::DoorVotePacket(DoorVotePacket{
+        head: Header::new(0, server_seq.clone()),
+        door: vote.door,
+        remaining: vote.remaining(),
+        voters: vote.voters.clone(),
+    });
+    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+    packet.serialize(&mut serializer).unwrap();
+    let to_send = serializer.view();
+    for addr in addresses.list.iter(){
+        udp.socket.send_to(&to_send, addr).unwrap();
+    }
+}
+
+/* door vote. the first living player on a door starts the countdown,
+ * the party moves when it runs out or when every living player is
+ * on that door. transition_map moves everybody so stragglers come too.
+ * if everyone steps off the door the vote is called off */
 pub fn check_door(
     mut player : Query<(&mut Transform), With<Player>>,
+    voter_q: Query<(Entity, &NetworkId, &Health), With<Player>>,
     door_query: Query<(&Transform, &Door), (Without<Player>, Without<Enemy>)>,
-    mut carnage: Query<&mut CarnageBar>,
+    mut carnage: Query<(&NetworkId, &mut CarnageBar), With<Player>>,
     mut commands: Commands,
     mut room_manager: ResMut<RoomManager>,
     mut room_query: Query<Entity, With<Room>>,
@@ -517,88 +806,170 @@ pub fn check_door(
     mut last_attribute_array: ResMut<LastAttributeArray>,
     mut enemy_id: ResMut<EnemyId>,
     room_config: Res<RoomConfig>,
-    enemies: Query<Entity, With<Enemy>>,
-    addresses: Res<AddressList>,
-    udp: Res<UDP>,
-    mut carnage_event: EventWriter<CarnageChangeEvent>,
-    mut num_players: Res<PlayerCount>,
+    enemies: Query<(Entity, &Transform, &EnemyId, &Enemy, &Health, Option<&PatrolAxis>), (With<Enemy>, Without<Player>)>,
+    /* bundled up, bevy stops counting system params at 16 */
+    (addresses, udp, server_seq): (Res<AddressList>, Res<UDP>, Res<Sequence>),
+    (rules, mut cooldowns, mut carnage_change, mut detection): (Res<CarnageRules>, ResMut<CarnageCooldowns>, EventWriter<CarnageChangeEvent>, ResMut<Detection>),
+    (num_players, pots, potions): (Res<PlayerCount>, Query<(&Transform, &Pot), Without<Player>>, Query<&Transform, (With<Potion>, Without<Player>)>),
+    (mut vote, time, mut party, dungeon_rng): (ResMut<DoorVote>, Res<Time>, ResMut<PartyCarnage>, Res<DungeonRng>),
 ){
-    /* are allthe players standing on a door? */
-    let mut all_hit = true;
-    let mut have_player = false;
-    let mut final_door = None;
+    /* challenge still going or a puzzle holding the doors, see
+     * special_rooms.rs. letting go of a lever calls off the vote */
+    if doors_locked(&room_manager){
+        if vote.door.is_some(){
+            vote.clear();
+            send_door_vote(&vote, &addresses, &server_seq, &udp);
+        }
+        return;
+    }
 
-    let mut player_transform: Option<Transform> = None;
-    
-    /* for all players */
-    for transform in player.iter(){
-        player_transform = Some(*transform); 
-        let (door_hit, door_type) = check_door_collision(&door_query, transform);
-        /* ah boolean. ensures if we get false, it'll
-         * stay false. do need to make sure we have a player lol.. */
-        all_hit = all_hit && door_hit;
-        have_player = true;
-        /* also set door. If the final door is None,
-         * we will set (lower). If final_door is something,
-         * we check if its the same as what we just got. if it's not,
-         * the players are on different doors, abort mission */
-        
+    /* who is alive, and who is standing on what */
+    let mut living = 0;
+    let mut on_door: Vec<(u8, DoorType)> = Vec::new();
+    for (entity, id, health) in voter_q.iter(){
+        if health.current <= 0. {
+            continue;
+        }
+        living += 1;
+        if let Ok(transform) = player.get(entity){
+            if let (true, Some(door_type)) = check_door_collision(&door_query, transform){
+                on_door.push((id.id, door_type));
+            }
+        }
+    }
 
-        if let Some(final_door) = final_door{
-            /* final door is something! Is door_type? */
-            if let Some(door_type) = door_type{
-                /* yippe! if same, we dont care, if not
-                 * boooo killll tomato */
-                if door_type != final_door{
-                    all_hit = false;
-                }
+    let door = match vote.door{
+        Some(door) => door,
+        None => {
+            /* nobody has called it yet, first one on a door does */
+            if let Some((_, door_type)) = on_door.first(){
+                vote.start(*door_type);
+                info!("door vote started: {:?}", door_type);
+            } else {
+                return;
             }
-        } else{
-            final_door = door_type;
+            vote.door.unwrap()
         }
+    };
+
+    vote.timer.tick(time.delta());
+    let voters: Vec<u8> = on_door.iter()
+        .filter(|(_, door_type)| *door_type == door)
+        .map(|(id, _)| *id)
+        .collect();
+
+    if voters.is_empty(){
+        info!("door vote called off");
+        vote.clear();
+        send_door_vote(&vote, &addresses, &server_seq, &udp);
+        return;
     }
 
-    // If a door was hit, handle the transition
-    if all_hit && have_player{
-        let packet = ServerPacket::DespawnAllPacket(DespawnAllPacket { kill: true });
-        let mut serializer = flexbuffers::FlexbufferSerializer::new();
-        packet.serialize(&mut serializer).unwrap();
-        let to_send = serializer.view();
-        for addr in addresses.list.iter(){
-            udp.socket.send_to(&to_send, addr).unwrap();
+    let all_in = voters.len() >= living;
+    if !vote.timer.finished() && !all_in{
+        /* only bother clients when something they can see changed */
+        let secs = vote.remaining().ceil() as u32;
+        if voters != vote.voters || secs != vote.last_sent{
+            vote.voters = voters;
+            vote.last_sent = secs;
+            send_door_vote(&vote, &addresses, &server_seq, &udp);
         }
-        for(entity) in enemies.iter(){
-            commands.entity(entity).despawn();
+        return;
+    }
+
+    /* party's moving. tell clients the vote is done before the new room lands */
+    vote.clear();
+    send_door_vote(&vote, &addresses, &server_seq, &udp);
+
+    /* score the room we're leaving. cleared = nothing left alive in it.
+     * hallways are just the walk between rooms, they dont count either way,
+     * and rooms we've cleared or been in before dont count twice.
+     * shops and rest stops never had anything to clear */
+    let cleared = enemies.iter().next().is_none();
+    let peaceful = matches!(room_manager.current_room_type(), RoomType::Shop | RoomType::Rest);
+    let here = room_manager.current_room_id();
+    let already_cleared = room_manager.room_state(here).map_or(false, |state| state.cleared);
+    let next = room_manager.layout.neighbor(here, door);
+    let leaving_hallway = room_manager.current_layout_room().map_or(false, |room| room.is_hallway());
+    let entering_hallway = next.and_then(|next| room_manager.layout.room(next)).map_or(false, |room| room.is_hallway());
+    let revisit = next.map_or(false, |next| room_manager.room_array.get_room_from_storage(next).is_some());
+    /* scored right here rather than sent, the next room rolls on them below */
+    let now = time.elapsed_seconds();
+    let mut scored = false;
+    for (_, id, health) in voter_q.iter(){
+        if health.current <= 0. {
+            continue;
         }
-        if let Some(final_door) = final_door {
-            if let Some(mut transform) = player_transform {
-                transition_map(
-                    &mut commands,
-                    &mut room_manager,
-                    &mut room_query,
-                    final_door,
-                    &mut carnage,
-                    &mut last_attribute_array,
-                    &room_config,
-                    &mut player
-                );
-                  server_spawn_enemies(&mut commands, &mut enemy_id, &mut last_attribute_array, &room_config, &room_manager, &num_players);
-
-                  room_change.send(RoomChangeEvent(all_hit));
-                  for mut carnage in carnage.iter_mut(){
-                      carnage.up_stealth(5.);
-                  }
-                 // carnage.single_mut().up_stealth(5.);
-                  carnage_event.send(CarnageChangeEvent(true));
-            } else {
-                eprintln!("Error: Player transform was not set!");
-            }
-        } else {
-            info!("ERROR: FINAL DOOR TYPE NOT SET");
+        if cleared && !already_cleared && !detection.room_detected && !leaving_hallway && !peaceful{
+            let event = CarnageEvent{player: id.id, trigger: CarnageTrigger::RoomClearedUndetected};
+            scored |= apply_carnage_event(&event, &rules, &mut cooldowns, carnage.iter_mut(), now);
         }
-    } else {
-        info!("ERROR: ALL_HIT OR HAVE_PLAYER FALSE");
+        if !entering_hallway && !revisit{
+            let event = CarnageEvent{player: id.id, trigger: CarnageTrigger::RoomEntered};
+            scored |= apply_carnage_event(&event, &rules, &mut cooldowns, carnage.iter_mut(), now);
+        }
+    }
+    if scored{
+        carnage_change.send(CarnageChangeEvent(true));
     }
+    detection.new_room();
+
+    /* remember what's left so coming back doesnt reset (or refill) the room */
+    let leaving = room_manager.room_state_mut(here);
+    leaving.enemies = enemies.iter()
+        .filter(|(_, _, _, _, health, _)| health.current > 0.)
+        .map(|(_, transform, id, enemy, health, patrol)| SavedEnemy {
+            kind: id.kind.clone(),
+            enemy: enemy.clone(),
+            translation: transform.translation,
+            health: *health,
+            patrol: patrol.map_or(Vec2::X, |axis| axis.0),
+        })
+        .collect();
+    leaving.potions = potions.iter().map(|transform| transform.translation.truncate()).collect();
+    leaving.pots = pots.iter().map(|(transform, pot)| (transform.translation.truncate(), pot.touch != 0)).collect();
+    leaving.cleared = already_cleared || cleared;
+    leaving.open_door(door);
+
+    let packet = ServerPacket::DespawnAllPacket(DespawnAllPacket { kill: true });
+    let mut serializer = flexbuffers::FlexbufferSerializer::new();
+    packet.serialize(&mut serializer).unwrap();
+    let to_send = serializer.view();
+    for addr in addresses.list.iter(){
+        udp.socket.send_to(&to_send, addr).unwrap();
+    }
+    for (entity, _, _, _, _, _) in enemies.iter(){
+        commands.entity(entity).despawn();
+    }
+    /* fresh party value with the room scores above in it. kills this
+     * tick still go through apply_carnage_rules and count next room */
+    party.update(carnage.iter().map(|(_, bar)| bar));
+    transition_map(
+        &mut commands,
+        &mut room_manager,
+        &mut room_query,
+        door,
+        party.percent(),
+        &mut last_attribute_array,
+        &room_config,
+        &mut player,
+        &dungeon_rng,
+    );
+
+    /* been here before, put it back how we left it. otherwise it's new */
+    let arriving = room_manager.current_room_id();
+    match room_manager.room_state(arriving).filter(|_| revisit).cloned(){
+        Some(state) => {
+            restore_room_items(&mut commands, &state, arriving.z());
+            server_restore_enemies(&mut commands, &mut enemy_id, &state.enemies, &room_manager, &dungeon_rng);
+        }
+        None => server_spawn_enemies(&mut commands, &mut enemy_id, &mut last_attribute_array, &room_config, &room_manager, &num_players, &dungeon_rng),
+    }
+    if let Some(arrival) = next.and_then(|next| room_manager.layout.door_to(next, here)){
+        room_manager.room_state_mut(arriving).open_door(arrival);
+    }
+
+    room_change.send(RoomChangeEvent(true));
 }
 
 fn decrease_enemy_health(
@@ -627,18 +998,20 @@ pub fn room_change_infodump(
         (With<Background>, Without<Potion>, Without<Enemy>, Without<Pot>,Without<InnerWall>)>,
     mut potion_query: Query<&mut Transform, 
         (With<Potion>, Without<Pot>, Without<Enemy>,Without<InnerWall>)>,
-    mut pot_query: Query<&mut Transform, 
+    mut pot_query: Query<(&mut Transform, &Pot), 
         (With<Pot>, Without<Enemy>,Without<InnerWall>)>,
     mut inner_wall_query: Query<&mut Transform, With<InnerWall>>,
+    stairs_query: Query<&Transform, (With<Stairs>, Without<Player>, Without<Door>, Without<Wall>, Without<Background>, Without<Potion>, Without<Pot>, Without<InnerWall>)>,
     mut room_manager: ResMut<RoomManager>,
+    (last_attribute_array, room_config, dungeon_rng): (Res<LastAttributeArray>, Res<RoomConfig>, Res<DungeonRng>),
 ){
     for event in event_listener.read(){
         if !event.0{continue};
         send_map_packet(&mut door_query, &mut wall_query,
              &mut background_query, &mut potion_query,
-              &mut pot_query, &mut inner_wall_query,
+              &mut pot_query, &mut inner_wall_query, &stairs_query,
               &server_seq,
-               &mut room_manager, &udp, & addresses);
+               &mut room_manager, &udp, & addresses, &last_attribute_array, &room_config, &dungeon_rng);
         send_player_to_self(&player, &mut server_seq, &addresses, &udp);
 
 
@@ -648,19 +1021,17 @@ pub fn room_change_infodump(
 pub fn carnage_update(
     addresses: Res<AddressList>,
     udp: Res<UDP>,
-    carnage: Query<& CarnageBar>,
+    carnage: Query<(&NetworkId, &CarnageBar), With<Player>>,
+    mut party: ResMut<PartyCarnage>,
     mut carnage_event: EventReader<CarnageChangeEvent>,
 ){
     for event in carnage_event.read(){
         if !event.0{continue};
-        let mut carnage_bar: &CarnageBar = &CarnageBar::new();
-        for carnage in carnage.iter(){
-            carnage_bar = carnage;
-        }
-       // let carnage_bar = carnage.single();
-        //println!("c.s:{} c.fight:{}", carnage_bar.stealth, carnage_bar.carnage);
+        party.update(carnage.iter().map(|(_, bar)| bar));
+        //println!("c.s:{} c.fight:{}", party.bar.stealth, party.bar.carnage);
         let pack= ServerPacket::CarnagePacket(CarnagePacket{
-            carnage: (*carnage_bar).clone(),
+            bars: carnage.iter().map(|(id, bar)| (id.id, bar.clone())).collect(),
+            party: party.bar.clone(),
         });
         let mut serializer = flexbuffers::FlexbufferSerializer::new();
         pack.serialize(&mut serializer).unwrap();
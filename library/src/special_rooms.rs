@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+
+use crate::cuscuta_resources::{Health, PlayerCount, Pot};
+use crate::dungeon_rng::DungeonRng;
+use crate::enemies::{spawn_room_enemies, Enemy, EnemyId};
+use crate::markov_chains::LastAttributeArray;
+use crate::network::ShopPacket;
+use crate::player::Player;
+use crate::reachability::{pixel_to_tile, reachable_spots, take_spot, ITEM_EDGE_MARGIN};
+use crate::room_gen::{Potion, Room, RoomChangeEvent, RoomConfig, RoomId, RoomManager, RoomType};
+
+/* SPECIAL ROOMS. which rooms get a type is decided with the layout
+ * (FloorRules before_boss and quotas), this is what the types do once
+ * the party walks in:
+ *   treasure  - extra potions and pots, see spawn_items_in_room
+ *   shop      - no enemies, SHOP_WARES potions that cost SHOP_PRICE
+ *               health to pick up. the client asks, the server checks
+ *               they can pay and charges them (ShopPacket)
+ *   challenge - doors lock behind you and CHALLENGE_WAVES waves come
+ *               in one after another, kill them all and the doors open
+ *               with CHALLENGE_REWARD potions on the floor
+ *   rest      - no enemies, heals everyone alive the first time in
+ * doors show what's behind them with RoomType::marker */
+
+pub const SHOP_WARES: usize = 4;
+pub const SHOP_PRICE: f32 = 20.;
+// secs to wait on a ShopPacket before giving up and letting the spot be asked again
+pub const SHOP_ANSWER_TIMEOUT: f32 = 1.;
+pub const CHALLENGE_WAVES: usize = 3;
+pub const CHALLENGE_REWARD: usize = 3;
+
+/* client side, on potions off a shop shelf */
+#[derive(Component)]
+pub struct ShopPrice(pub f32);
+
+/* client side, shop potions we've asked the server for and what it
+ * said. one ask out at a time, and a spot stays asked till the room
+ * comes in again so a no doesnt get us asking every frame. an ask
+ * nobody answers is dropped after SHOP_ANSWER_TIMEOUT */
+#[derive(Resource)]
+pub struct ShopCounter {
+    pub asked: Vec<Vec2>,
+    pub waiting: bool,
+    /* elapsed secs when the ask went out, for SHOP_ANSWER_TIMEOUT */
+    pub asked_at: f32,
+    pub answers: Vec<ShopPacket>,
+}
+
+impl ShopCounter {
+    pub fn new() -> Self {
+        Self {
+            asked: Vec::new(),
+            waiting: false,
+            asked_at: 0.,
+            answers: Vec::new(),
+        }
+    }
+}
+
+/* the challenge the party is stuck in, if any */
+#[derive(Resource)]
+pub struct Challenge {
+    pub room: Option<RoomId>,
+    /* waves spawned so far, the first comes in with the room */
+    pub wave: usize,
+}
+
+impl Challenge {
+    pub fn new() -> Self {
+        Self { room: None, wave: 0 }
+    }
+}
+
+/* nobody leaves a challenge room until it's done, or a teamwork room
+ * until its puzzle lets them (see teamwork.rs) */
+pub fn doors_locked(room_manager: &RoomManager) -> bool {
+    let state = room_manager.room_state(room_manager.current_room_id());
+    let challenge = room_manager.current_room_type() == RoomType::Challenge
+        && !state.map_or(false, |state| state.challenge_done);
+    let puzzle = state.and_then(|state| state.puzzle.as_ref()).map_or(false, |puzzle| !puzzle.open());
+    challenge || puzzle
+}
+
+/* next wave once the last one is dead, doors open after the last */
+pub fn run_challenge(
+    mut commands: Commands,
+    mut challenge: ResMut<Challenge>,
+    enemies: Query<(), With<Enemy>>,
+    items: Query<&Transform, Or<(With<Potion>, With<Pot>)>>,
+    mut room_manager: ResMut<RoomManager>,
+    mut enemy_id: ResMut<EnemyId>,
+    mut last_attribute_array: ResMut<LastAttributeArray>,
+    room_config: Res<RoomConfig>,
+    num_players: Res<PlayerCount>,
+    dungeon_rng: Res<DungeonRng>,
+    mut room_change: EventWriter<RoomChangeEvent>,
+){
+    let done = room_manager.room_state(room_manager.current_room_id()).map_or(false, |state| state.challenge_done);
+    if room_manager.current_room_type() != RoomType::Challenge || done {
+        challenge.room = None;
+        return;
+    }
+    let room = room_manager.current_room_id();
+    if challenge.room != Some(room) {
+        println!("room {} is a challenge, doors locked for {} waves", room.0, CHALLENGE_WAVES);
+        challenge.room = Some(room);
+        challenge.wave = 1;
+        return;
+    }
+    if !enemies.is_empty() {
+        return;
+    }
+
+    if challenge.wave < CHALLENGE_WAVES {
+        challenge.wave += 1;
+        println!("challenge wave {} of {}", challenge.wave, CHALLENGE_WAVES);
+        let mut rng = dungeon_rng.wave(room, challenge.wave);
+        spawn_room_enemies(&mut commands, &mut enemy_id, &mut last_attribute_array, &room_config, &room_manager, &num_players, &mut rng);
+        return;
+    }
+
+    /* survived it, reward goes anywhere nothing is sitting yet */
+    println!("challenge in room {} done, doors open", room.0);
+    let grid = room_manager.current_grid_ref();
+    let taken: Vec<(usize, usize)> = items.iter()
+        .map(|transform| pixel_to_tile(grid, transform.translation.truncate()))
+        .collect();
+    let mut spots = reachable_spots(grid, &room_manager.current_key_tiles(), ITEM_EDGE_MARGIN);
+    spots.retain(|spot| !taken.contains(spot));
+    let mut rng = dungeon_rng.wave(room, CHALLENGE_WAVES + 1);
+    for _ in 0..CHALLENGE_REWARD {
+        let Some(spot) = take_spot(grid, &mut spots, &mut rng) else {
+            break;
+        };
+        commands.spawn((
+            Transform::from_xyz(spot.x, spot.y, room.z() + 0.1),
+            Potion,
+            Room,
+        ));
+    }
+    room_manager.room_state_mut(room).challenge_done = true;
+    challenge.room = None;
+    room_change.send(RoomChangeEvent(true));
+}
+
+/* first time into a rest room patches everyone up. the map resend
+ * carries the new health back to the clients */
+pub fn rest_heal(
+    mut players: Query<&mut Health, With<Player>>,
+    mut room_manager: ResMut<RoomManager>,
+    mut room_change: EventWriter<RoomChangeEvent>,
+){
+    if room_manager.current_room_type() != RoomType::Rest {
+        return;
+    }
+    let room = room_manager.current_room_id();
+    if room_manager.room_state(room).map_or(false, |state| state.rested) {
+        return;
+    }
+    for mut health in players.iter_mut() {
+        if health.current > 0. {
+            health.current = health.max;
+        }
+    }
+    println!("party rests up in room {}", room.0);
+    room_manager.room_state_mut(room).rested = true;
+    room_change.send(RoomChangeEvent(true));
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use rand::{Rng, rngs::StdRng, distributions::{Distribution, WeightedIndex}};
+
+use crate::dungeon_rng::{DungeonRng, RngStream};
+use crate::markov_chains::{LastAttributeArray, STEALTH_PROPS};
+use crate::reachability::{pixel_to_tile, reachable_spots, tile_to_pixel, ITEM_EDGE_MARGIN};
+use crate::room_gen::{RoomConfig, RoomManager};
+
+/* STEALTH PROPS. every room rolls the stealth_props markov chain and
+ * gets that state's stealth_props (room_config.ron) clumps of:
+ *   cover  - knee high crates in a short line. crouch and enemies cant
+ *            see through them
+ *   grass  - tall grass and vines, enemies have to be a lot closer to
+ *            spot you in it, closer still if you crouch
+ *   shadow - dark patch, cuts how far enemies see you
+ *   noisy  - creaky boards. walk on them standing up and every enemy in
+ *            earshot hears you, walls or not. crouch across
+ * none of them block walking. they're rolled once with the room, kept in
+ * its RoomState and go out with the map (codes 19-22), enemy_movement
+ * asks a PropMap what the tile under each player does */
+
+// spot distance multiplier standing in shadow
+pub const SHADOW_SPOT: f32 = 0.5;
+// in tall grass, and crouched in it
+pub const GRASS_SPOT: f32 = 0.6;
+pub const GRASS_CROUCH_SPOT: f32 = 0.35;
+// creaky boards carry this many spot distances
+pub const NOISE_HEARD: f32 = 1.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropKind {
+    Cover,
+    Grass,
+    Shadow,
+    Noisy,
+}
+
+impl PropKind {
+    pub const ALL: [PropKind; 4] = [PropKind::Cover, PropKind::Grass, PropKind::Shadow, PropKind::Noisy];
+
+    /* how many tiles one clump is */
+    pub fn clump_size(&self) -> (usize, usize) {
+        match self {
+            PropKind::Cover => (2, 4),
+            PropKind::Grass => (4, 9),
+            PropKind::Shadow => (6, 12),
+            PropKind::Noisy => (2, 5),
+        }
+    }
+
+    /* map packet code, 19-22 */
+    pub fn code(&self) -> u8 {
+        19 + *self as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<PropKind> {
+        PropKind::ALL.get(code.checked_sub(19)? as usize).copied()
+    }
+}
+
+/* chance of each kind (ALL order) per stealth_props state. quiet rooms
+ * lean toward places to hide, loud ones toward boards that give you away */
+pub const PROP_WEIGHTS: [[u32; 4]; 3] = [
+    [3, 3, 3, 1],
+    [2, 2, 2, 2],
+    [2, 1, 1, 3],
+];
+
+fn roll_kind(weights: [u32; 4], rng: &mut StdRng) -> PropKind {
+    PropKind::ALL[WeightedIndex::new(weights).unwrap().sample(rng)]
+}
+
+/* new room, scatter its props. only on floor you can reach, so nothing
+ * ends up behind a wall where it does no good */
+pub fn roll_stealth_props(
+    room_manager: &mut RoomManager,
+    last_attribute_array: &LastAttributeArray,
+    room_config: &RoomConfig,
+    dungeon_rng: &DungeonRng,
+) {
+    let room = room_manager.current_room_id();
+    let state = last_attribute_array.get_attribute(STEALTH_PROPS).unwrap_or(1);
+    let (min, max) = room_config.get_stealth_props(state);
+    let mut rng = dungeon_rng.stream(RngStream::Props, room);
+    let clumps = rng.gen_range(min..=max);
+    if clumps == 0 {
+        return;
+    }
+    let weights = PROP_WEIGHTS.get(state as usize).copied().unwrap_or(PROP_WEIGHTS[1]);
+
+    let grid = room_manager.current_grid_ref();
+    let mut open = reachable_spots(grid, &room_manager.current_key_tiles(), ITEM_EDGE_MARGIN);
+    let mut props = Vec::new();
+    for _ in 0..clumps {
+        if open.is_empty() {
+            println!("room {} ran out of open floor for stealth props", room.0);
+            break;
+        }
+        let kind = roll_kind(weights, &mut rng);
+        let (least, most) = kind.clump_size();
+        let size = rng.gen_range(least..=most);
+        let clump = grow_clump(&mut open, size, kind == PropKind::Cover, &mut rng);
+        props.extend(clump.into_iter().map(|tile| (tile_to_pixel(grid, tile), kind)));
+    }
+
+    println!("room {} got {} stealth prop tiles", room.0, props.len());
+    room_manager.room_state_mut(room).props = props;
+}
+
+/* `size` touching tiles out of open (they're taken out as they're
+ * used), grown off a random one. straight clumps keep to a line. comes
+ * up short if it gets boxed in, empty only if open is */
+pub fn grow_clump(open: &mut Vec<(usize, usize)>, size: usize, straight: bool, rng: &mut StdRng) -> Vec<(usize, usize)> {
+    if open.is_empty() {
+        return Vec::new();
+    }
+    let mut clump = vec![open.swap_remove(rng.gen_range(0..open.len()))];
+    while clump.len() < size {
+        let next: Vec<usize> = open.iter().enumerate()
+            .filter(|(_, tile)| clump.iter().any(|had| had.0.abs_diff(tile.0) + had.1.abs_diff(tile.1) == 1))
+            .filter(|(_, tile)| !straight || clump.len() < 2
+                || (clump[0].0 == clump[1].0 && tile.0 == clump[0].0)
+                || (clump[0].1 == clump[1].1 && tile.1 == clump[0].1))
+            .map(|(index, _)| index)
+            .collect();
+        if next.is_empty() {
+            break;
+        }
+        clump.push(open.swap_remove(next[rng.gen_range(0..next.len())]));
+    }
+    clump
+}
+
+/* the current room's props by tile, for asking about one spot at a time */
+pub struct PropMap<'a> {
+    grid: Option<&'a Vec<Vec<u32>>>,
+    tiles: HashMap<(usize, usize), PropKind>,
+}
+
+impl<'a> PropMap<'a> {
+    pub fn current(room_manager: &'a RoomManager) -> Self {
+        let props = room_manager.room_state(room_manager.current_room_id())
+            .map(|state| state.props.as_slice())
+            .unwrap_or(&[]);
+        if props.is_empty() {
+            return Self { grid: None, tiles: HashMap::new() };
+        }
+        let grid = room_manager.current_grid_ref();
+        Self {
+            grid: Some(grid),
+            tiles: props.iter().map(|(spot, kind)| (pixel_to_tile(grid, *spot), *kind)).collect(),
+        }
+    }
+
+    pub fn at(&self, spot: Vec2) -> Option<PropKind> {
+        let grid = self.grid?;
+        self.tiles.get(&pixel_to_tile(grid, spot)).copied()
+    }
+
+    /* how far off an enemy with spot distance `spot` sees someone here */
+    pub fn spot_range(&self, spot: f32, at: Vec2, crouching: bool) -> f32 {
+        match self.at(at) {
+            Some(PropKind::Shadow) => spot * SHADOW_SPOT,
+            Some(PropKind::Grass) if crouching => spot * GRASS_CROUCH_SPOT,
+            Some(PropKind::Grass) => spot * GRASS_SPOT,
+            _ => spot,
+        }
+    }
+
+    /* standing up on creaky boards, enemies hear it through walls */
+    pub fn heard(&self, at: Vec2, crouching: bool) -> bool {
+        !crouching && self.at(at) == Some(PropKind::Noisy)
+    }
+
+    /* does a line of sight sample at `point` get stopped by cover. only
+     * when the target's crouched, and not the tile either end is on */
+    pub fn covers(&self, point: Vec2, from: Vec2, to: Vec2, crouching: bool) -> bool {
+        let Some(grid) = self.grid.filter(|_| crouching) else {
+            return false;
+        };
+        let tile = pixel_to_tile(grid, point);
+        self.tiles.get(&tile) == Some(&PropKind::Cover)
+            && tile != pixel_to_tile(grid, from)
+            && tile != pixel_to_tile(grid, to)
+    }
+}
@@ -0,0 +1,184 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::collision::Aabb;
+use crate::cuscuta_resources::{Health, PlayerCount, Pot, TILE_SIZE};
+use crate::dungeon_rng::{DungeonRng, RngStream};
+use crate::player::Player;
+use crate::reachability::{reachable_spots, tile_to_pixel, wall_keeps_room_open, ITEM_EDGE_MARGIN};
+use crate::room_gen::{draw_inner_wall, InnerWall, InnerWallStartPos, Potion, RoomChangeEvent, RoomConfig, RoomManager, RoomType};
+
+/* TEAMWORK ROOMS. with more than one player in the party some combat
+ * rooms roll a puzzle on the way in (room_config.ron teamwork_chance)
+ * and keep their doors shut until it's done:
+ *   plates - two pressure plates across the room from each other, both
+ *            stood on at once opens the doors for good
+ *   lever  - doors only open while somebody holds the lever, so one
+ *            player holds it while the rest vote their way out
+ *   split  - a wall down the middle with a plate on each side, same as
+ *            plates but you have to split up to get there
+ * everything lives in the room's RoomState, the server checks who is
+ * standing where and resends the map when a plate or lever changes.
+ * if the party is down to one living player the puzzle gives up */
+
+// a split room's divider stops this many tiles short of the outer walls
+pub const SPLIT_GAP: usize = 4;
+// and sits this many tiles off center so it misses the middle and the doors
+pub const SPLIT_OFFSET: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PuzzleKind {
+    Plates,
+    Lever,
+    Split,
+}
+
+impl PuzzleKind {
+    pub const ALL: [PuzzleKind; 3] = [PuzzleKind::Plates, PuzzleKind::Lever, PuzzleKind::Split];
+}
+
+/* one room's puzzle. spots are pixels, room centered on 0,0 */
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub kind: PuzzleKind,
+    pub spots: Vec<Vec2>,
+    /* somebody alive is standing on each spot, as of the last check */
+    pub held: Vec<bool>,
+    pub solved: bool,
+}
+
+impl Puzzle {
+    pub fn new(kind: PuzzleKind, spots: Vec<Vec2>) -> Self {
+        let held = vec![false; spots.len()];
+        Self {
+            kind: kind,
+            spots: spots,
+            held: held,
+            solved: false,
+        }
+    }
+
+    /* can the party walk out right now */
+    pub fn open(&self) -> bool {
+        self.solved || (self.kind == PuzzleKind::Lever && self.held.iter().any(|held| *held))
+    }
+}
+
+/* first time into a room, maybe give it a puzzle. runs after check_door
+ * so the room's walls and items are already in */
+pub fn set_up_puzzle(
+    mut commands: Commands,
+    items: Query<&Transform, Or<(With<Potion>, With<Pot>)>>,
+    mut room_manager: ResMut<RoomManager>,
+    room_config: Res<RoomConfig>,
+    num_players: Res<PlayerCount>,
+    dungeon_rng: Res<DungeonRng>,
+    mut room_change: EventWriter<RoomChangeEvent>,
+){
+    let room = room_manager.current_room_id();
+    if room_manager.room_state(room).map_or(false, |state| state.teamwork_rolled) {
+        return;
+    }
+    room_manager.room_state_mut(room).teamwork_rolled = true;
+
+    /* solo runs, the start, the boss, hallways and special rooms never get one */
+    let plain = room_manager.current_layout_room().map_or(false, |slot| !slot.is_hallway() && slot.room_type == RoomType::Combat);
+    if num_players.count < 2 || !plain || room == room_manager.layout.start || room == room_manager.layout.boss {
+        return;
+    }
+    let mut rng = dungeon_rng.stream(RngStream::Teamwork, room);
+    if rng.gen::<f32>() >= room_config.teamwork_chance() {
+        return;
+    }
+
+    let grid = room_manager.current_grid_ref().clone();
+    let (width, height) = (grid.len(), grid[0].len());
+    let taken: Vec<Vec2> = items.iter().map(|transform| transform.translation.truncate()).collect();
+    let mut kind = PuzzleKind::ALL[rng.gen_range(0..PuzzleKind::ALL.len())];
+
+    /* split rooms get their wall first so the plates land either side of it */
+    let divider = width / 2 + SPLIT_OFFSET;
+    if kind == PuzzleKind::Split {
+        let wall = InnerWall {
+            start_pos: InnerWallStartPos { x: divider + 1, y: SPLIT_GAP + 1 },
+            length_direction_vector: (1, (height - 1 - 2 * SPLIT_GAP) as i32 + 3),
+        };
+        if height > 2 * SPLIT_GAP + 4 && wall_keeps_room_open(&grid, &wall, &room_manager.current_key_tiles()) {
+            room_manager.add_inner_wall(room, wall.clone());
+            draw_inner_wall(&mut commands, &wall, room.z(), width, height, &mut room_manager);
+        } else {
+            kind = PuzzleKind::Plates;
+        }
+    }
+
+    let grid = room_manager.current_grid_ref();
+    let spots: Vec<(usize, usize)> = reachable_spots(grid, &room_manager.current_key_tiles(), ITEM_EDGE_MARGIN)
+        .into_iter()
+        .filter(|tile| !taken.contains(&tile_to_pixel(grid, *tile)))
+        .collect();
+    let pick = |from: Vec<(usize, usize)>, rng: &mut rand::rngs::StdRng| {
+        (!from.is_empty()).then(|| from[rng.gen_range(0..from.len())])
+    };
+    let tiles = match kind {
+        PuzzleKind::Lever => pick(spots, &mut rng).map(|lever| vec![lever]),
+        PuzzleKind::Plates => pick(spots.clone(), &mut rng).map(|first| {
+            /* second plate as far from the first as the room allows */
+            let far = |tile: &&(usize, usize)| tile.0.abs_diff(first.0).pow(2) + tile.1.abs_diff(first.1).pow(2);
+            let second = *spots.iter().max_by_key(far).unwrap_or(&first);
+            vec![first, second]
+        }),
+        PuzzleKind::Split => {
+            let left = pick(spots.iter().copied().filter(|tile| tile.0 < divider).collect(), &mut rng);
+            let right = pick(spots.iter().copied().filter(|tile| tile.0 > divider).collect(), &mut rng);
+            left.zip(right).map(|(left, right)| vec![left, right])
+        }
+    };
+    let Some(tiles) = tiles.filter(|tiles| tiles.len() < 2 || tiles[0] != tiles[1]) else {
+        println!("room {} had no floor left for a {:?} puzzle", room.0, kind);
+        return;
+    };
+
+    let spots = tiles.iter().map(|tile| tile_to_pixel(grid, *tile)).collect();
+    println!("room {} is a teamwork room: {:?}", room.0, kind);
+    room_manager.room_state_mut(room).puzzle = Some(Puzzle::new(kind, spots));
+    room_change.send(RoomChangeEvent(true));
+}
+
+/* who's standing on what. plates solve once they're all held together,
+ * levers just open the doors while somebody is on them */
+pub fn run_puzzle(
+    players: Query<(&Transform, &Health), With<Player>>,
+    mut room_manager: ResMut<RoomManager>,
+    mut room_change: EventWriter<RoomChangeEvent>,
+){
+    let room = room_manager.current_room_id();
+    if room_manager.room_state(room).and_then(|state| state.puzzle.as_ref()).map_or(true, |puzzle| puzzle.solved) {
+        return;
+    }
+    let Some(puzzle) = room_manager.room_state_mut(room).puzzle.as_mut() else {
+        return;
+    };
+
+    let living: Vec<Aabb> = players.iter()
+        .filter(|(_, health)| health.current > 0.)
+        .map(|(transform, _)| Aabb::new(transform.translation, Vec2::splat(TILE_SIZE as f32)))
+        .collect();
+    let held: Vec<bool> = puzzle.spots.iter()
+        .map(|spot| {
+            let plate = Aabb::new(spot.extend(0.), Vec2::splat(TILE_SIZE as f32));
+            living.iter().any(|player| player.intersects(&plate))
+        })
+        .collect();
+
+    if living.len() < 2 {
+        println!("nobody left to help, room {} gives up its puzzle", room.0);
+        puzzle.solved = true;
+    } else if puzzle.kind != PuzzleKind::Lever && held.iter().all(|held| *held) {
+        println!("room {} puzzle solved", room.0);
+        puzzle.solved = true;
+    }
+    if held != puzzle.held || puzzle.solved {
+        puzzle.held = held;
+        room_change.send(RoomChangeEvent(true));
+    }
+}
@@ -0,0 +1,287 @@
+use bevy::prelude::*;
+use rand::{Rng, rngs::StdRng, distributions::{Distribution, WeightedIndex}};
+use serde::Serialize;
+
+use crate::carnage::{CarnageEvent, CarnageRules, CarnageTrigger, Detection};
+use crate::cuscuta_resources::{ClientId, Health, TILE_SIZE};
+use crate::dungeon_rng::{DungeonRng, RngStream};
+use crate::enemies::{Enemy, EnemyMovement};
+use crate::markov_chains::{LastAttributeArray, TRAPS};
+use crate::network::{HazardPacket, Header, Sequence, ServerPacket, UDP};
+use crate::player::{Crouch, NetworkId, Player};
+use crate::reachability::{pixel_to_tile, reachable_spots, tile_to_pixel, ITEM_EDGE_MARGIN};
+use crate::room_gen::{RoomChangeEvent, RoomConfig, RoomManager, RoomType};
+use crate::stealth::grow_clump;
+
+/* TRAPS. rooms past the start roll the traps markov chain and get that
+ * state's traps (room_config.ron) clumps of:
+ *   spikes   - step on them and they come up, SPIKE_DAMAGE then they
+ *              take SPIKE_REARM to go back down
+ *   acid     - pools that eat ACID_DAMAGE every ACID_TICK you stand in
+ *              them and slow you down for a bit after
+ *   spores   - vents that puff every SPORE_PERIOD, anyone close gets
+ *              poisoned for SPORE_POISON seconds
+ *   tripwire - a line across the floor. walk into it standing up and
+ *              every enemy within ALARM_RADIUS comes running and you've
+ *              been spotted. crouch and you step over. goes once
+ * the chain leans on party carnage like the rest, so loud parties walk
+ * into more of them. traps live in the room's RoomState and go out with
+ * the map (codes 23-26). the server sets them off and sends whoever got
+ * hit a HazardPacket, their client takes the health and runs the
+ * statuses (Afflictions) since health is client side */
+
+pub const SPIKE_DAMAGE: f32 = 15.;
+pub const SPIKE_REARM: f32 = 1.5;
+pub const ACID_DAMAGE: f32 = 3.;
+pub const ACID_TICK: f32 = 0.5;
+// seconds of slow every acid tick leaves you with
+pub const ACID_SLOW: f32 = 1.5;
+pub const SLOW_MULTIPLIER: f32 = 0.5;
+pub const SPORE_PERIOD: f32 = 3.;
+// tiles
+pub const SPORE_RADIUS: f32 = 2.5;
+pub const SPORE_POISON: f32 = 4.;
+pub const POISON_DPS: f32 = 2.;
+// tiles
+pub const ALARM_RADIUS: f32 = 12.;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapKind {
+    Spikes,
+    Acid,
+    Spores,
+    Tripwire,
+}
+
+impl TrapKind {
+    pub const ALL: [TrapKind; 4] = [TrapKind::Spikes, TrapKind::Acid, TrapKind::Spores, TrapKind::Tripwire];
+
+    /* how many tiles one clump is */
+    pub fn clump_size(&self) -> (usize, usize) {
+        match self {
+            TrapKind::Spikes => (1, 3),
+            TrapKind::Acid => (2, 6),
+            TrapKind::Spores => (1, 1),
+            TrapKind::Tripwire => (3, 5),
+        }
+    }
+
+    /* map packet code, 23-26 */
+    pub fn code(&self) -> u8 {
+        23 + *self as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<TrapKind> {
+        TrapKind::ALL.get(code.checked_sub(23)? as usize).copied()
+    }
+}
+
+/* chance of each kind (ALL order) per traps state. tripwires are there
+ * to catch sneaking parties, the rest to hurt */
+pub const TRAP_WEIGHTS: [[u32; 4]; 3] = [
+    [1, 1, 1, 4],
+    [2, 2, 2, 2],
+    [4, 3, 2, 1],
+];
+
+/* one trap tile. spot is pixels, room centered on 0,0 */
+#[derive(Clone, Debug)]
+pub struct Trap {
+    pub spot: Vec2,
+    pub kind: TrapKind,
+    /* elapsed seconds it can go off again */
+    pub ready_at: f32,
+    /* tripwires only go once */
+    pub sprung: bool,
+}
+
+impl Trap {
+    pub fn new(spot: Vec2, kind: TrapKind) -> Self {
+        Self {
+            spot: spot,
+            kind: kind,
+            ready_at: 0.,
+            sprung: false,
+        }
+    }
+}
+
+/* new room, lay its traps. reachable floor only and never on a stealth
+ * prop, shops and rest rooms stay safe */
+pub fn roll_traps(
+    room_manager: &mut RoomManager,
+    last_attribute_array: &LastAttributeArray,
+    room_config: &RoomConfig,
+    dungeon_rng: &DungeonRng,
+) {
+    if matches!(room_manager.current_room_type(), RoomType::Shop | RoomType::Rest) {
+        return;
+    }
+    let room = room_manager.current_room_id();
+    let state = last_attribute_array.get_attribute(TRAPS).unwrap_or(1);
+    let (min, max) = room_config.get_traps(state);
+    let mut rng: StdRng = dungeon_rng.stream(RngStream::Traps, room);
+    let clumps = rng.gen_range(min..=max);
+    if clumps == 0 {
+        return;
+    }
+    let weights = WeightedIndex::new(TRAP_WEIGHTS.get(state as usize).copied().unwrap_or(TRAP_WEIGHTS[1])).unwrap();
+
+    let grid = room_manager.current_grid_ref();
+    let props: Vec<(usize, usize)> = room_manager.room_state(room)
+        .map(|state| state.props.iter().map(|(spot, _)| pixel_to_tile(grid, *spot)).collect())
+        .unwrap_or_default();
+    let mut open = reachable_spots(grid, &room_manager.current_key_tiles(), ITEM_EDGE_MARGIN);
+    open.retain(|tile| !props.contains(tile));
+    let mut traps = Vec::new();
+    for _ in 0..clumps {
+        if open.is_empty() {
+            println!("room {} ran out of open floor for traps", room.0);
+            break;
+        }
+        let kind = TrapKind::ALL[weights.sample(&mut rng)];
+        let (least, most) = kind.clump_size();
+        let size = rng.gen_range(least..=most);
+        let clump = grow_clump(&mut open, size, kind == TrapKind::Tripwire, &mut rng);
+        traps.extend(clump.into_iter().map(|tile| Trap::new(tile_to_pixel(grid, tile), kind)));
+    }
+
+    println!("room {} got {} trap tiles", room.0, traps.len());
+    room_manager.room_state_mut(room).traps = traps;
+}
+
+/* server side, sets off whatever the party is standing on (or near, for
+ * spores) and tells the clients that got hit */
+pub fn run_traps(
+    time: Res<Time>,
+    players: Query<(&Transform, &Health, &NetworkId, &Crouch), With<Player>>,
+    mut enemies: Query<(&Transform, &mut EnemyMovement), (With<Enemy>, Without<Player>)>,
+    mut room_manager: ResMut<RoomManager>,
+    mut detection: ResMut<Detection>,
+    rules: Res<CarnageRules>,
+    mut carnage_rules: EventWriter<CarnageEvent>,
+    (udp, server_seq): (Res<UDP>, Res<Sequence>),
+    mut room_change: EventWriter<RoomChangeEvent>,
+){
+    let now = time.elapsed_seconds();
+    let room = room_manager.current_room_id();
+    if room_manager.room_state(room).map_or(true, |state| state.traps.is_empty()) {
+        return;
+    }
+    let living: Vec<_> = players.iter().filter(|(_, health, _, _)| health.current > 0.).collect();
+
+    let mut hits = Vec::new();
+    let mut alarms = Vec::new();
+    for trap in room_manager.room_state_mut(room).traps.iter_mut().filter(|trap| !trap.sprung && now >= trap.ready_at) {
+        /* on the tile, or in puffing distance of a vent */
+        let near = |transform: &Transform| {
+            let off = (transform.translation.truncate() - trap.spot).abs();
+            match trap.kind {
+                TrapKind::Spores => off.length() < SPORE_RADIUS * TILE_SIZE as f32,
+                _ => off.x < TILE_SIZE as f32 / 2. && off.y < TILE_SIZE as f32 / 2.,
+            }
+        };
+        let caught: Vec<_> = living.iter()
+            .filter(|(transform, _, _, crouch)| near(transform) && !(trap.kind == TrapKind::Tripwire && crouch.crouching))
+            .map(|(_, _, id, _)| *id)
+            .collect();
+        /* vents go off on a timer whether anyone's there or not */
+        if trap.kind == TrapKind::Spores {
+            trap.ready_at = now + SPORE_PERIOD;
+        }
+        if caught.is_empty() {
+            continue;
+        }
+        let (damage, slow, poison) = match trap.kind {
+            TrapKind::Spikes => {
+                trap.ready_at = now + SPIKE_REARM;
+                (SPIKE_DAMAGE, 0., 0.)
+            }
+            TrapKind::Acid => {
+                trap.ready_at = now + ACID_TICK;
+                (ACID_DAMAGE, ACID_SLOW, 0.)
+            }
+            TrapKind::Spores => (0., 0., SPORE_POISON),
+            TrapKind::Tripwire => {
+                trap.sprung = true;
+                alarms.push(trap.spot);
+                for id in caught.iter() {
+                    if detection.spot(id.id, now, rules.undetected_window) {
+                        carnage_rules.send(CarnageEvent{player: id.id, trigger: CarnageTrigger::Detected});
+                    }
+                }
+                continue;
+            }
+        };
+        hits.extend(caught.into_iter().map(|id| (id.addr, HazardPacket {
+            head: Header::new(id.id, server_seq.clone()),
+            damage: damage,
+            slow: slow,
+            poison: poison,
+        })));
+    }
+
+    for (addr, hazard) in hits {
+        let mut serializer = flexbuffers::FlexbufferSerializer::new();
+        ServerPacket::HazardPacket(hazard).serialize(&mut serializer).unwrap();
+        udp.socket.send_to(serializer.view(), addr).unwrap();
+    }
+
+    /* tripped wires bring everyone in earshot over, and come off the map */
+    for spot in alarms.iter() {
+        println!("tripwire in room {} went off", room.0);
+        for (transform, mut movement) in enemies.iter_mut() {
+            if transform.translation.truncate().distance(*spot) < ALARM_RADIUS * TILE_SIZE as f32 {
+                movement.lastseen = spot.extend(transform.translation.z);
+            }
+        }
+    }
+    if !alarms.is_empty() {
+        room_change.send(RoomChangeEvent(true));
+    }
+}
+
+/* client side, what traps have done to us lately. seconds left of each */
+#[derive(Resource)]
+pub struct Afflictions {
+    pub slow: f32,
+    pub poison: f32,
+}
+
+impl Afflictions {
+    pub fn new() -> Self {
+        Self {
+            slow: 0.,
+            poison: 0.,
+        }
+    }
+
+    /* a fresh dose tops up, it doesnt stack */
+    pub fn take(&mut self, hazard: &HazardPacket) {
+        self.slow = self.slow.max(hazard.slow);
+        self.poison = self.poison.max(hazard.poison);
+    }
+
+    pub fn speed_multiplier(&self) -> f32 {
+        if self.slow > 0. { SLOW_MULTIPLIER } else { 1. }
+    }
+}
+
+/* poison eats our health while it lasts, everything wears off */
+pub fn tick_afflictions(
+    time: Res<Time>,
+    mut afflictions: ResMut<Afflictions>,
+    mut players: Query<(&mut Health, &NetworkId), With<Player>>,
+    client_id: Res<ClientId>,
+){
+    let dt = time.delta_seconds();
+    if afflictions.poison > 0. {
+        for (mut health, id) in players.iter_mut() {
+            if id.id == client_id.id && health.current > 0. {
+                health.current -= POISON_DPS * dt;
+            }
+        }
+    }
+    afflictions.slow = (afflictions.slow - dt).max(0.);
+    afflictions.poison = (afflictions.poison - dt).max(0.);
+}
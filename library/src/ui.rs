@@ -1,9 +1,11 @@
 use bevy::prelude::*;
-use bevy::color::palettes::css::{SEA_GREEN, RED, MAROON, BLACK};
+use bevy::color::palettes::css::{SEA_GREEN, RED, MAROON, BLACK, GOLD, WHITE};
 use serde::{Serialize, Deserialize};
 
-use crate::cuscuta_resources::{ClientId, Health, TILE_SIZE};
+use crate::cuscuta_resources::{player_color, ClientId, Health, TILE_SIZE};
+use crate::network::DoorVotePacket;
 use crate::player::{NetworkId, Player, ItemStatus};
+use crate::room_gen::{ClientDoor, ClientRoomManager, DoorType};
 
 
 /* stupud to do math like this but basically window is  */
@@ -23,6 +25,73 @@ pub struct CarnageBar{
 #[derive(Component)]
 pub struct PotionIcon;
 
+/* server side. every player has their own bar, room gen gets one
+ * number for the party: the average pulled toward whoever has the
+ * most carnage. weight 0 is a plain average, 1 is just the max */
+#[derive(Resource)]
+pub struct PartyCarnage{
+    pub weight: f32,
+    pub bar: CarnageBar,
+}
+
+impl PartyCarnage{
+    pub fn new(weight: f32) -> Self{
+        Self{
+            weight: weight.clamp(0., 1.),
+            bar: CarnageBar::new(),
+        }
+    }
+
+    pub fn update<'a>(&mut self, bars: impl Iterator<Item = &'a CarnageBar>){
+        let mut total = 0.;
+        let mut count = 0.;
+        let mut most: f32 = 0.;
+        for bar in bars{
+            total += bar.carnage;
+            count += 1.;
+            most = most.max(bar.carnage);
+        }
+        /* empty party, back to neutral */
+        if count == 0. {
+            self.bar = CarnageBar::new();
+            return;
+        }
+        let average = total / count;
+        self.bar.carnage = average + (most - average) * self.weight;
+        self.bar.stealth = 100. - self.bar.carnage;
+    }
+
+    /* 0-1, what generation skews on */
+    pub fn percent(&self) -> f32{
+        self.bar.carnage / 100.
+    }
+}
+
+/* client side copy of the last CarnagePacket, everyone's bars
+ * plus the party value so the hud can show all of it */
+#[derive(Resource)]
+pub struct CarnageStatus{
+    pub bars: Vec<(u8, CarnageBar)>,
+    pub party: CarnageBar,
+}
+
+impl CarnageStatus{
+    pub fn new() -> Self{
+        Self{
+            bars: Vec::new(),
+            party: CarnageBar::new(),
+        }
+    }
+
+    pub fn get(&self, id: u8) -> Option<&CarnageBar>{
+        self.bars.iter().find(|(bar_id, _)| *bar_id == id).map(|(_, bar)| bar)
+    }
+}
+
+/* tick on our carnage bar showing where the party sits */
+#[derive(Component)]
+pub struct PartyMarker;
+
 impl CarnageBar{
     pub fn new() -> Self {
         Self{
@@ -63,6 +132,38 @@ impl CarnageBar{
     }
 }
 
+/* last door vote we heard about. remaining ticks down locally
+ * between packets so the countdown doesnt stutter */
+#[derive(Resource)]
+pub struct DoorVoteStatus{
+    pub door: Option<DoorType>,
+    pub remaining: f32,
+    pub voters: Vec<u8>,
+}
+
+impl DoorVoteStatus{
+    pub fn new() -> Self{
+        Self{
+            door: None,
+            remaining: 0.,
+            voters: Vec::new(),
+        }
+    }
+
+    pub fn set(&mut self, pack: &DoorVotePacket){
+        self.door = pack.door;
+        self.remaining = pack.remaining;
+        self.voters = pack.voters.clone();
+    }
+}
+
+#[derive(Component)]
+pub struct DoorVoteText;
+
+/* f3 readout of what the markov chain picked for this room */
+#[derive(Component)]
+pub struct RoomDebugText;
+
 #[derive(Component)]
 pub struct Red;
 
@@ -130,6 +231,25 @@ pub fn client_spawn_ui(
         Green,
     ));
 
+    // PARTY MARKER
+    commands.spawn(
+        (NodeBundle{
+            style: Style {
+                width: Val::Px(4.),
+                height: Val::Px(32.),
+                margin: UiRect {
+                    top: Val::VMin(83.), left: Val::Px(196.),
+                    .. default()
+                },
+                ..default()
+            },
+            z_index: ZIndex::Global(6),
+            ..default()
+        },
+        UiImage::solid_color(Color::from(WHITE)),
+        PartyMarker,
+    ));
+
     // HEALTH BAR RED
     commands.spawn(
         (NodeBundle{
@@ -187,6 +307,43 @@ pub fn client_spawn_ui(
         UiImage::new(asset_server.load("ui/potion_icon_empty.png")),
         PotionIcon,
     ));
+
+    // DOOR VOTE COUNTDOWN, empty till someone steps on a door
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 28.,
+                color: Color::from(GOLD),
+                ..default()
+            },
+        ).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::VMin(5.),
+            left: Val::VMax(40.),
+            ..default()
+        }),
+        DoorVoteText,
+    ));
+
+    // ROOM DEBUG, hidden till f3
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 18.,
+                color: Color::from(WHITE),
+                ..default()
+            },
+        ).with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::VMin(2.),
+            left: Val::VMin(2.),
+            ..default()
+        }),
+        Visibility::Hidden,
+        RoomDebugText,
+    ));
 }
 
 
@@ -194,6 +351,8 @@ pub fn update_ui_elements(
     mut red_q: Query<&mut Style, (With<Red>, Without<Green>, Without<Health>, Without<CarnageBar>)>,
     mut green_q: Query<&mut Style, (With<Green>, Without<Red>, Without<Health>, Without<CarnageBar>)>,
     mut health_bar : Query<&mut Style, (With<Health>, Without<Green>, Without<Red>, Without<CarnageBar>)>,
+    mut party_marker: Query<&mut Style, (With<PartyMarker>, Without<Health>, Without<Green>, Without<Red>, Without<CarnageBar>)>,
+    status: Res<CarnageStatus>,
     mut potion_icon_q: Query<&mut UiImage, With<PotionIcon>>,
     player_q : Query<(&Health, &ItemStatus, &NetworkId), With<Player>>, 
     mut carnage_q: Query<&CarnageBar>,
@@ -209,6 +368,9 @@ pub fn update_ui_elements(
     red.width = Val::Px(carnage.carnage * 3. );
     green.width = Val::Px(carnage.stealth * 3. );
     red.margin.left=Val::Px(48.+carnage.stealth * 3.);
+    if let Ok(mut marker) = party_marker.get_single_mut(){
+        marker.margin.left = Val::Px(46.+status.party.stealth * 3.);
+    }
 
     let full_health_width = 150.0;
 
@@ -227,4 +389,134 @@ pub fn update_ui_elements(
             } 
         }
     }
-}
\ No newline at end of file
+}
+/* little tinted bars up top, one per teammate (not us, we have
+ * the big one) with a red strip under it for their carnage.
+ * spawned the first time we hear about a player and cleaned up
+ * when they go away */
+#[derive(Component)]
+pub struct TeammateBar{
+    pub id: u8,
+    pub carnage: bool,
+}
+
+const TEAMMATE_BAR_WIDTH: f32 = 100.;
+
+pub fn update_teammate_bars(
+    mut commands: Commands,
+    mut bar_q: Query<(Entity, &TeammateBar, &mut Style)>,
+    player_q: Query<(&Health, &NetworkId), With<Player>>,
+    client_id: Res<ClientId>,
+    status: Res<CarnageStatus>,
+){
+    /* players come and go so hand out rows by id order each frame */
+    let mut teammates: Vec<(u8, f32, f32)> = player_q.iter()
+        .filter(|(_, id)| id.id != client_id.id)
+        .map(|(health, id)| {
+            let carnage = status.get(id.id).map_or(0., |bar| bar.carnage / 100.);
+            (id.id, (health.current / health.max).clamp(0., 1.), carnage)
+        })
+        .collect();
+    teammates.sort_by_key(|(id, _, _)| *id);
+
+    for (entity, bar, _) in bar_q.iter(){
+        if !teammates.iter().any(|(id, _, _)| *id == bar.id){
+            commands.entity(entity).despawn();
+        }
+    }
+
+    for (row, (id, health, carnage)) in teammates.iter().enumerate(){
+        let row_top = 8. + row as f32 * 20.;
+        /* health bar, then the carnage strip tucked under it */
+        for (is_carnage, top, height, ratio, color) in [
+            (false, row_top, 10., *health, player_color(*id)),
+            (true, row_top + 11., 4., *carnage, Color::from(RED)),
+        ]{
+            let width = Val::Px(TEAMMATE_BAR_WIDTH * ratio);
+            match bar_q.iter_mut().find(|(_, bar, _)| bar.id == *id && bar.carnage == is_carnage){
+                Some((_, _, mut style)) => {
+                    style.width = width;
+                    style.margin.top = Val::Px(top);
+                }
+                None => {
+                    commands.spawn((
+                        NodeBundle{
+                            style: Style {
+                                position_type: PositionType::Absolute,
+                                width: width,
+                                height: Val::Px(height),
+                                margin: UiRect {
+                                    top: Val::Px(top), left: Val::Px(8.),
+                                    .. default()
+                                },
+                                ..default()
+                            },
+                            z_index: ZIndex::Global(5),
+                            ..default()
+                        },
+                        UiImage::solid_color(color),
+                        TeammateBar{id: *id, carnage: is_carnage},
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/* countdown text plus a gold tint on whichever door the party picked.
+ * the rest show their room marker, or red while a challenge has them shut */
+pub fn update_door_vote_ui(
+    mut status: ResMut<DoorVoteStatus>,
+    mut text_q: Query<&mut Text, With<DoorVoteText>>,
+    mut door_q: Query<(&mut Sprite, &ClientDoor)>,
+    player_q: Query<&Health, With<Player>>,
+    time: Res<Time>,
+){
+    status.remaining = (status.remaining - time.delta_seconds()).max(0.);
+
+    if let Ok(mut text) = text_q.get_single_mut(){
+        text.sections[0].value = match status.door{
+            Some(door) => {
+                let living = player_q.iter().filter(|health| health.current > 0.).count();
+                format!("heading {:?} in {:.0}s ({}/{} on door)",
+                    door, status.remaining.ceil(), status.voters.len(), living)
+            }
+            None => String::new(),
+        };
+    }
+
+    for (mut sprite, door) in door_q.iter_mut(){
+        sprite.color = if Some(door.door_type) == status.door {
+            Color::from(GOLD)
+        } else if door.locked {
+            Color::from(MAROON)
+        } else {
+            door.behind.marker()
+        };
+    }
+}
+
+/* f3 flips the room debug text, contents follow whatever the last map packet said */
+pub fn update_room_debug(
+    input: Res<ButtonInput<KeyCode>>,
+    room_manager: Res<ClientRoomManager>,
+    mut text_q: Query<(&mut Text, &mut Visibility), With<RoomDebugText>>,
+){
+    let Ok((mut text, mut visibility)) = text_q.get_single_mut() else {
+        return;
+    };
+    if input.just_pressed(KeyCode::F3){
+        *visibility = match *visibility{
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+    if !room_manager.is_changed() && !input.just_pressed(KeyCode::F3){
+        return;
+    }
+    let mut readout = format!("floor {} ({})\nrolled @ carnage {:.2}", room_manager.floor + 1, room_manager.biome.name(), room_manager.carnage);
+    for (chain, state) in room_manager.states.iter(){
+        readout.push_str(&format!("\n{}: {}", chain.replace('_', " "), state));
+    }
+    text.sections[0].value = readout;
+}
@@ -1,5 +1,7 @@
 use bevy::{prelude::*, time::common_conditions::on_timer};
 use library::*;
+use carnage::CarnageEvent;
+use enemies::BossKillEvent;
 use room_gen::RoomChangeEvent;
 use ui::CarnageChangeEvent;
 use std::{env, time::Duration};
@@ -12,9 +14,16 @@ fn main() {
     App::new()
         /* dont need no screen */
         .add_plugins(MinimalPlugins)
+        /* --max-players N, --carnage-weight W, --carnage-rules file.ron, --room-config file.ron, --seed N, --hallway-width N,
+         * --prefabs file.ron, --room-gen procedural/mixed/prefab */
+        .insert_resource(cuscuta_resources::ServerConfig::from_args())
         /* for room change packet sending */
         .add_event::<RoomChangeEvent>()
         .add_event::<CarnageChangeEvent>()
+        /* gameplay -> carnage rules */
+        .add_event::<CarnageEvent>()
+        /* floor boss down -> stairs */
+        .add_event::<BossKillEvent>()
         /* sets up server/start room */
         .add_systems(
             Startup,
@@ -29,15 +38,28 @@ fn main() {
                 server::listen,
                 server::check_door.after(server::listen),
                 server::room_change_infodump.after(server::check_door),
-                server::carnage_update.after(server::check_door),
+                carnage::apply_carnage_rules.after(server::check_door).after(enemies::enemy_movement),
+                server::carnage_update.after(carnage::apply_carnage_rules),
                 server::send_despawn_command,
                 enemies::enemy_movement,
                 server::send_enemies.after(server::check_door),
                 server::send_player.after(server::listen),
                 server::send_despawn_command.after(server::send_enemies),
                 player::update_server_monkey,
+                floors::open_stairs.after(server::listen).after(enemies::enemy_movement).before(server::room_change_infodump),
+                floors::check_stairs.after(server::check_door).before(server::room_change_infodump),
+                special_rooms::run_challenge.after(server::check_door).after(server::listen).before(server::room_change_infodump),
+                special_rooms::rest_heal.after(server::check_door).before(server::room_change_infodump),
+                teamwork::set_up_puzzle.after(server::check_door).after(floors::check_stairs).before(server::room_change_infodump),
+                teamwork::run_puzzle.after(teamwork::set_up_puzzle).before(server::room_change_infodump),
+                traps::run_traps.after(server::listen).after(server::check_door).before(server::room_change_infodump),
 
             ),
         )
+        /* pick up room_config.ron edits without a restart */
+        .add_systems(
+            Update,
+            room_gen::reload_room_config.run_if(on_timer(Duration::from_secs(room_gen::ROOM_CONFIG_RELOAD_SECS))),
+        )
         .run();
 }